@@ -1,3 +1,5 @@
+pub mod block_index;
 pub mod db;
 pub mod lmdb_utils;
 pub mod progress;
+pub mod schema;
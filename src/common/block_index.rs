@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+use lmdb::{Cursor, Error as LmdbError, Transaction};
+use log::info;
+use thiserror::Error;
+
+use casper_node::types::{BlockHash, BlockHeader};
+use casper_types::EraId;
+
+use super::db::{parse_digest_key, BlockHeaderDatabase, Database, DeserializationError};
+
+const BLOCK_LOG_INTERVAL: usize = 100_000;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+    #[error("Error parsing block header with key {0}: {1}")]
+    Parsing(String, DeserializationError),
+}
+
+/// Block stored in the block header database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexedBlock {
+    pub block_hash: BlockHash,
+    pub era_id: EraId,
+    pub is_switch_block: bool,
+}
+
+/// Blocks of the block header database by height. The block header database
+/// is keyed by block hash, so finding the blocks in a height range requires
+/// reading all the headers.
+#[derive(Debug, Default)]
+pub struct BlockIndex {
+    /// Blocks at each height. Storage may hold several blocks at the same
+    /// height, such as blocks orphaned by an emergency upgrade.
+    by_height: BTreeMap<u64, Vec<IndexedBlock>>,
+}
+
+impl BlockIndex {
+    /// Reads all the block headers in the block header database.
+    pub fn read<T: Transaction>(txn: &T) -> Result<Self, Error> {
+        let block_header_db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name()))? };
+        let mut index = Self::default();
+        let mut cursor = txn.open_ro_cursor(block_header_db)?;
+        for (idx, (raw_key, raw_val)) in cursor.iter().enumerate() {
+            if idx % BLOCK_LOG_INTERVAL == 0 {
+                info!("Indexed {} block headers...", idx);
+            }
+            let block_hash: BlockHash = parse_digest_key(raw_key)
+                .map_err(|parsing_err| Error::Parsing(hex::encode(raw_key), parsing_err))?
+                .into();
+            let header: BlockHeader = bincode::deserialize(raw_val)
+                .map_err(|bincode_err| Error::Parsing(hex::encode(raw_key), bincode_err.into()))?;
+            index
+                .by_height
+                .entry(header.height())
+                .or_default()
+                .push(IndexedBlock {
+                    block_hash,
+                    era_id: header.era_id(),
+                    is_switch_block: header.is_switch_block(),
+                });
+        }
+        Ok(index)
+    }
+
+    /// Returns the height of the highest block, or `None` if there are no
+    /// blocks.
+    pub fn highest_height(&self) -> Option<u64> {
+        self.by_height.keys().next_back().copied()
+    }
+
+    /// Returns the blocks in the height range, from the lowest to the
+    /// highest.
+    pub fn blocks_in(
+        &self,
+        heights: RangeInclusive<u64>,
+    ) -> impl Iterator<Item = (u64, &IndexedBlock)> {
+        self.by_height
+            .range(heights)
+            .flat_map(|(height, blocks)| blocks.iter().map(move |block| (*height, block)))
+    }
+}
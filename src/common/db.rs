@@ -7,6 +7,7 @@ mod deploy_metadata_db;
 mod deploys_db;
 mod finalized_approvals_db;
 mod proposers_db;
+mod sample;
 mod state_store_db;
 #[cfg(test)]
 mod tests;
@@ -22,11 +23,13 @@ pub use deploy_metadata_db::DeployMetadataDatabase;
 pub use deploys_db::DeployDatabase;
 pub use finalized_approvals_db::FinalizedApprovalsDatabase;
 pub use proposers_db::ProposerDatabase;
+pub use sample::{CorruptionEstimate, SampleSize, SampleStats};
 pub use state_store_db::StateStoreDatabase;
 pub use transfer_db::TransferDatabase;
 pub use transfer_hashes_db::TransferHashesDatabase;
 
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter, Result as FormatterResult},
     path::Path,
     result::Result,
@@ -34,15 +37,27 @@ use std::{
 
 use bincode::Error as BincodeError;
 use lmdb::{Cursor, Environment, EnvironmentFlags, Error as LmdbError, RoCursor, Transaction};
+use lmdb_sys::{MDB_FIRST, MDB_SET_RANGE};
 use log::info;
+use rand::RngCore;
 use thiserror::Error;
 
+use casper_hashing::Digest;
 use casper_types::bytesrepr::Error as BytesreprError;
 
+use super::{
+    lmdb_utils,
+    schema::{SchemaSelection, SchemaVersion},
+};
+
 pub const STORAGE_FILE_NAME: &str = "storage.lmdb";
 pub const TRIE_STORE_FILE_NAME: &str = "data.lmdb";
 const ENTRY_LOG_INTERVAL: usize = 100_000;
+const CHECKPOINT_INTERVAL: usize = 10_000;
 const MAX_DB_READERS: u32 = 100;
+/// Size of the memory map of the environments opened with `db_env`, large
+/// enough for the storage of a node to grow into.
+pub const DEFAULT_MAX_DB_SIZE: usize = 483_183_820_800; // 450 gb
 
 #[derive(Debug, Error)]
 pub enum DeserializationError {
@@ -50,6 +65,10 @@ pub enum DeserializationError {
     BincodeError(#[from] BincodeError),
     #[error("failed parsing struct with bytesrepr")]
     BytesreprError(String),
+    #[error("invalid key of {0} bytes, expected {1} bytes")]
+    KeyLength(usize, usize),
+    #[error("key doesn't match the value, which is identified by {0}")]
+    KeyMismatch(String),
 }
 
 impl From<BytesreprError> for DeserializationError {
@@ -58,13 +77,33 @@ impl From<BytesreprError> for DeserializationError {
     }
 }
 
+/// Parses a key which must be a 32 byte hash, such as a `BlockHash`, a
+/// `DeployHash` or a `Digest`.
+pub(crate) fn parse_digest_key(raw_key: &[u8]) -> Result<Digest, DeserializationError> {
+    Digest::try_from(raw_key)
+        .map_err(|_| DeserializationError::KeyLength(raw_key.len(), Digest::LENGTH))
+}
+
+/// Verifies that the raw key of an entry is the hash identifying its value.
+pub(crate) fn check_key_matches(
+    raw_key: &[u8],
+    value_hash: &Digest,
+) -> Result<(), DeserializationError> {
+    if raw_key != value_hash.as_ref() {
+        return Err(DeserializationError::KeyMismatch(hex::encode(value_hash)));
+    }
+    Ok(())
+}
+
 /// Errors encountered when operating on the storage database.
 #[derive(Debug, Error)]
 pub enum Error {
-    /// Errors accumulated when parsing a database with "--no-failfast".
-    Accumulated(Vec<Self>),
-    /// Parsing error on entry at index in the database.
-    Parsing(usize, DeserializationError),
+    /// Errors accumulated when parsing a database with "--no-failfast",
+    /// along with the number of entries parsed.
+    Accumulated(usize, Vec<Self>),
+    /// Parsing error on entry at index in the database, along with the raw
+    /// key of the entry.
+    Parsing(usize, Vec<u8>, DeserializationError),
     /// Database operation error.
     Database(#[from] LmdbError),
 }
@@ -73,8 +112,12 @@ impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> FormatterResult {
         match self {
             Self::Database(e) => write!(f, "Error operating the database: {e}"),
-            Self::Parsing(idx, inner) => write!(f, "Error parsing element {idx}: {inner}"),
-            Self::Accumulated(accumulated_errors) => {
+            Self::Parsing(idx, raw_key, inner) => write!(
+                f,
+                "Error parsing element {idx} with key {}: {inner}",
+                hex::encode(raw_key)
+            ),
+            Self::Accumulated(_entries_parsed, accumulated_errors) => {
                 writeln!(f, "Errors caught:")?;
                 for error in accumulated_errors {
                     writeln!(f, "{error}")?;
@@ -85,7 +128,94 @@ impl Display for Error {
     }
 }
 
+/// Range of raw keys `[start, end)` in a database. Missing bounds leave the
+/// range open on that side.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Option<Vec<u8>>,
+    pub end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    /// Splits the key space into at most `count` contiguous ranges, delimited
+    /// by the first byte of the keys. Keys in the storage databases are mostly
+    /// hashes, so the ranges hold roughly equal amounts of entries.
+    pub fn shards(count: usize) -> Vec<Self> {
+        let count = count.clamp(1, 256);
+        let mut bounds: Vec<Option<Vec<u8>>> = (1..count)
+            .map(|shard| Some(vec![(shard * 256 / count) as u8]))
+            .collect();
+        bounds.insert(0, None);
+        bounds.push(None);
+        bounds
+            .windows(2)
+            .map(|window| Self {
+                start: window[0].clone(),
+                end: window[1].clone(),
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the range is the whole key space.
+    pub fn is_full(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// Returns the range holding the keys in this range which are strictly
+    /// greater than `key`.
+    pub fn after(&self, key: &[u8]) -> Self {
+        // Appending a zero byte gives the smallest key greater than `key`.
+        let mut start = key.to_vec();
+        start.push(0);
+        Self {
+            start: Some(start),
+            end: self.end.clone(),
+        }
+    }
+
+    /// Returns `true` if the key is lower than the end of the range.
+    pub fn is_before_end(&self, key: &[u8]) -> bool {
+        self.end.as_ref().map_or(true, |end| key < end.as_slice())
+    }
+}
+
+impl Display for KeyRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatterResult {
+        let start = self.start.as_ref().map(hex::encode).unwrap_or_default();
+        let end = self.end.as_ref().map(hex::encode).unwrap_or_default();
+        write!(f, "[{start}..{end})")
+    }
+}
+
+/// Decoder of the entries of a database for one layout of its values.
+#[derive(Clone, Copy)]
+pub struct Decoder {
+    /// Name of the layout, used when reporting which layout matched.
+    pub layout: &'static str,
+    /// Oldest schema version which stores values in this layout.
+    pub since: SchemaVersion,
+    /// Parses the raw key and the value of an entry.
+    pub parse: fn(&[u8], &[u8]) -> Result<(), DeserializationError>,
+}
+
 pub fn db_env<P: AsRef<Path>>(path: P) -> Result<Environment, LmdbError> {
+    db_env_with_map_size(path, DEFAULT_MAX_DB_SIZE)
+}
+
+/// Opens the environment of the file at `path` like `db_env`, unless the
+/// file is missing, as LMDB would create an empty one.
+pub fn existing_db_env<P: AsRef<Path>>(path: P) -> Result<Option<Environment>, LmdbError> {
+    if !path.as_ref().exists() {
+        return Ok(None);
+    }
+    db_env(path).map(Some)
+}
+
+/// Opens an environment like `db_env`, with a map of `map_size` bytes.
+pub fn db_env_with_map_size<P: AsRef<Path>>(
+    path: P,
+    map_size: usize,
+) -> Result<Environment, LmdbError> {
     let env = Environment::new()
         .set_flags(
             EnvironmentFlags::NO_SUB_DIR
@@ -93,6 +223,7 @@ pub fn db_env<P: AsRef<Path>>(path: P) -> Result<Environment, LmdbError> {
                 | EnvironmentFlags::NO_READAHEAD,
         )
         .set_max_dbs(MAX_DB_READERS)
+        .set_map_size(map_size)
         .open(path.as_ref())?;
     Ok(env)
 }
@@ -103,42 +234,213 @@ pub trait Database {
     /// Parses a value of an entry in a database.
     fn parse_element(bytes: &[u8]) -> Result<(), DeserializationError>;
 
-    /// Parses all elements of a database by trying to deserialize them sequentially.
-    fn parse_elements(mut cursor: RoCursor, failfast: bool, start_at: usize) -> Result<(), Error> {
+    /// Parses the raw key of an entry in a database. Accepts any key by
+    /// default.
+    fn parse_key(_raw_key: &[u8]) -> Result<(), DeserializationError> {
+        Ok(())
+    }
+
+    /// Parses both the key and the value of an entry in a database. Databases
+    /// whose values are identified by a hash override this to also verify
+    /// that the entry is stored under that hash.
+    fn parse_entry(raw_key: &[u8], bytes: &[u8]) -> Result<(), DeserializationError> {
+        Self::parse_key(raw_key)?;
+        Self::parse_element(bytes)
+    }
+
+    /// Decoders for the layouts of the entries of this database, oldest
+    /// first. Databases whose values changed layout across schema versions
+    /// register a decoder for each layout. Defaults to `parse_entry` for all
+    /// schema versions, as the values of most databases kept their layout
+    /// across the 1.x versions known to `casper-node`.
+    fn decoders() -> Vec<Decoder> {
+        vec![Decoder {
+            layout: "1.x",
+            since: SchemaVersion::V1_0,
+            parse: Self::parse_entry,
+        }]
+    }
+
+    /// Parses an entry with the decoders selected by `schema` and returns the
+    /// name of the layout which matched.
+    ///
+    /// A specific schema version selects the newest decoder introduced up to
+    /// that version, while `SchemaSelection::Auto` tries every decoder, newest
+    /// first, and fails with the error of the newest one.
+    fn decode_entry(
+        schema: SchemaSelection,
+        raw_key: &[u8],
+        bytes: &[u8],
+    ) -> Result<&'static str, DeserializationError> {
+        let decoders = Self::decoders();
+        let mut candidates = decoders.iter().rev().filter(|decoder| match schema {
+            SchemaSelection::Auto => true,
+            SchemaSelection::Version(version) => decoder.since <= version,
+        });
+        let newest = match candidates.next() {
+            Some(decoder) => decoder,
+            // Schemas older than all the layouts use the oldest one.
+            None => decoders.first().expect("should have at least one decoder"),
+        };
+        let newest_err = match (newest.parse)(raw_key, bytes) {
+            Ok(()) => return Ok(newest.layout),
+            Err(parsing_err) => parsing_err,
+        };
+        if schema == SchemaSelection::Auto {
+            for decoder in candidates {
+                if (decoder.parse)(raw_key, bytes).is_ok() {
+                    return Ok(decoder.layout);
+                }
+            }
+        }
+        Err(newest_err)
+    }
+
+    /// Parses all entries of a database in the given key range by trying to
+    /// deserialize them sequentially. Returns the number of entries parsed.
+    ///
+    /// Entry indices, both for `start_at` and in the reported errors, are
+    /// relative to the start of the key range. `on_checkpoint` is called
+    /// periodically with the key of the last entry checked, as well as after
+    /// the last entry in the range. Values are decoded with the layouts
+    /// selected by `schema`.
+    fn parse_elements(
+        mut cursor: RoCursor,
+        failfast: bool,
+        start_at: usize,
+        range: &KeyRange,
+        schema: SchemaSelection,
+        on_checkpoint: &mut dyn FnMut(&[u8]),
+    ) -> Result<usize, Error> {
         if start_at > 0 {
             info!("Skipping {} entries.", start_at);
         }
+        let entries = match range.start.as_ref() {
+            Some(start_key) => match lmdb_utils::iter_from_key(&mut cursor, start_key)? {
+                Some(entries) => entries,
+                None => {
+                    info!("No entries in {} for range {}.", Self::db_name(), range);
+                    return Ok(0);
+                }
+            },
+            None => cursor.iter(),
+        };
         let mut error_buffer = vec![];
-        for (idx, (_raw_key, raw_val)) in cursor.iter().skip(start_at).enumerate() {
-            if let Err(e) =
-                Self::parse_element(raw_val).map_err(|parsing_err| Error::Parsing(idx, parsing_err))
-            {
-                if failfast {
-                    return Err(e);
-                } else {
-                    error_buffer.push(e);
+        let mut entries_parsed = 0;
+        let mut last_key: Option<&[u8]> = None;
+        let mut layouts_matched: BTreeMap<&'static str, usize> = BTreeMap::new();
+        for (idx, (raw_key, raw_val)) in entries
+            .take_while(|(raw_key, _)| range.is_before_end(raw_key))
+            .enumerate()
+            .skip(start_at)
+        {
+            entries_parsed += 1;
+            match Self::decode_entry(schema, raw_key, raw_val) {
+                Ok(layout) => *layouts_matched.entry(layout).or_default() += 1,
+                Err(parsing_err) => {
+                    let e = Error::Parsing(idx, raw_key.to_vec(), parsing_err);
+                    if failfast {
+                        return Err(e);
+                    } else {
+                        error_buffer.push(e);
+                    }
                 }
             }
             if idx % ENTRY_LOG_INTERVAL == 0 {
-                info!("Parsed {} entries...", idx);
+                info!("Parsed {} entries in {}...", idx, Self::db_name());
+            }
+            if entries_parsed % CHECKPOINT_INTERVAL == 0 {
+                on_checkpoint(raw_key);
             }
+            last_key = Some(raw_key);
+        }
+        if let Some(raw_key) = last_key {
+            on_checkpoint(raw_key);
+        }
+        info!("Parsing {} complete.", Self::db_name());
+        if layouts_matched.len() > 1 || schema == SchemaSelection::Auto {
+            let layouts: Vec<String> = layouts_matched
+                .iter()
+                .map(|(layout, count)| format!("{layout} ({count})"))
+                .collect();
+            info!(
+                "Layouts matched in {}: {}.",
+                Self::db_name(),
+                layouts.join(", ")
+            );
         }
-        info!("Parsing complete.");
         if !failfast && !error_buffer.is_empty() {
-            return Err(Error::Accumulated(error_buffer));
+            return Err(Error::Accumulated(entries_parsed, error_buffer));
         }
-        Ok(())
+        Ok(entries_parsed)
     }
 
-    /// Validates the database by ensuring every value of an entry can be parsed.
-    fn check_db(env: &Environment, failfast: bool, start_at: usize) -> Result<(), Error> {
-        info!("Checking {} database.", Self::db_name());
+    /// Validates the database by ensuring every entry in the key range can be
+    /// parsed. Returns the number of entries parsed.
+    fn check_db(
+        env: &Environment,
+        failfast: bool,
+        start_at: usize,
+        range: &KeyRange,
+        schema: SchemaSelection,
+        on_checkpoint: &mut dyn FnMut(&[u8]),
+    ) -> Result<usize, Error> {
+        if range.is_full() {
+            info!("Checking {} database.", Self::db_name());
+        } else {
+            info!("Checking {} database in range {}.", Self::db_name(), range);
+        }
         let txn = env.begin_ro_txn()?;
         let db = unsafe { txn.open_db(Some(Self::db_name()))? };
 
         if let Ok(cursor) = txn.open_ro_cursor(db) {
-            Self::parse_elements(cursor, failfast, start_at)?;
+            return Self::parse_elements(cursor, failfast, start_at, range, schema, on_checkpoint);
         }
-        Ok(())
+        Ok(0)
+    }
+
+    /// Parses a random sample of the entries of the database. Each entry is
+    /// found by positioning a cursor on the first key greater than or equal
+    /// to a random 32 byte key, wrapping around to the first entry past the
+    /// last key.
+    ///
+    /// Entries are sampled uniformly only when keys are hashes, and entries
+    /// may be sampled more than once.
+    fn sample_db(
+        env: &Environment,
+        sample_size: SampleSize,
+        schema: SchemaSelection,
+    ) -> Result<SampleStats, Error> {
+        let txn = env.begin_ro_txn()?;
+        let db = unsafe { txn.open_db(Some(Self::db_name()))? };
+        let entry_count = lmdb_utils::entry_count(&txn, db)?;
+        let entries_to_sample = sample_size.entries_to_sample(entry_count);
+        info!(
+            "Sampling {} of {} entries in {} database.",
+            entries_to_sample,
+            entry_count,
+            Self::db_name()
+        );
+
+        let cursor = txn.open_ro_cursor(db)?;
+        let mut rng = rand::thread_rng();
+        let mut random_key = [0u8; 32];
+        let mut errors = vec![];
+        for idx in 0..entries_to_sample {
+            rng.fill_bytes(&mut random_key);
+            let (maybe_key, raw_val) = match cursor.get(Some(&random_key), None, MDB_SET_RANGE) {
+                Err(LmdbError::NotFound) => cursor.get(None, None, MDB_FIRST)?,
+                result => result?,
+            };
+            let raw_key = maybe_key.unwrap_or_default();
+            if let Err(parsing_err) = Self::decode_entry(schema, raw_key, raw_val) {
+                errors.push(Error::Parsing(idx, raw_key.to_vec(), parsing_err));
+            }
+        }
+        Ok(SampleStats {
+            entry_count,
+            entries_sampled: entries_to_sample,
+            errors,
+        })
     }
 }
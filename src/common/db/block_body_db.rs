@@ -5,7 +5,9 @@ use std::{
 
 use casper_node::types::BlockBody;
 
-use super::{Database, DeserializationError};
+use crate::subcommands::execution_results_summary::block_body::BlockBody as BlockBodyParts;
+
+use super::{check_key_matches, parse_digest_key, Database, DeserializationError};
 
 pub struct BlockBodyDatabase;
 
@@ -24,4 +26,16 @@ impl Database for BlockBodyDatabase {
         let _: BlockBody = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
+
+    fn parse_entry(raw_key: &[u8], bytes: &[u8]) -> Result<(), DeserializationError> {
+        Self::parse_key(raw_key)?;
+        let body: BlockBodyParts = bincode::deserialize(bytes)?;
+        // Bodies identified by their Merkle root, from protocol version 1.4
+        // on, are stored as parts in the `block_body_merkle` database instead.
+        check_key_matches(raw_key, &body.hash_v1()?)
+    }
 }
@@ -6,7 +6,7 @@ use std::{
 use casper_hashing::Digest;
 use casper_types::bytesrepr::FromBytes;
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct BlockBodyMerkleDatabase;
 
@@ -25,4 +25,8 @@ impl Database for BlockBodyMerkleDatabase {
         let _: (Digest, Digest) = FromBytes::from_bytes(bytes)?.0;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
@@ -1,11 +1,23 @@
 use std::{
+    collections::BTreeMap,
     fmt::{Display, Formatter, Result as FormatterResult},
     result::Result,
 };
 
-use casper_node::types::BlockSignatures;
+use casper_node::types::BlockHash;
+use casper_types::{EraId, PublicKey, Signature};
+use serde::{Deserialize, Serialize};
 
-use super::{Database, DeserializationError};
+use super::{check_key_matches, parse_digest_key, Database, DeserializationError};
+
+/// Mirror of the `BlockSignatures` of `casper-node`, whose fields aren't
+/// exported outside of the crate.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BlockSignatures {
+    pub(crate) block_hash: BlockHash,
+    pub(crate) era_id: EraId,
+    pub(crate) proofs: BTreeMap<PublicKey, Signature>,
+}
 
 pub struct BlockMetadataDatabase;
 
@@ -24,4 +36,14 @@ impl Database for BlockMetadataDatabase {
         let _: BlockSignatures = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
+
+    fn parse_entry(raw_key: &[u8], bytes: &[u8]) -> Result<(), DeserializationError> {
+        Self::parse_key(raw_key)?;
+        let signatures: BlockSignatures = bincode::deserialize(bytes)?;
+        check_key_matches(raw_key, signatures.block_hash.inner())
+    }
 }
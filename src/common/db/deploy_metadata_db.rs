@@ -4,7 +4,7 @@ use std::{
     result::Result,
 };
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct DeployMetadataDatabase;
 
@@ -23,4 +23,8 @@ impl Database for DeployMetadataDatabase {
         let _: DeployMetadata = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
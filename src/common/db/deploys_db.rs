@@ -5,7 +5,7 @@ use std::{
 
 use casper_node::types::Deploy;
 
-use super::{Database, DeserializationError};
+use super::{check_key_matches, parse_digest_key, Database, DeserializationError};
 
 pub struct DeployDatabase;
 
@@ -24,4 +24,14 @@ impl Database for DeployDatabase {
         let _: Deploy = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
+
+    fn parse_entry(raw_key: &[u8], bytes: &[u8]) -> Result<(), DeserializationError> {
+        Self::parse_key(raw_key)?;
+        let deploy: Deploy = bincode::deserialize(bytes)?;
+        check_key_matches(raw_key, deploy.id().inner())
+    }
 }
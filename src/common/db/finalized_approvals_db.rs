@@ -5,7 +5,7 @@ use std::{
 
 use casper_node::types::FinalizedApprovals;
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct FinalizedApprovalsDatabase;
 
@@ -24,4 +24,8 @@ impl Database for FinalizedApprovalsDatabase {
         let _: FinalizedApprovals = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
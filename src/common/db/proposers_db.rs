@@ -5,7 +5,7 @@ use std::{
 
 use casper_types::{bytesrepr::FromBytes, PublicKey};
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct ProposerDatabase;
 
@@ -24,4 +24,8 @@ impl Database for ProposerDatabase {
         let _: PublicKey = FromBytes::from_bytes(bytes)?.0;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
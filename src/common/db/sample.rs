@@ -0,0 +1,97 @@
+use std::{result::Result, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::Error;
+
+/// Z-score of the 95% confidence level.
+const Z_95: f64 = 1.96;
+
+/// Number of entries to sample from a database.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleSize {
+    /// Fraction of the entries in the database, in `(0, 1]`.
+    Fraction(f64),
+    /// Fixed number of entries.
+    Count(usize),
+}
+
+impl SampleSize {
+    /// Returns the number of entries to sample from a database holding
+    /// `entry_count` entries. Sampling more entries than the database holds
+    /// is pointless, so the count is capped.
+    pub fn entries_to_sample(&self, entry_count: usize) -> usize {
+        let count = match self {
+            SampleSize::Fraction(fraction) => (fraction * entry_count as f64).ceil() as usize,
+            SampleSize::Count(count) => *count,
+        };
+        count.min(entry_count)
+    }
+}
+
+impl FromStr for SampleSize {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Ok(count) = input.parse::<usize>() {
+            if count == 0 {
+                return Err("sample count must be positive".to_string());
+            }
+            return Ok(SampleSize::Count(count));
+        }
+        match input.parse::<f64>() {
+            Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(SampleSize::Fraction(fraction)),
+            _ => Err(format!(
+                "{input} is neither an entry count nor a fraction in (0, 1]"
+            )),
+        }
+    }
+}
+
+/// Estimated rate of corrupted entries in a database, with its 95% Wilson
+/// score confidence interval.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CorruptionEstimate {
+    pub rate: f64,
+    pub lower_bound: f64,
+    pub upper_bound: f64,
+}
+
+impl CorruptionEstimate {
+    /// Estimates the corruption rate from `failures` out of `sampled`
+    /// entries. Returns `None` if nothing was sampled.
+    pub fn new(failures: usize, sampled: usize) -> Option<Self> {
+        if sampled == 0 {
+            return None;
+        }
+        let n = sampled as f64;
+        let rate = failures as f64 / n;
+        let z_squared = Z_95 * Z_95;
+        let denominator = 1.0 + z_squared / n;
+        let center = (rate + z_squared / (2.0 * n)) / denominator;
+        let half_width =
+            Z_95 * (rate * (1.0 - rate) / n + z_squared / (4.0 * n * n)).sqrt() / denominator;
+        Some(Self {
+            rate,
+            lower_bound: (center - half_width).max(0.0),
+            upper_bound: (center + half_width).min(1.0),
+        })
+    }
+}
+
+/// Outcome of sampling a database.
+#[derive(Debug)]
+pub struct SampleStats {
+    /// Number of entries in the database.
+    pub entry_count: usize,
+    /// Number of entries sampled, possibly including duplicates.
+    pub entries_sampled: usize,
+    /// Parsing errors of the sampled entries, indexed by sample number.
+    pub errors: Vec<Error>,
+}
+
+impl SampleStats {
+    pub fn estimate(&self) -> Option<CorruptionEstimate> {
+        CorruptionEstimate::new(self.errors.len(), self.entries_sampled)
+    }
+}
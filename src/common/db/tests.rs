@@ -1,9 +1,26 @@
+use std::collections::BTreeMap;
+
 use lmdb::{Database as LmdbDatabase, Environment, Transaction, WriteFlags};
 use rand::{self, prelude::ThreadRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
 
-use super::{Database, DeserializationError};
-use crate::test_utils::LmdbTestFixture;
+use casper_hashing::Digest;
+use casper_node::types::BlockHash;
+use casper_types::EraId;
+
+use super::{
+    block_metadata_db::BlockSignatures, existing_db_env, BlockBodyDatabase, BlockMetadataDatabase,
+    CorruptionEstimate, Database, Decoder, DeserializationError, Error, KeyRange, SampleSize,
+    StateStoreDatabase, STORAGE_FILE_NAME,
+};
+use crate::{
+    common::{
+        lmdb_utils,
+        schema::{SchemaSelection, SchemaVersion},
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+    test_utils::{mock_deploy_hash, LmdbTestFixture},
+};
 
 fn gen_bytes(rng: &mut ThreadRng) -> Vec<u8> {
     let mock = MockStruct::random(rng);
@@ -28,8 +45,13 @@ fn populate_db(env: &Environment, db: &LmdbDatabase) {
 }
 
 fn populate_faulty_db(env: &Environment, db: &LmdbDatabase) {
+    let entry_count = rand::thread_rng().gen_range(10u32..100u32);
+    populate_faulty_db_with_count(env, db, entry_count);
+}
+
+// Every 5th entry, starting with the first one, is faulty.
+fn populate_faulty_db_with_count(env: &Environment, db: &LmdbDatabase, entry_count: u32) {
     let mut rng = rand::thread_rng();
-    let entry_count = rng.gen_range(10u32..100u32);
     let mut rw_tx = env.begin_rw_txn().expect("couldn't begin rw transaction");
     for i in 0..entry_count {
         let bytes = if i % 5 == 0 {
@@ -124,6 +146,46 @@ impl Database for MockDb {
     }
 }
 
+// Database whose values gained a version tag in schema 1.5.
+struct MultiLayoutMockDb {}
+
+impl MultiLayoutMockDb {
+    fn parse_versioned(_raw_key: &[u8], bytes: &[u8]) -> Result<(), DeserializationError> {
+        match bytes.split_first() {
+            Some((1, rest)) => Self::parse_element(rest),
+            _ => Err(DeserializationError::BytesreprError(
+                "unknown version".to_string(),
+            )),
+        }
+    }
+}
+
+impl Database for MultiLayoutMockDb {
+    fn db_name() -> &'static str {
+        "test_multi_layout_db"
+    }
+
+    fn parse_element(bytes: &[u8]) -> Result<(), DeserializationError> {
+        bincode::deserialize::<MockStruct>(bytes)?;
+        Ok(())
+    }
+
+    fn decoders() -> Vec<Decoder> {
+        vec![
+            Decoder {
+                layout: "legacy",
+                since: SchemaVersion::V1_0,
+                parse: Self::parse_entry,
+            },
+            Decoder {
+                layout: "versioned",
+                since: SchemaVersion::V1_5,
+                parse: Self::parse_versioned,
+            },
+        ]
+    }
+}
+
 #[test]
 fn sanity_check_ser_deser() {
     let mut rng = rand::thread_rng();
@@ -144,10 +206,42 @@ fn good_db_should_pass_check() {
     let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
     populate_db(&fixture.env, fixture.db(Some(MockDb::db_name())).unwrap());
 
-    assert!(MockDb::check_db(&fixture.env, true, 0).is_ok());
-    assert!(MockDb::check_db(&fixture.env, false, 0).is_ok());
-    assert!(MockDb::check_db(&fixture.env, true, 4).is_ok());
-    assert!(MockDb::check_db(&fixture.env, false, 4).is_ok());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        true,
+        0,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_ok());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        false,
+        0,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_ok());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        true,
+        4,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_ok());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        false,
+        4,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_ok());
 }
 
 #[test]
@@ -155,8 +249,389 @@ fn bad_db_should_fail_check() {
     let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
     populate_faulty_db(&fixture.env, fixture.db(Some(MockDb::db_name())).unwrap());
 
-    assert!(MockDb::check_db(&fixture.env, true, 0).is_err());
-    assert!(MockDb::check_db(&fixture.env, false, 0).is_err());
-    assert!(MockDb::check_db(&fixture.env, true, 4).is_err());
-    assert!(MockDb::check_db(&fixture.env, false, 4).is_err());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        true,
+        0,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_err());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        false,
+        0,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_err());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        true,
+        4,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_err());
+    assert!(MockDb::check_db(
+        &fixture.env,
+        false,
+        4,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {}
+    )
+    .is_err());
+}
+
+#[test]
+fn bad_db_errors_should_hold_index_and_key() {
+    let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
+    let db = fixture.db(Some(MockDb::db_name())).unwrap();
+    populate_faulty_db(&fixture.env, db);
+    let entry_count = {
+        let txn = fixture.env.begin_ro_txn().unwrap();
+        lmdb_utils::entry_count(&txn, *db).unwrap()
+    };
+
+    match MockDb::check_db(
+        &fixture.env,
+        true,
+        0,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {},
+    ) {
+        Err(Error::Parsing(idx, raw_key, _)) => {
+            assert_eq!(idx, 0);
+            assert_eq!(raw_key, 0u32.to_le_bytes());
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+
+    match MockDb::check_db(
+        &fixture.env,
+        false,
+        4,
+        &KeyRange::default(),
+        SchemaSelection::Auto,
+        &mut |_| {},
+    ) {
+        Err(Error::Accumulated(entries_parsed, errors)) => {
+            assert_eq!(entries_parsed, entry_count - 4);
+            assert!(!errors.is_empty());
+            for error in errors {
+                match error {
+                    Error::Parsing(idx, raw_key, _) => {
+                        // Every 5th entry is faulty and keys are stored in
+                        // ascending order.
+                        assert_eq!(idx % 5, 0);
+                        assert_eq!(raw_key, (idx as u32).to_le_bytes());
+                    }
+                    other => panic!("unexpected error {other:?}"),
+                }
+            }
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn key_range_shards() {
+    assert_eq!(KeyRange::shards(1), vec![KeyRange::default()]);
+    assert_eq!(KeyRange::shards(0), vec![KeyRange::default()]);
+
+    let shards = KeyRange::shards(4);
+    assert_eq!(shards.len(), 4);
+    assert_eq!(shards[0].start, None);
+    assert_eq!(shards[0].end, Some(vec![64u8]));
+    assert_eq!(shards[1].start, Some(vec![64u8]));
+    assert_eq!(shards[2].end, Some(vec![192u8]));
+    assert_eq!(shards[3].end, None);
+
+    assert_eq!(KeyRange::shards(1000).len(), 256);
+}
+
+#[test]
+fn ranged_check_should_only_parse_range() {
+    let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
+    populate_faulty_db_with_count(
+        &fixture.env,
+        fixture.db(Some(MockDb::db_name())).unwrap(),
+        30,
+    );
+
+    // Keys are little endian encoded indices, so their first byte is the
+    // index of the entry.
+    let range = KeyRange {
+        start: Some(vec![10u8]),
+        end: Some(vec![20u8]),
+    };
+    match MockDb::check_db(
+        &fixture.env,
+        false,
+        0,
+        &range,
+        SchemaSelection::Auto,
+        &mut |_| {},
+    ) {
+        Err(Error::Accumulated(entries_parsed, errors)) => {
+            assert_eq!(entries_parsed, 10);
+            // Entries 10 and 15 are faulty, indexed relative to the range.
+            let indices: Vec<usize> = errors
+                .iter()
+                .map(|error| match error {
+                    Error::Parsing(idx, _, _) => *idx,
+                    other => panic!("unexpected error {other:?}"),
+                })
+                .collect();
+            assert_eq!(indices, vec![0, 5]);
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+
+    let range = KeyRange {
+        start: Some(vec![11u8]),
+        end: Some(vec![15u8]),
+    };
+    assert_eq!(
+        MockDb::check_db(
+            &fixture.env,
+            true,
+            0,
+            &range,
+            SchemaSelection::Auto,
+            &mut |_| {}
+        )
+        .unwrap(),
+        4
+    );
+
+    let range = KeyRange {
+        start: Some(vec![200u8]),
+        end: None,
+    };
+    assert_eq!(
+        MockDb::check_db(
+            &fixture.env,
+            true,
+            0,
+            &range,
+            SchemaSelection::Auto,
+            &mut |_| {}
+        )
+        .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn checkpoint_key_should_resume_after_last_entry() {
+    let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
+    let db = fixture.db(Some(MockDb::db_name())).unwrap();
+    let mut rng = rand::thread_rng();
+    {
+        let mut rw_tx = fixture.env.begin_rw_txn().unwrap();
+        for i in 0..20u8 {
+            rw_tx
+                .put(*db, &[i, 0], &gen_bytes(&mut rng), WriteFlags::empty())
+                .unwrap();
+        }
+        rw_tx.commit().unwrap();
+    }
+
+    let range = KeyRange {
+        start: None,
+        end: Some(vec![12u8]),
+    };
+    let mut checkpoints = vec![];
+    assert_eq!(
+        MockDb::check_db(
+            &fixture.env,
+            true,
+            0,
+            &range,
+            SchemaSelection::Auto,
+            &mut |raw_key| checkpoints.push(raw_key.to_vec())
+        )
+        .unwrap(),
+        12
+    );
+    // The last checkpoint is the last key in the range.
+    assert_eq!(checkpoints.last().unwrap(), &vec![11u8, 0]);
+
+    let resumed_range = KeyRange::default().after(checkpoints.last().unwrap());
+    assert_eq!(resumed_range.start, Some(vec![11u8, 0, 0]));
+    assert_eq!(
+        MockDb::check_db(
+            &fixture.env,
+            true,
+            0,
+            &resumed_range,
+            SchemaSelection::Auto,
+            &mut |_| {}
+        )
+        .unwrap(),
+        8
+    );
+}
+
+#[test]
+fn entries_should_be_keyed_by_hash_of_value() {
+    let block_hash = BlockHash::new(Digest::hash([1u8; 32]));
+    let signatures = bincode::serialize(&BlockSignatures {
+        block_hash,
+        era_id: EraId::new(1),
+        proofs: BTreeMap::new(),
+    })
+    .unwrap();
+
+    assert!(BlockMetadataDatabase::parse_entry(block_hash.as_ref(), &signatures).is_ok());
+    assert!(matches!(
+        BlockMetadataDatabase::parse_entry(&[2u8; 32], &signatures),
+        Err(DeserializationError::KeyMismatch(_))
+    ));
+    assert!(matches!(
+        BlockMetadataDatabase::parse_entry(&[2u8; 31], &signatures),
+        Err(DeserializationError::KeyLength(31, 32))
+    ));
+
+    // Keys of the state store are not hashes.
+    assert!(StateStoreDatabase::parse_key(b"state_key").is_ok());
+}
+
+#[test]
+fn schema_selection_should_pick_decoders() {
+    let legacy_bytes = bincode::serialize(&MockStruct {
+        a: 0x0200,
+        b: "hello".to_string(),
+        c: None,
+    })
+    .unwrap();
+    let mut versioned_bytes = vec![1u8];
+    versioned_bytes.extend_from_slice(&legacy_bytes);
+    let decode = |schema, bytes: &[u8]| MultiLayoutMockDb::decode_entry(schema, &[], bytes).ok();
+
+    for version in [SchemaVersion::V1_0, SchemaVersion::V1_4] {
+        let schema = SchemaSelection::Version(version);
+        assert_eq!(decode(schema, &legacy_bytes), Some("legacy"));
+        assert_eq!(decode(schema, &versioned_bytes), None);
+    }
+
+    let schema = SchemaSelection::Version(SchemaVersion::V1_5);
+    assert_eq!(decode(schema, &legacy_bytes), None);
+    assert_eq!(decode(schema, &versioned_bytes), Some("versioned"));
+
+    assert_eq!(decode(SchemaSelection::Auto, &legacy_bytes), Some("legacy"));
+    assert_eq!(
+        decode(SchemaSelection::Auto, &versioned_bytes),
+        Some("versioned")
+    );
+    assert!(matches!(
+        MultiLayoutMockDb::decode_entry(SchemaSelection::Auto, &[], &[2u8, 3]),
+        Err(DeserializationError::BytesreprError(_))
+    ));
+
+    assert_eq!("auto".parse::<SchemaSelection>(), Ok(SchemaSelection::Auto));
+    assert_eq!(
+        "1.4".parse::<SchemaSelection>(),
+        Ok(SchemaSelection::Version(SchemaVersion::V1_4))
+    );
+    assert!("2.0".parse::<SchemaSelection>().is_err());
+}
+
+#[test]
+fn block_bodies_should_be_keyed_by_hash_of_whole_body() {
+    let body = BlockBody::new(vec![mock_deploy_hash(1), mock_deploy_hash(2)]);
+    let raw_body = bincode::serialize(&body).unwrap();
+    let body_hash = body.hash_v1().unwrap();
+    let merkle_root = body.merkle_root().unwrap();
+
+    for schema in [
+        SchemaSelection::Version(SchemaVersion::V1_0),
+        SchemaSelection::Version(SchemaVersion::V1_4),
+        SchemaSelection::Auto,
+    ] {
+        assert_eq!(
+            BlockBodyDatabase::decode_entry(schema, body_hash.as_ref(), &raw_body).unwrap(),
+            "1.x"
+        );
+        // Bodies keyed by their Merkle root are only stored as parts.
+        assert!(matches!(
+            BlockBodyDatabase::decode_entry(schema, merkle_root.as_ref(), &raw_body),
+            Err(DeserializationError::KeyMismatch(_))
+        ));
+    }
+}
+
+#[test]
+fn sample_size_parsing() {
+    assert_eq!("250".parse::<SampleSize>(), Ok(SampleSize::Count(250)));
+    assert_eq!("0.01".parse::<SampleSize>(), Ok(SampleSize::Fraction(0.01)));
+    assert_eq!("1.0".parse::<SampleSize>(), Ok(SampleSize::Fraction(1.0)));
+    assert!("0".parse::<SampleSize>().is_err());
+    assert!("1.5".parse::<SampleSize>().is_err());
+    assert!("-0.5".parse::<SampleSize>().is_err());
+    assert!("all".parse::<SampleSize>().is_err());
+
+    assert_eq!(SampleSize::Fraction(0.1).entries_to_sample(1001), 101);
+    assert_eq!(SampleSize::Count(500).entries_to_sample(100), 100);
+}
+
+#[test]
+fn corruption_estimate_confidence_interval() {
+    assert!(CorruptionEstimate::new(0, 0).is_none());
+
+    let estimate = CorruptionEstimate::new(0, 100).unwrap();
+    assert_eq!(estimate.rate, 0.0);
+    assert_eq!(estimate.lower_bound, 0.0);
+    assert!(estimate.upper_bound > 0.036 && estimate.upper_bound < 0.038);
+
+    let estimate = CorruptionEstimate::new(10, 100).unwrap();
+    assert_eq!(estimate.rate, 0.1);
+    assert!(estimate.lower_bound > 0.05 && estimate.lower_bound < 0.1);
+    assert!(estimate.upper_bound > 0.1 && estimate.upper_bound < 0.18);
+}
+
+#[test]
+fn sampled_errors_should_be_faulty_entries() {
+    let fixture = LmdbTestFixture::new(vec![MockDb::db_name()], None);
+    populate_faulty_db_with_count(
+        &fixture.env,
+        fixture.db(Some(MockDb::db_name())).unwrap(),
+        30,
+    );
+
+    let stats =
+        MockDb::sample_db(&fixture.env, SampleSize::Count(1000), SchemaSelection::Auto).unwrap();
+    assert_eq!(stats.entry_count, 30);
+    assert_eq!(stats.entries_sampled, 30);
+    for error in &stats.errors {
+        match error {
+            Error::Parsing(_, raw_key, _) => {
+                let key_idx = u32::from_le_bytes(raw_key.as_slice().try_into().unwrap());
+                assert_eq!(key_idx % 5, 0);
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+    assert!(stats.estimate().is_some());
+}
+
+#[test]
+fn existing_db_env_should_not_create_missing_file() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join(STORAGE_FILE_NAME);
+    assert!(existing_db_env(&path).unwrap().is_none());
+    assert!(!path.exists());
+
+    let fixture = LmdbTestFixture::new(vec![], Some(STORAGE_FILE_NAME));
+    assert!(
+        existing_db_env(fixture.tmp_dir.path().join(STORAGE_FILE_NAME))
+            .unwrap()
+            .is_some()
+    );
 }
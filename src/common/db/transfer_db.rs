@@ -5,7 +5,7 @@ use std::{
 
 use casper_types::Transfer;
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct TransferDatabase;
 
@@ -24,4 +24,8 @@ impl Database for TransferDatabase {
         let _: Vec<Transfer> = bincode::deserialize(bytes)?;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
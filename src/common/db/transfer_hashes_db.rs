@@ -5,7 +5,7 @@ use std::{
 
 use casper_types::{bytesrepr::FromBytes, DeployHash};
 
-use super::{Database, DeserializationError};
+use super::{parse_digest_key, Database, DeserializationError};
 
 pub struct TransferHashesDatabase;
 
@@ -24,4 +24,8 @@ impl Database for TransferHashesDatabase {
         let _: Vec<DeployHash> = FromBytes::from_bytes(bytes)?.0;
         Ok(())
     }
+
+    fn parse_key(raw_key: &[u8]) -> Result<(), DeserializationError> {
+        parse_digest_key(raw_key).map(|_| ())
+    }
 }
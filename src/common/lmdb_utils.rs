@@ -1,10 +1,16 @@
-use std::result::Result;
+use std::{mem, ptr, result::Result};
 
-use lmdb::{Database, Error, Transaction};
-use lmdb_sys::{mdb_stat, MDB_stat};
+use lmdb::{Cursor, Database, Environment, Error, Iter, RoCursor, Transaction};
+use lmdb_sys::{
+    mdb_cursor_close, mdb_cursor_get, mdb_cursor_open, mdb_env_info, mdb_stat, MDB_cursor, MDB_dbi,
+    MDB_envinfo, MDB_stat, MDB_val, MDB_FIRST, MDB_NEXT, MDB_NOTFOUND, MDB_SET_RANGE,
+};
 
-/// Retrieves the number of entries in a database.
-pub fn entry_count<T: Transaction>(txn: &'_ T, database: Database) -> Result<usize, Error> {
+/// Index of the free list database, which isn't exposed by `lmdb`.
+const FREE_DBI: MDB_dbi = 0;
+
+/// Retrieves the statistics of a database.
+pub fn db_stat<T: Transaction>(txn: &'_ T, database: Database) -> Result<MDB_stat, Error> {
     let mut stat = MDB_stat {
         ms_psize: 0,
         ms_depth: 0,
@@ -17,7 +23,102 @@ pub fn entry_count<T: Transaction>(txn: &'_ T, database: Database) -> Result<usi
     if result != 0 {
         Err(Error::from_err_code(result))
     } else {
-        Ok(stat.ms_entries)
+        Ok(stat)
+    }
+}
+
+/// Opens a named database, or returns `None` if it doesn't exist in this
+/// version of the storage schema.
+pub fn open_db_if_present<T: Transaction>(
+    txn: &T,
+    db_name: &str,
+) -> Result<Option<Database>, Error> {
+    match unsafe { txn.open_db(Some(db_name)) } {
+        Ok(db) => Ok(Some(db)),
+        Err(Error::NotFound) => Ok(None),
+        Err(lmdb_err) => Err(lmdb_err),
+    }
+}
+
+/// Retrieves the number of entries in a database.
+pub fn entry_count<T: Transaction>(txn: &'_ T, database: Database) -> Result<usize, Error> {
+    db_stat(txn, database).map(|stat| stat.ms_entries)
+}
+
+/// Retrieves the information about an environment, such as its map size
+/// and last page used.
+pub fn env_info(env: &Environment) -> Result<MDB_envinfo, Error> {
+    let mut info = MDB_envinfo {
+        me_mapaddr: ptr::null_mut(),
+        me_mapsize: 0,
+        me_last_pgno: 0,
+        me_last_txnid: 0,
+        me_maxreaders: 0,
+        me_numreaders: 0,
+    };
+    let result = unsafe { mdb_env_info(env.env(), &mut info as *mut MDB_envinfo) };
+    if result != 0 {
+        Err(Error::from_err_code(result))
+    } else {
+        Ok(info)
+    }
+}
+
+/// Retrieves the number of the last page in use in the environment's data
+/// file.
+#[cfg(all(target_endian = "little", target_pointer_width = "64"))]
+pub fn last_page_number(env: &Environment) -> Result<usize, Error> {
+    env_info(env).map(|info| info.me_last_pgno)
+}
+
+/// Retrieves the number of pages listed in the free list of the environment.
+pub fn free_page_count<T: Transaction>(txn: &'_ T) -> Result<usize, Error> {
+    let mut cursor: *mut MDB_cursor = ptr::null_mut();
+    let result = unsafe { mdb_cursor_open(txn.txn(), FREE_DBI, &mut cursor) };
+    if result != 0 {
+        return Err(Error::from_err_code(result));
+    }
+    let mut key = MDB_val {
+        mv_size: 0,
+        mv_data: ptr::null_mut(),
+    };
+    let mut data = MDB_val {
+        mv_size: 0,
+        mv_data: ptr::null_mut(),
+    };
+    let mut free_pages = 0;
+    let mut op = MDB_FIRST;
+    let result = loop {
+        let result = unsafe { mdb_cursor_get(cursor, &mut key, &mut data, op) };
+        if result != 0 {
+            break result;
+        }
+        // Each entry is a list of page numbers, prefixed by their count.
+        if data.mv_size >= mem::size_of::<usize>() {
+            free_pages += unsafe { ptr::read_unaligned(data.mv_data as *const usize) };
+        }
+        op = MDB_NEXT;
+    };
+    unsafe { mdb_cursor_close(cursor) };
+    match result {
+        MDB_NOTFOUND => Ok(free_pages),
+        err_code => Err(Error::from_err_code(err_code)),
+    }
+}
+
+/// Positions the cursor on the first key greater than or equal to `key` and
+/// returns an iterator over the entries starting from there. Returns `None` if
+/// there is no such key in the database.
+pub fn iter_from_key<'txn>(
+    cursor: &mut RoCursor<'txn>,
+    key: &[u8],
+) -> Result<Option<Iter<'txn>>, Error> {
+    // `Cursor::iter_from` panics when the key is past the last entry, so look
+    // it up first.
+    match cursor.get(Some(key), None, MDB_SET_RANGE) {
+        Ok(_) => Ok(Some(cursor.iter_from(key))),
+        Err(Error::NotFound) => Ok(None),
+        Err(lmdb_err) => Err(lmdb_err),
     }
 }
 
@@ -27,7 +128,7 @@ mod tests {
 
     use crate::test_utils::LmdbTestFixture;
 
-    use super::entry_count;
+    use super::{entry_count, iter_from_key};
 
     #[test]
     fn db_entry_count() {
@@ -87,4 +188,36 @@ mod tests {
             txn.commit().unwrap();
         };
     }
+
+    #[test]
+    fn db_iter_from_key() {
+        let fixture = LmdbTestFixture::new(vec![], None);
+        let env = &fixture.env;
+        let db = fixture.db(None).unwrap();
+
+        if let Ok(mut txn) = env.begin_rw_txn() {
+            for key in [[1u8, 0u8], [3u8, 0u8], [5u8, 0u8]] {
+                txn.put(*db, &key, &key, WriteFlags::empty()).unwrap();
+            }
+            txn.commit().unwrap();
+        };
+
+        let txn = env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(*db).unwrap();
+        let keys: Vec<Vec<u8>> = iter_from_key(&mut cursor, &[3u8])
+            .unwrap()
+            .unwrap()
+            .map(|(key, _)| key.to_vec())
+            .collect();
+        assert_eq!(keys, vec![vec![3u8, 0u8], vec![5u8, 0u8]]);
+
+        let keys: Vec<Vec<u8>> = iter_from_key(&mut cursor, &[0u8])
+            .unwrap()
+            .unwrap()
+            .map(|(key, _)| key.to_vec())
+            .collect();
+        assert_eq!(keys.len(), 3);
+
+        assert!(iter_from_key(&mut cursor, &[5u8, 1u8]).unwrap().is_none());
+    }
 }
@@ -56,7 +56,11 @@ impl ProgressTracker {
     /// as input.
     pub fn advance_by(&mut self, step: usize) {
         self.processed += step;
-        while self.processed >= (self.total_to_process * self.progress_factor as usize) / STEPS {
+        // With fewer than `STEPS` items, several milestones are passed at
+        // once, and the last one is passed before all are processed.
+        while self.progress_factor <= STEPS as u64
+            && self.processed >= (self.total_to_process * self.progress_factor as usize) / STEPS
+        {
             (*self.log_progress)(self.progress_factor * PROGRESS_MULTIPLIER);
             self.progress_factor += 1;
         }
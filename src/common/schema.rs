@@ -0,0 +1,245 @@
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter, Result as FormatterResult},
+    result::Result,
+    str::FromStr,
+};
+
+use lmdb::{Cursor, Environment, Error as LmdbError, Transaction};
+use log::info;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use casper_node::types::BlockHeader;
+use casper_types::ProtocolVersion;
+
+use super::db::{
+    BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase, BlockMetadataDatabase,
+    Database, DeployDatabase, DeployHashesDatabase, DeployMetadataDatabase,
+    FinalizedApprovalsDatabase, ProposerDatabase, StateStoreDatabase, TransferDatabase,
+    TransferHashesDatabase,
+};
+
+/// Number of block headers sampled to determine the protocol versions.
+const HEADER_SAMPLE_SIZE: usize = 100;
+/// Databases introduced with the 1.5 storage layout.
+const V1_5_DATABASE_NAMES: [&str; 2] = ["approvals_hashes", "block_body_v2"];
+/// Prefix of the databases introduced after the 1.x storage layouts.
+const VERSIONED_DATABASE_PREFIX: &str = "versioned_";
+
+/// Errors encountered when detecting the schema of a storage database.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Database operation error.
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+}
+
+/// Generation of the storage layout written by `casper-node`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum SchemaVersion {
+    /// Nodes before 1.4, without the merkleized block body databases.
+    V1_0,
+    /// Nodes 1.4.x, with the merkleized block body databases.
+    V1_4,
+    /// Nodes 1.5.x, with versioned block bodies and approvals hashes.
+    V1_5,
+    /// Layout not recognized by this tool.
+    Unknown,
+}
+
+impl SchemaVersion {
+    /// Returns `true` if the databases of this schema can be decoded. The
+    /// versioned layouts of 1.5 aren't known to the `casper-node` this tool
+    /// is built against.
+    pub fn is_supported(&self) -> bool {
+        matches!(self, SchemaVersion::V1_0 | SchemaVersion::V1_4)
+    }
+}
+
+impl Display for SchemaVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatterResult {
+        match self {
+            SchemaVersion::V1_0 => write!(f, "1.0"),
+            SchemaVersion::V1_4 => write!(f, "1.4"),
+            SchemaVersion::V1_5 => write!(f, "1.5"),
+            SchemaVersion::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl FromStr for SchemaVersion {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "1.0" => Ok(SchemaVersion::V1_0),
+            "1.4" => Ok(SchemaVersion::V1_4),
+            "1.5" => Ok(SchemaVersion::V1_5),
+            other => Err(format!("unknown schema version {other}")),
+        }
+    }
+}
+
+/// Selection of the decoders used to parse the values of a database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaSelection {
+    /// Try the decoders of all known layouts.
+    Auto,
+    /// Use the decoders of the given schema version.
+    Version(SchemaVersion),
+}
+
+impl FromStr for SchemaSelection {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "auto" => Ok(SchemaSelection::Auto),
+            version => SchemaVersion::from_str(version).map(SchemaSelection::Version),
+        }
+    }
+}
+
+/// Description of the schema of a storage database.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SchemaReport {
+    /// Detected schema generation.
+    pub schema: SchemaVersion,
+    /// Names of the databases present in the environment.
+    pub databases: Vec<String>,
+    /// Distinct protocol versions found in the sampled block headers.
+    pub protocol_versions: Vec<String>,
+    /// Number of block headers sampled.
+    pub headers_sampled: usize,
+    /// Number of sampled block headers which couldn't be decoded.
+    pub headers_undecodable: usize,
+}
+
+impl SchemaReport {
+    /// Returns `true` if a database with the given name is present.
+    pub fn has_database(&self, db_name: &str) -> bool {
+        self.databases.iter().any(|name| name == db_name)
+    }
+}
+
+/// Returns the names of the named databases in the environment, which are the
+/// keys of its unnamed database.
+pub(crate) fn database_names(env: &Environment) -> Result<Vec<String>, LmdbError> {
+    let txn = env.begin_ro_txn()?;
+    let main_db = unsafe { txn.open_db(None)? };
+    let mut cursor = txn.open_ro_cursor(main_db)?;
+    let names = cursor
+        .iter()
+        .map(|(raw_key, _)| String::from_utf8_lossy(raw_key).to_string())
+        .collect();
+    Ok(names)
+}
+
+/// Returns the distinct protocol versions of the first block headers in the
+/// database, along with the number of headers sampled and the number which
+/// couldn't be decoded.
+fn sample_protocol_versions(
+    env: &Environment,
+) -> Result<(BTreeSet<ProtocolVersion>, usize, usize), LmdbError> {
+    let txn = env.begin_ro_txn()?;
+    let db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name()))? };
+    let mut cursor = txn.open_ro_cursor(db)?;
+    let mut protocol_versions = BTreeSet::new();
+    let mut sampled = 0;
+    let mut undecodable = 0;
+    // Keys are block hashes, so the first entries are a random sample.
+    for (_raw_key, raw_val) in cursor.iter().take(HEADER_SAMPLE_SIZE) {
+        sampled += 1;
+        match bincode::deserialize::<BlockHeader>(raw_val) {
+            Ok(header) => {
+                protocol_versions.insert(header.protocol_version());
+            }
+            Err(_) => undecodable += 1,
+        }
+    }
+    Ok((protocol_versions, sampled, undecodable))
+}
+
+/// Returns the names of the databases of the 1.x storage layouts.
+fn storage_database_names() -> [&'static str; 12] {
+    [
+        BlockHeaderDatabase::db_name(),
+        BlockBodyDatabase::db_name(),
+        BlockBodyMerkleDatabase::db_name(),
+        BlockMetadataDatabase::db_name(),
+        DeployDatabase::db_name(),
+        DeployHashesDatabase::db_name(),
+        DeployMetadataDatabase::db_name(),
+        FinalizedApprovalsDatabase::db_name(),
+        ProposerDatabase::db_name(),
+        StateStoreDatabase::db_name(),
+        TransferDatabase::db_name(),
+        TransferHashesDatabase::db_name(),
+    ]
+}
+
+/// Detects the schema generation of a storage database from the named
+/// databases it holds and the protocol versions of a sample of its block
+/// headers.
+///
+/// Storages without block headers, such as partial copies holding a few
+/// databases, are recognized from the other databases they hold.
+pub fn detect_schema(env: &Environment) -> Result<SchemaReport, Error> {
+    let databases = database_names(env)?;
+    let has_database = |db_name: &str| databases.iter().any(|name| name == db_name);
+
+    let (protocol_versions, headers_sampled, headers_undecodable) =
+        if has_database(BlockHeaderDatabase::db_name()) {
+            sample_protocol_versions(env)?
+        } else {
+            (BTreeSet::new(), 0, 0)
+        };
+    let max_protocol_version = protocol_versions.last();
+
+    let schema = if databases
+        .iter()
+        .any(|name| name.starts_with(VERSIONED_DATABASE_PREFIX))
+        || !storage_database_names()
+            .iter()
+            .chain(V1_5_DATABASE_NAMES.iter())
+            .any(|db_name| has_database(db_name))
+    {
+        SchemaVersion::Unknown
+    } else if V1_5_DATABASE_NAMES
+        .iter()
+        .any(|db_name| has_database(db_name))
+        || max_protocol_version.map_or(false, |version| {
+            *version >= ProtocolVersion::from_parts(1, 5, 0)
+        })
+    {
+        SchemaVersion::V1_5
+    } else if has_database(BlockBodyMerkleDatabase::db_name()) {
+        SchemaVersion::V1_4
+    } else {
+        SchemaVersion::V1_0
+    };
+
+    let report = SchemaReport {
+        schema,
+        databases,
+        protocol_versions: protocol_versions.iter().map(ToString::to_string).collect(),
+        headers_sampled,
+        headers_undecodable,
+    };
+    info!(
+        "Detected schema {} with protocol versions [{}].",
+        report.schema,
+        report.protocol_versions.join(", ")
+    );
+    if report.headers_undecodable > 0 {
+        info!(
+            "{} of {} sampled block headers couldn't be decoded.",
+            report.headers_undecodable, report.headers_sampled
+        );
+    }
+    Ok(report)
+}
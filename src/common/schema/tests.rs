@@ -0,0 +1,134 @@
+use lmdb::{Transaction, WriteFlags};
+
+use casper_types::ProtocolVersion;
+
+use super::{detect_schema, SchemaVersion};
+use crate::{
+    common::db::{BlockBodyMerkleDatabase, BlockHeaderDatabase, Database, DeployDatabase},
+    test_utils::{mock_block_header, LmdbTestFixture},
+};
+
+fn populate_headers(fixture: &LmdbTestFixture, protocol_versions: &[ProtocolVersion]) {
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for (idx, protocol_version) in protocol_versions.iter().enumerate() {
+        let (block_hash, mut block_header) = mock_block_header(idx as u8);
+        block_header.protocol_version = *protocol_version;
+        txn.put(
+            *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap(),
+            &block_hash,
+            &bincode::serialize(&block_header).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn schema_should_follow_named_databases() {
+    let fixture = LmdbTestFixture::new(vec![BlockHeaderDatabase::db_name()], None);
+    populate_headers(&fixture, &[ProtocolVersion::from_parts(1, 2, 0)]);
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::V1_0);
+    assert_eq!(report.databases, vec![BlockHeaderDatabase::db_name()]);
+
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+        ],
+        None,
+    );
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::V1_4);
+    assert!(report.has_database(BlockBodyMerkleDatabase::db_name()));
+    assert_eq!(report.headers_sampled, 0);
+
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+            "approvals_hashes",
+        ],
+        None,
+    );
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::V1_5);
+    // The decoders of the 1.5 layouts aren't available.
+    assert!(!report.schema.is_supported());
+
+    let fixture = LmdbTestFixture::new(vec!["versioned_block_header"], None);
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::Unknown);
+    assert!(!report.schema.is_supported());
+
+    let fixture = LmdbTestFixture::new(vec!["unrelated"], None);
+    assert_eq!(
+        detect_schema(&fixture.env).unwrap().schema,
+        SchemaVersion::Unknown
+    );
+}
+
+#[test]
+fn schema_should_be_detected_without_block_headers() {
+    let fixture = LmdbTestFixture::new(vec![DeployDatabase::db_name()], None);
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::V1_0);
+    assert_eq!(report.headers_sampled, 0);
+
+    let fixture = LmdbTestFixture::new(
+        vec![
+            DeployDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+        ],
+        None,
+    );
+    assert_eq!(
+        detect_schema(&fixture.env).unwrap().schema,
+        SchemaVersion::V1_4
+    );
+}
+
+#[test]
+fn schema_should_report_sampled_protocol_versions() {
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+        ],
+        None,
+    );
+    populate_headers(
+        &fixture,
+        &[
+            ProtocolVersion::from_parts(1, 4, 5),
+            ProtocolVersion::from_parts(1, 4, 3),
+            ProtocolVersion::from_parts(1, 4, 5),
+        ],
+    );
+    {
+        let mut txn = fixture.env.begin_rw_txn().unwrap();
+        txn.put(
+            *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap(),
+            &[0xffu8; 32],
+            &[0u8; 4],
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+
+    let report = detect_schema(&fixture.env).unwrap();
+    assert_eq!(report.schema, SchemaVersion::V1_4);
+    assert_eq!(report.protocol_versions, vec!["1.4.3", "1.4.5"]);
+    assert_eq!(report.headers_sampled, 4);
+    assert_eq!(report.headers_undecodable, 1);
+
+    // Headers from 1.5 nodes mean the 1.5 layout even if its databases are
+    // not created yet.
+    populate_headers(&fixture, &[ProtocolVersion::from_parts(1, 5, 0)]);
+    assert_eq!(
+        detect_schema(&fixture.env).unwrap().schema,
+        SchemaVersion::V1_5
+    );
+}
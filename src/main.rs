@@ -10,7 +10,8 @@ use clap::{crate_description, crate_version, Arg, Command};
 use log::error;
 
 use subcommands::{
-    archive, check, execution_results_summary, extract_slice, latest_block_summary, trie_compact,
+    archive, check, check_trie, compact_storage, db_stats, detect_version,
+    execution_results_summary, extract_slice, find_orphans, latest_block_summary, trie_compact,
     unsparse, Error,
 };
 
@@ -19,8 +20,13 @@ const LOGGING: &str = "logging";
 enum DisplayOrder {
     Archive,
     Check,
+    CheckTrie,
+    CompactStorage,
+    DbStats,
+    DetectVersion,
     ExecutionResults,
     ExtractSlice,
+    FindOrphans,
     LatestBlock,
     TrieCompact,
     Unsparse,
@@ -33,10 +39,19 @@ fn cli() -> Command<'static> {
         .arg_required_else_help(true)
         .subcommand(archive::command(DisplayOrder::Archive as usize))
         .subcommand(check::command(DisplayOrder::Check as usize))
+        .subcommand(check_trie::command(DisplayOrder::CheckTrie as usize))
+        .subcommand(compact_storage::command(
+            DisplayOrder::CompactStorage as usize,
+        ))
+        .subcommand(db_stats::command(DisplayOrder::DbStats as usize))
+        .subcommand(detect_version::command(
+            DisplayOrder::DetectVersion as usize,
+        ))
         .subcommand(execution_results_summary::command(
             DisplayOrder::ExecutionResults as usize,
         ))
         .subcommand(extract_slice::command(DisplayOrder::ExtractSlice as usize))
+        .subcommand(find_orphans::command(DisplayOrder::FindOrphans as usize))
         .subcommand(latest_block_summary::command(
             DisplayOrder::LatestBlock as usize,
         ))
@@ -80,10 +95,15 @@ fn main() {
     let result: Result<(), Error> = match subcommand_name {
         archive::COMMAND_NAME => archive::run(matches).map_err(Error::from),
         check::COMMAND_NAME => check::run(matches).map_err(Error::from),
+        check_trie::COMMAND_NAME => check_trie::run(matches).map_err(Error::from),
+        compact_storage::COMMAND_NAME => compact_storage::run(matches).map_err(Error::from),
+        db_stats::COMMAND_NAME => db_stats::run(matches).map_err(Error::from),
+        detect_version::COMMAND_NAME => detect_version::run(matches).map_err(Error::from),
         execution_results_summary::COMMAND_NAME => {
             execution_results_summary::run(matches).map_err(Error::from)
         }
         extract_slice::COMMAND_NAME => extract_slice::run(matches).map_err(Error::from),
+        find_orphans::COMMAND_NAME => find_orphans::run(matches).map_err(Error::from),
         latest_block_summary::COMMAND_NAME => {
             latest_block_summary::run(matches).map_err(Error::from)
         }
@@ -1,17 +1,30 @@
 pub mod archive;
 pub mod check;
+pub mod check_trie;
+pub mod compact_storage;
+pub mod db_stats;
+pub mod detect_version;
 pub mod execution_results_summary;
 pub mod extract_slice;
+pub mod find_orphans;
 pub mod latest_block_summary;
 pub mod trie_compact;
 pub mod unsparse;
 
+use std::str::FromStr;
+
+use clap::ArgMatches;
 use thiserror::Error as ThisError;
 
 use archive::{CreateError, UnpackError};
 use check::Error as CheckError;
+use check_trie::Error as CheckTrieError;
+use compact_storage::Error as CompactStorageError;
+use db_stats::Error as DbStatsError;
+use detect_version::Error as DetectVersionError;
 use execution_results_summary::Error as ExecutionResultsSummaryError;
 use extract_slice::Error as ExtractSliceError;
+use find_orphans::Error as FindOrphansError;
 use latest_block_summary::Error as LatestBlockSummaryError;
 use trie_compact::Error as TrieCompactError;
 use unsparse::Error as UnsparseError;
@@ -24,10 +37,20 @@ pub enum Error {
     ArchiveUnpack(#[from] UnpackError),
     #[error("Check command failed: {0}")]
     Check(#[from] CheckError),
+    #[error("Check trie command failed: {0}")]
+    CheckTrie(#[from] CheckTrieError),
+    #[error("Compact storage command failed: {0}")]
+    CompactStorage(#[from] CompactStorageError),
+    #[error("Database statistics command failed: {0}")]
+    DbStats(#[from] DbStatsError),
+    #[error("Detect version command failed: {0}")]
+    DetectVersion(#[from] DetectVersionError),
     #[error("Execution results summary command failed: {0}")]
     ExecutionResultsSummary(#[from] ExecutionResultsSummaryError),
     #[error("Extract slice command failed: {0}")]
     ExtractSlice(#[from] ExtractSliceError),
+    #[error("Find orphans command failed: {0}")]
+    FindOrphans(#[from] FindOrphansError),
     #[error("Latest block summary command failed: {0}")]
     LatestBlockSummary(#[from] LatestBlockSummaryError),
     #[error("Trie compact failed: {0}")]
@@ -35,3 +58,13 @@ pub enum Error {
     #[error("Unsparse failed: {0}")]
     Unsparse(#[from] UnsparseError),
 }
+
+/// Parses the value of the integer argument `name`, if present. Panics with
+/// the name of the argument if the value isn't an integer.
+pub(crate) fn integer_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    matches.value_of(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Value of \"--{name}\" must be an integer."))
+    })
+}
@@ -1,37 +1,139 @@
-use std::path::{Path, PathBuf};
+mod checkpoint;
+mod integrity;
+mod jobs;
+mod repair;
+mod report;
+mod structure;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{self, Error as IoError, Write},
+    path::{Path, PathBuf},
+};
 
 use clap::{Arg, ArgMatches, Command};
-use lmdb::Error as LmdbError;
+use lmdb::{Environment, Error as LmdbError};
+use log::info;
+use serde_json::Error as JsonSerializationError;
 use thiserror::Error as ThisError;
 
-use crate::common::db::{
-    db_env, BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase, BlockMetadataDatabase,
-    Database, DeployDatabase, DeployHashesDatabase, DeployMetadataDatabase, Error as DbError,
-    FinalizedApprovalsDatabase, ProposerDatabase, StateStoreDatabase, TransferDatabase,
-    TransferHashesDatabase, STORAGE_FILE_NAME,
+use crate::{
+    common::{
+        db::{
+            db_env, BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase,
+            BlockMetadataDatabase, Database, DeployDatabase, DeployHashesDatabase,
+            DeployMetadataDatabase, Error as DbError, FinalizedApprovalsDatabase, KeyRange,
+            ProposerDatabase, SampleSize, SampleStats, StateStoreDatabase, TransferDatabase,
+            TransferHashesDatabase, STORAGE_FILE_NAME,
+        },
+        schema::{self, Error as SchemaError, SchemaSelection},
+    },
+    subcommands::integer_arg,
 };
 
+use checkpoint::{Checkpoint, CHECKPOINT_FILE_NAME};
+pub use integrity::IntegrityError;
+use jobs::CheckTask;
+pub use repair::RepairError;
+use report::{CheckReport, DatabaseReport};
+pub use structure::StructureError;
+
 pub const COMMAND_NAME: &str = "check";
+const CHECKPOINT_FILE: &str = "checkpoint-file";
 const DB_PATH: &str = "db-path";
+const DEEP: &str = "deep";
+const FORMAT: &str = "format";
+const JOBS: &str = "jobs";
+const LMDB_STRUCTURE: &str = "lmdb-structure";
+const NO_DRY_RUN: &str = "no-dry-run";
 const NO_FAILFAST: &str = "no-failfast";
+const OUTPUT: &str = "output";
+const OVERWRITE: &str = "overwrite";
+const QUARANTINE: &str = "quarantine";
+const REPAIR: &str = "repair";
+const RESUME: &str = "resume";
+const SAMPLE: &str = "sample";
+const SCHEMA: &str = "schema";
+const SHARDS: &str = "shards";
 const SPECIFIC: &str = "specific";
 const START_AT: &str = "start-at";
+const START_AT_KEY: &str = "start-at-key";
+
+const FORMAT_JSON: &str = "json";
+const FORMAT_TEXT: &str = "text";
+
+/// Names of all the databases checked when no specific database is requested.
+const DATABASE_NAMES: [&str; 12] = [
+    "block_body",
+    "block_body_merkle",
+    "block_header",
+    "block_metadata",
+    "deploy_hashes",
+    "deploy_metadata",
+    "deploys",
+    "finalized_approvals",
+    "proposers",
+    "state_store",
+    "transfer",
+    "transfer_hashes",
+];
 
 enum DisplayOrder {
     NoFailfast,
     DbPath,
     Specific,
     StartAt,
+    Deep,
+    Format,
+    Output,
+    Overwrite,
+    Jobs,
+    Shards,
+    StartAtKey,
+    CheckpointFile,
+    Resume,
+    Repair,
+    Quarantine,
+    NoDryRun,
+    Schema,
+    Sample,
+    LmdbStructure,
 }
 
 #[derive(ThisError, Debug)]
 pub enum Error {
-    #[error("Error checking the database: {0}")]
-    Database(#[from] DbError),
+    #[error(
+        "{}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+    )]
+    Accumulated(Vec<Self>),
+    #[error("Error reading checkpoint file {0}: {1}")]
+    Checkpoint(PathBuf, String),
+    #[error("Error checking the {0} database: {1}")]
+    Database(String, DbError),
+    #[error("Error checking references between databases: {0}")]
+    Integrity(#[from] IntegrityError),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
     #[error("Error initializing lmdb environment at {0}: {1}")]
     Path(PathBuf, LmdbError),
+    #[error("Error repairing database: {0}")]
+    Repair(#[from] RepairError),
+    #[error("Error detecting schema: {0}")]
+    Schema(#[from] SchemaError),
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] JsonSerializationError),
+    #[error("Error verifying the LMDB page structure: {0}")]
+    Structure(#[from] StructureError),
+    #[error("Found {0} problems in the LMDB page structure")]
+    StructureProblems(usize),
     #[error("Unknown database {0}")]
     UnknownDb(String),
+    #[error("Unsupported storage schema, found databases: {0}")]
+    UnsupportedSchema(String),
 }
 
 pub fn command(display_order: usize) -> Command<'static> {
@@ -84,63 +186,621 @@ pub fn command(display_order: usize) -> Command<'static> {
                     to be set.",
                 ),
         )
+        .arg(
+            Arg::new(DEEP)
+                .display_order(DisplayOrder::Deep as usize)
+                .long(DEEP)
+                .takes_value(false)
+                .conflicts_with(SPECIFIC)
+                .help(
+                    "After parsing all databases, walk the block header database and verify \
+                    that every block body, deploy, execution result and transfer referenced \
+                    by a block is present in storage.",
+                ),
+        )
+        .arg(
+            Arg::new(FORMAT)
+                .display_order(DisplayOrder::Format as usize)
+                .long(FORMAT)
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values([FORMAT_TEXT, FORMAT_JSON])
+                .default_value(FORMAT_TEXT)
+                .help(
+                    "Format of the results. With \"json\", a report with the entries scanned \
+                    and the entries which failed to parse in each database is written to the \
+                    output.",
+                ),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .help(
+                    "Path to where the program will output the JSON report. Implies \
+                    \"--format json\". If unspecified, the report is written to standard \
+                    output.",
+                ),
+        )
+        .arg(
+            Arg::new(OVERWRITE)
+                .display_order(DisplayOrder::Overwrite as usize)
+                .required(false)
+                .short('w')
+                .long(OVERWRITE)
+                .takes_value(false)
+                .requires(OUTPUT)
+                .help("Overwrite an already existing output file."),
+        )
+        .arg(
+            Arg::new(JOBS)
+                .display_order(DisplayOrder::Jobs as usize)
+                .short('j')
+                .long(JOBS)
+                .takes_value(true)
+                .value_name("JOBS")
+                .default_value("1")
+                .help(
+                    "Number of threads checking databases concurrently, each with its own read \
+                    transaction.",
+                ),
+        )
+        .arg(
+            Arg::new(SHARDS)
+                .display_order(DisplayOrder::Shards as usize)
+                .long(SHARDS)
+                .takes_value(true)
+                .value_name("SHARD_COUNT")
+                .default_value("1")
+                .conflicts_with(START_AT)
+                .help(
+                    "Split each database into this many key ranges which are checked as \
+                    separate jobs. Entry indices in errors are relative to the start of their \
+                    key range.",
+                ),
+        )
+        .arg(
+            Arg::new(START_AT_KEY)
+                .display_order(DisplayOrder::StartAtKey as usize)
+                .long(START_AT_KEY)
+                .takes_value(true)
+                .value_name("HEX_KEY")
+                .requires(SPECIFIC)
+                .conflicts_with_all(&[START_AT, SHARDS, RESUME])
+                .help(
+                    "Hex encoded key from which parsing will start. The first entry checked is \
+                    the one with the lowest key greater than or equal to it. Requires \
+                    \"--specific\" parameter to be set.",
+                ),
+        )
+        .arg(
+            Arg::new(CHECKPOINT_FILE)
+                .display_order(DisplayOrder::CheckpointFile as usize)
+                .long(CHECKPOINT_FILE)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .help(
+                    "Path of the file where the last key checked in each database is \
+                    recorded, so that an interrupted check can be resumed with \"--resume\". \
+                    No checkpoint file is written unless this or \"--resume\" is given, in \
+                    which case it defaults to \"check_checkpoint.json\" in the database \
+                    directory.",
+                ),
+        )
+        .arg(
+            Arg::new(RESUME)
+                .display_order(DisplayOrder::Resume as usize)
+                .long(RESUME)
+                .takes_value(false)
+                .conflicts_with(START_AT)
+                .help(
+                    "Resume an interrupted check from the checkpoint file, skipping the \
+                    databases which were fully checked and starting the others after the last \
+                    key checked. Must be run with the same \"--shards\" value.",
+                ),
+        )
+        .arg(
+            Arg::new(REPAIR)
+                .display_order(DisplayOrder::Repair as usize)
+                .long(REPAIR)
+                .takes_value(false)
+                .requires(QUARANTINE)
+                .help(
+                    "Move the entries which fail to parse out of their database and into the \
+                    quarantine directory. Implies \"--no-failfast\". Only lists the entries \
+                    unless \"--no-dry-run\" is set.",
+                ),
+        )
+        .arg(
+            Arg::new(QUARANTINE)
+                .display_order(DisplayOrder::Quarantine as usize)
+                .long(QUARANTINE)
+                .takes_value(true)
+                .value_name("DIR_PATH")
+                .requires(REPAIR)
+                .help(
+                    "Directory where the entries removed by \"--repair\" are appended to a \
+                    JSON-lines file, with their hex encoded keys and values.",
+                ),
+        )
+        .arg(
+            Arg::new(NO_DRY_RUN)
+                .display_order(DisplayOrder::NoDryRun as usize)
+                .long(NO_DRY_RUN)
+                .takes_value(false)
+                .requires(REPAIR)
+                .help("Actually delete the quarantined entries from the databases."),
+        )
+        .arg(
+            Arg::new(SCHEMA)
+                .display_order(DisplayOrder::Schema as usize)
+                .long(SCHEMA)
+                .takes_value(true)
+                .value_name("SCHEMA_VERSION")
+                .possible_values(["auto", "1.0", "1.4"])
+                .help(
+                    "Schema version whose decoders are used to parse the entries. With \
+                    \"auto\", every known layout is tried and the layouts which matched are \
+                    logged. Defaults to the schema detected from the database.",
+                ),
+        )
+        .arg(
+            Arg::new(SAMPLE)
+                .display_order(DisplayOrder::Sample as usize)
+                .long(SAMPLE)
+                .takes_value(true)
+                .value_name("FRACTION_OR_COUNT")
+                .conflicts_with_all(&[START_AT, START_AT_KEY, SHARDS, RESUME, REPAIR, DEEP])
+                .help(
+                    "Parse a random sample of the entries in each database instead of all of \
+                    them, either a fraction in (0, 1] or a number of entries, and report the \
+                    estimated corruption rate with its 95% confidence interval.",
+                ),
+        )
+        .arg(
+            Arg::new(LMDB_STRUCTURE)
+                .display_order(DisplayOrder::LmdbStructure as usize)
+                .long(LMDB_STRUCTURE)
+                .takes_value(false)
+                .conflicts_with_all(&[
+                    SPECIFIC,
+                    START_AT,
+                    START_AT_KEY,
+                    SHARDS,
+                    RESUME,
+                    REPAIR,
+                    DEEP,
+                    SCHEMA,
+                    SAMPLE,
+                ])
+                .help(
+                    "Instead of parsing the entries, walk the LMDB page tree of every database \
+                    in the data file and verify the page headers, the key ordering, the \
+                    overflow pages and the free list. All the problems found are reported. \
+                    Only available on little-endian 64-bit targets.",
+                ),
+        )
+}
+
+/// Options of the repair mode.
+struct RepairOptions {
+    /// Directory holding the quarantine file.
+    quarantine_dir: PathBuf,
+    /// Only log the entries which would be quarantined.
+    dry_run: bool,
+}
+
+/// Options of a `check` run.
+struct CheckOptions<'a> {
+    /// Stop at the first entry which fails to parse.
+    failfast: bool,
+    /// Name of the only database to check, all of them if `None`.
+    specific: Option<&'a str>,
+    /// Index of the entry where parsing starts.
+    start_at: usize,
+    /// Verify the references between databases.
+    deep: bool,
+    /// Number of databases, or key ranges of them, checked concurrently.
+    jobs: usize,
+    /// Number of key ranges each database is split into.
+    shards: usize,
+    /// Key of the entry where parsing starts.
+    start_at_key: Option<Vec<u8>>,
+    /// Path of the checkpoint file, `None` if no checkpoint is recorded.
+    checkpoint_path: Option<PathBuf>,
+    /// Resume from the checkpoint file.
+    resume: bool,
+    /// Quarantine the entries which fail to parse.
+    repair: Option<RepairOptions>,
+    /// Decoders used to parse the entries, those of the detected schema if
+    /// `None`.
+    schema: Option<SchemaSelection>,
+    /// Parse a random sample of the entries instead of all of them.
+    sample: Option<SampleSize>,
+    /// Verify the LMDB page structure instead of parsing the entries.
+    lmdb_structure: bool,
 }
 
 pub fn run(matches: &ArgMatches) -> Result<(), Error> {
     let path = matches.value_of(DB_PATH).unwrap();
-    let failfast = !matches.is_present(NO_FAILFAST);
+    let repair = matches.is_present(REPAIR).then(|| RepairOptions {
+        quarantine_dir: PathBuf::from(
+            matches
+                .value_of(QUARANTINE)
+                .expect("should be required by --repair"),
+        ),
+        dry_run: !matches.is_present(NO_DRY_RUN),
+    });
+    // Repairing needs all the faulty entries.
+    let failfast = !matches.is_present(NO_FAILFAST) && repair.is_none();
     let specific = matches.value_of(SPECIFIC);
-    let start_at: usize = matches
-        .value_of(START_AT)
-        .expect("should have a default")
-        .parse()
-        .unwrap_or_else(|_| panic!("Value of \"--{START_AT}\" must be an integer."));
+    let start_at: usize = integer_arg(matches, START_AT).expect("should have a default");
+    let deep = matches.is_present(DEEP);
+    let jobs: usize = integer_arg(matches, JOBS).expect("should have a default");
+    let shards: usize = integer_arg(matches, SHARDS).expect("should have a default");
+    let start_at_key = matches.value_of(START_AT_KEY).map(|hex_key| {
+        hex::decode(hex_key)
+            .unwrap_or_else(|_| panic!("Value of \"--{START_AT_KEY}\" must be hex encoded."))
+    });
+    let resume = matches.is_present(RESUME);
+    let checkpoint_path = match matches.value_of(CHECKPOINT_FILE) {
+        Some(checkpoint_path) => Some(PathBuf::from(checkpoint_path)),
+        None if resume => Some(Path::new(path).join(CHECKPOINT_FILE_NAME)),
+        None => None,
+    };
+    let schema = matches.value_of(SCHEMA).map(|schema| {
+        schema
+            .parse()
+            .unwrap_or_else(|err| panic!("Invalid value of \"--{SCHEMA}\": {err}"))
+    });
+    let sample = matches.value_of(SAMPLE).map(|sample_size| {
+        sample_size
+            .parse()
+            .unwrap_or_else(|err| panic!("Invalid value of \"--{SAMPLE}\": {err}"))
+    });
+    let lmdb_structure = matches.is_present(LMDB_STRUCTURE);
+    let options = CheckOptions {
+        failfast,
+        specific,
+        start_at,
+        deep,
+        jobs,
+        shards,
+        start_at_key,
+        checkpoint_path,
+        resume,
+        repair,
+        schema,
+        sample,
+        lmdb_structure,
+    };
+    let output = matches.value_of(OUTPUT).map(Path::new);
+    let overwrite = matches.is_present(OVERWRITE);
+    // Validate the output file early so that, in case this fails
+    // we don't unnecessarily read the whole database.
+    let maybe_report_writer: Option<Box<dyn Write>> = match output {
+        Some(out_path) => {
+            let file = OpenOptions::new()
+                .create_new(!overwrite)
+                .write(true)
+                .open(out_path)?;
+            Some(Box::new(file))
+        }
+        None if matches.value_of(FORMAT) == Some(FORMAT_JSON) => Some(Box::new(io::stdout())),
+        None => None,
+    };
 
-    check_db(path, failfast, specific, start_at)
+    check_db(path, &options, maybe_report_writer)
 }
 
-fn check_db<P: AsRef<Path>>(
-    path: P,
+type CheckFn = fn(
+    &Environment,
+    bool,
+    usize,
+    &KeyRange,
+    SchemaSelection,
+    &mut dyn FnMut(&[u8]),
+) -> Result<usize, DbError>;
+type SampleFn = fn(&Environment, SampleSize, SchemaSelection) -> Result<SampleStats, DbError>;
+
+/// Operations of the `Database` implementation selected by name.
+struct DatabaseOps {
+    check: CheckFn,
+    sample: SampleFn,
+}
+
+impl DatabaseOps {
+    fn of<D: Database>() -> Self {
+        Self {
+            check: D::check_db,
+            sample: D::sample_db,
+        }
+    }
+
+    fn by_name(db_name: &str) -> Result<Self, Error> {
+        let ops = match db_name {
+            "block_body" => Self::of::<BlockBodyDatabase>(),
+            "block_body_merkle" => Self::of::<BlockBodyMerkleDatabase>(),
+            "block_header" => Self::of::<BlockHeaderDatabase>(),
+            "block_metadata" => Self::of::<BlockMetadataDatabase>(),
+            "deploy_hashes" => Self::of::<DeployHashesDatabase>(),
+            "deploy_metadata" => Self::of::<DeployMetadataDatabase>(),
+            "deploys" => Self::of::<DeployDatabase>(),
+            "finalized_approvals" => Self::of::<FinalizedApprovalsDatabase>(),
+            "proposers" => Self::of::<ProposerDatabase>(),
+            "state_store" => Self::of::<StateStoreDatabase>(),
+            "transfer" => Self::of::<TransferDatabase>(),
+            "transfer_hashes" => Self::of::<TransferHashesDatabase>(),
+            _ => return Err(Error::UnknownDb(db_name.to_string())),
+        };
+        Ok(ops)
+    }
+}
+
+/// Checks the key range of the database with the given name. The outer result
+/// fails if the name is unknown, while the inner one holds the outcome of the
+/// check.
+fn check_named_db(
+    env: &Environment,
+    db_name: &str,
     failfast: bool,
-    specific: Option<&str>,
     start_at: usize,
+    range: &KeyRange,
+    schema: SchemaSelection,
+    on_checkpoint: &mut dyn FnMut(&[u8]),
+) -> Result<Result<usize, DbError>, Error> {
+    let check = DatabaseOps::by_name(db_name)?.check;
+    Ok(check(env, failfast, start_at, range, schema, on_checkpoint))
+}
+
+fn check_db<P: AsRef<Path>>(
+    path: P,
+    options: &CheckOptions,
+    maybe_report_writer: Option<Box<dyn Write>>,
 ) -> Result<(), Error> {
     let storage_path = path.as_ref().join(STORAGE_FILE_NAME);
-    let env = db_env(storage_path)
+    let env = db_env(&storage_path)
         .map_err(|lmdb_err| Error::Path(path.as_ref().to_path_buf(), lmdb_err))?;
-    if let Some(db_name) = specific {
-        match db_name.trim() {
-            "block_body" => BlockBodyDatabase::check_db(&env, failfast, start_at)?,
-            "block_body_merkle" => BlockBodyMerkleDatabase::check_db(&env, failfast, start_at)?,
-            "block_header" => BlockHeaderDatabase::check_db(&env, failfast, start_at)?,
-            "block_metadata" => BlockMetadataDatabase::check_db(&env, failfast, start_at)?,
-            "deploy_hashes" => DeployHashesDatabase::check_db(&env, failfast, start_at)?,
-            "deploy_metadata" => DeployMetadataDatabase::check_db(&env, failfast, start_at)?,
-            "deploys" => DeployDatabase::check_db(&env, failfast, start_at)?,
-            "finalized_approvals" => {
-                FinalizedApprovalsDatabase::check_db(&env, failfast, start_at)?
+    // Runs before anything reads the databases through LMDB, which could
+    // crash on broken pages.
+    if options.lmdb_structure {
+        return check_structure(&env, &storage_path, maybe_report_writer);
+    }
+    let schema_report = schema::detect_schema(&env)?;
+    if !schema_report.schema.is_supported() {
+        return Err(Error::UnsupportedSchema(schema_report.databases.join(", ")));
+    }
+    let schema = options
+        .schema
+        .unwrap_or(SchemaSelection::Version(schema_report.schema));
+    let db_names = match options.specific {
+        Some(db_name) => vec![db_name.trim()],
+        None => {
+            // Sanity check for `start_at`, already validated in arg parser.
+            assert_eq!(options.start_at, 0);
+            // Older schemas don't have all the databases.
+            DATABASE_NAMES
+                .iter()
+                .copied()
+                .filter(|db_name| {
+                    let present = schema_report.has_database(db_name);
+                    if !present {
+                        info!(
+                            "Skipping {} database, not present in storage with schema {}.",
+                            db_name, schema_report.schema
+                        );
+                    }
+                    present
+                })
+                .collect()
+        }
+    };
+
+    let mut report = CheckReport::default();
+    let mut errors = vec![];
+    match options.sample {
+        Some(sample_size) => sample_dbs(
+            &env,
+            &db_names,
+            sample_size,
+            schema,
+            &mut report,
+            &mut errors,
+        )?,
+        None => scan_dbs(&env, &db_names, options, schema, &mut report, &mut errors)?,
+    }
+    if options.deep && (!options.failfast || errors.is_empty()) {
+        if let Err(integrity_err) = integrity::check_integrity(&env, options.failfast) {
+            errors.push(Error::Integrity(integrity_err));
+        }
+    }
+
+    if let Some(out_writer) = maybe_report_writer {
+        report::dump_report(&report, out_writer)?;
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.pop().expect("should have one error")),
+        _ => Err(Error::Accumulated(errors)),
+    }
+}
+
+/// Walks the page trees of the databases and fails if any problem was found.
+fn check_structure(
+    env: &Environment,
+    storage_path: &Path,
+    maybe_report_writer: Option<Box<dyn Write>>,
+) -> Result<(), Error> {
+    let structure_report = structure::check_structure(env, storage_path)?;
+    let problem_count = structure_report.problem_count();
+    info!(
+        "Walked {} databases over {} pages of {} bytes, {} free and {} unaccounted for.",
+        structure_report.databases.len(),
+        structure_report.last_page + 1,
+        structure_report.page_size,
+        structure_report.free_pages,
+        structure_report.unaccounted_pages
+    );
+    if let Some(out_writer) = maybe_report_writer {
+        let report = CheckReport {
+            structure: Some(structure_report),
+            ..Default::default()
+        };
+        report::dump_report(&report, out_writer)?;
+    }
+    match problem_count {
+        0 => Ok(()),
+        _ => Err(Error::StructureProblems(problem_count)),
+    }
+}
+
+/// Fully scans the databases, possibly in parallel key ranges, and quarantines
+/// the faulty entries in repair mode.
+fn scan_dbs(
+    env: &Environment,
+    db_names: &[&str],
+    options: &CheckOptions,
+    schema: SchemaSelection,
+    report: &mut CheckReport,
+    errors: &mut Vec<Error>,
+) -> Result<(), Error> {
+    let mut tasks = CheckTask::split(db_names, options.shards);
+    if let Some(start_key) = options.start_at_key.as_ref() {
+        // Sanity check, already validated in arg parser.
+        assert_eq!(tasks.len(), 1);
+        tasks[0].range.start = Some(start_key.clone());
+    }
+    let checkpoint = if options.resume {
+        let checkpoint = Checkpoint::load(
+            options
+                .checkpoint_path
+                .as_ref()
+                .expect("should have a checkpoint path when resuming"),
+        )?;
+        let mut remaining_tasks = vec![];
+        for mut task in tasks {
+            match checkpoint.remaining_range(&task)? {
+                Some(range) => {
+                    task.range = range;
+                    remaining_tasks.push(task);
+                }
+                None => info!(
+                    "Skipping {} which was fully checked by the previous run.",
+                    task.checkpoint_key
+                ),
             }
-            "proposers" => ProposerDatabase::check_db(&env, failfast, start_at)?,
-            "state_store" => StateStoreDatabase::check_db(&env, failfast, start_at)?,
-            "transfer" => TransferDatabase::check_db(&env, failfast, start_at)?,
-            "transfer_hashes" => TransferHashesDatabase::check_db(&env, failfast, start_at)?,
-            _ => return Err(Error::UnknownDb(db_name.to_string())),
         }
+        tasks = remaining_tasks;
+        checkpoint
     } else {
-        // Sanity check for `start_at`, already validated in arg parser.
-        assert_eq!(start_at, 0);
-        BlockBodyDatabase::check_db(&env, failfast, start_at)?;
-        BlockBodyMerkleDatabase::check_db(&env, failfast, start_at)?;
-        BlockHeaderDatabase::check_db(&env, failfast, start_at)?;
-        BlockMetadataDatabase::check_db(&env, failfast, start_at)?;
-        DeployHashesDatabase::check_db(&env, failfast, start_at)?;
-        DeployMetadataDatabase::check_db(&env, failfast, start_at)?;
-        DeployDatabase::check_db(&env, failfast, start_at)?;
-        FinalizedApprovalsDatabase::check_db(&env, failfast, start_at)?;
-        ProposerDatabase::check_db(&env, failfast, start_at)?;
-        StateStoreDatabase::check_db(&env, failfast, start_at)?;
-        TransferDatabase::check_db(&env, failfast, start_at)?;
-        TransferHashesDatabase::check_db(&env, failfast, start_at)?;
+        match options.checkpoint_path.as_ref() {
+            Some(checkpoint_path) => Checkpoint::new(checkpoint_path),
+            None => Checkpoint::in_memory(),
+        }
     };
+    let results = jobs::run_tasks(
+        env,
+        &tasks,
+        options.jobs,
+        options.failfast,
+        options.start_at,
+        &checkpoint,
+        schema,
+    );
+
+    let mut faulty_entries: BTreeMap<&str, Vec<(Vec<u8>, String)>> = BTreeMap::new();
+    for (task, maybe_result) in tasks.iter().zip(results) {
+        // Tasks which didn't run because of an earlier failure are skipped.
+        let result = match maybe_result {
+            Some(result) => result?,
+            None => continue,
+        };
+        let task_report = DatabaseReport::new(task.db_name, options.start_at, &result);
+        // Key ranges of the same database are consecutive tasks.
+        match report.databases.last_mut() {
+            Some(db_report) if db_report.db_name == task.db_name => db_report.merge(task_report),
+            _ => report.databases.push(task_report),
+        }
+        if options.repair.is_some() {
+            faulty_entries
+                .entry(task.db_name)
+                .or_default()
+                .extend(repair::faulty_entries(&result));
+        }
+        if let Err(db_err) = result {
+            errors.push(Error::Database(task.db_name.to_string(), db_err));
+        }
+    }
+    if let Some(repair_options) = options.repair.as_ref() {
+        let mut quarantined = 0;
+        for (db_name, entries) in faulty_entries {
+            quarantined += repair::quarantine_entries(
+                env,
+                db_name,
+                &entries,
+                &repair_options.quarantine_dir,
+                repair_options.dry_run,
+            )?;
+        }
+        if repair_options.dry_run {
+            info!(
+                "{} entries would be quarantined, run with \"--{}\" to remove them.",
+                quarantined, NO_DRY_RUN
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parses a random sample of the entries of each database and logs the
+/// estimated corruption rates.
+fn sample_dbs(
+    env: &Environment,
+    db_names: &[&str],
+    sample_size: SampleSize,
+    schema: SchemaSelection,
+    report: &mut CheckReport,
+    errors: &mut Vec<Error>,
+) -> Result<(), Error> {
+    for db_name in db_names {
+        let stats = match (DatabaseOps::by_name(db_name)?.sample)(env, sample_size, schema) {
+            Ok(stats) => stats,
+            Err(db_err) => {
+                let result = Err(db_err);
+                report
+                    .databases
+                    .push(DatabaseReport::new(db_name, 0, &result));
+                if let Err(db_err) = result {
+                    errors.push(Error::Database(db_name.to_string(), db_err));
+                }
+                continue;
+            }
+        };
+        if let Some(estimate) = stats.estimate() {
+            info!(
+                "Estimated corruption rate of {}: {:.4}% (95% confidence interval {:.4}% to \
+                {:.4}%) from {} samples of {} entries.",
+                db_name,
+                estimate.rate * 100.0,
+                estimate.lower_bound * 100.0,
+                estimate.upper_bound * 100.0,
+                stats.entries_sampled,
+                stats.entry_count
+            );
+        }
+        report
+            .databases
+            .push(DatabaseReport::from_sample(db_name, &stats));
+        if !stats.errors.is_empty() {
+            errors.push(Error::Database(
+                db_name.to_string(),
+                DbError::Accumulated(stats.entries_sampled, stats.errors),
+            ));
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,166 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Error as IoError,
+    path::{Path, PathBuf},
+    result::Result,
+    sync::Mutex,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::common::db::KeyRange;
+
+use super::{jobs::CheckTask, Error};
+
+/// Default name of the checkpoint file, created in the database directory.
+pub(crate) const CHECKPOINT_FILE_NAME: &str = "check_checkpoint.json";
+
+/// Progress of a single check task.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct TaskProgress {
+    /// Hex encoded key of the last entry checked.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) last_key: Option<String>,
+    /// Whether all the entries of the task were checked.
+    pub(crate) complete: bool,
+}
+
+/// Contents of the checkpoint file, the progress of each task keyed by
+/// `CheckTask::checkpoint_key`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct CheckpointState {
+    pub(crate) tasks: BTreeMap<String, TaskProgress>,
+}
+
+/// Records the progress of a `check` run to a file, so that an interrupted run
+/// can be resumed from the last key checked in each database.
+///
+/// Failing to write the file is logged but doesn't interrupt the check.
+pub(crate) struct Checkpoint {
+    /// Path of the checkpoint file, `None` if the progress is only kept in
+    /// memory.
+    path: Option<PathBuf>,
+    state: Mutex<CheckpointState>,
+}
+
+impl Checkpoint {
+    /// Creates an empty checkpoint, overwriting the file at `path` on the
+    /// first update.
+    pub(crate) fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: Some(path.as_ref().to_path_buf()),
+            state: Mutex::new(CheckpointState::default()),
+        }
+    }
+
+    /// Creates an empty checkpoint which is never written to a file.
+    pub(crate) fn in_memory() -> Self {
+        Self {
+            path: None,
+            state: Mutex::new(CheckpointState::default()),
+        }
+    }
+
+    /// Loads the checkpoint written by a previous run.
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let checkpoint_err =
+            |reason: String| Error::Checkpoint(path.as_ref().to_path_buf(), reason);
+        let file =
+            File::open(path.as_ref()).map_err(|io_err| checkpoint_err(io_err.to_string()))?;
+        let state: CheckpointState = serde_json::from_reader(file)
+            .map_err(|json_err| checkpoint_err(json_err.to_string()))?;
+        Ok(Self {
+            path: Some(path.as_ref().to_path_buf()),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Returns the key range left to check for the task, or `None` if the
+    /// task was completed by the previous run.
+    pub(crate) fn remaining_range(&self, task: &CheckTask) -> Result<Option<KeyRange>, Error> {
+        let state = self
+            .state
+            .lock()
+            .expect("checkpoint lock should not be poisoned");
+        match state.tasks.get(&task.checkpoint_key) {
+            None => Ok(Some(task.range.clone())),
+            Some(progress) if progress.complete => Ok(None),
+            Some(TaskProgress { last_key: None, .. }) => Ok(Some(task.range.clone())),
+            Some(TaskProgress {
+                last_key: Some(hex_key),
+                ..
+            }) => {
+                let last_key = hex::decode(hex_key).map_err(|hex_err| {
+                    Error::Checkpoint(
+                        self.path.clone().unwrap_or_default(),
+                        format!("invalid key for {}: {hex_err}", task.checkpoint_key),
+                    )
+                })?;
+                Ok(Some(task.range.after(&last_key)))
+            }
+        }
+    }
+
+    /// Records `raw_key` as the last key checked by the task.
+    pub(crate) fn record(&self, task: &CheckTask, raw_key: &[u8]) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("checkpoint lock should not be poisoned");
+        state
+            .tasks
+            .entry(task.checkpoint_key.clone())
+            .or_default()
+            .last_key = Some(hex::encode(raw_key));
+        self.save(&state);
+    }
+
+    /// Marks all the entries of the task as checked.
+    pub(crate) fn complete(&self, task: &CheckTask) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("checkpoint lock should not be poisoned");
+        state
+            .tasks
+            .entry(task.checkpoint_key.clone())
+            .or_default()
+            .complete = true;
+        self.save(&state);
+    }
+
+    /// Returns a copy of the recorded progress.
+    #[cfg(test)]
+    pub(crate) fn state(&self) -> CheckpointState {
+        self.state
+            .lock()
+            .expect("checkpoint lock should not be poisoned")
+            .clone()
+    }
+
+    fn save(&self, state: &CheckpointState) {
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(io_err) = write_file(path, state) {
+            warn!(
+                "Couldn't write checkpoint file {}: {}",
+                path.display(),
+                io_err
+            );
+        }
+    }
+}
+
+// Writes to a temporary file first, so that an interruption doesn't leave a
+// truncated checkpoint behind.
+fn write_file(path: &Path, state: &CheckpointState) -> Result<(), IoError> {
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+    let tmp_file = File::create(&tmp_path)?;
+    serde_json::to_writer_pretty(tmp_file, state)?;
+    fs::rename(tmp_path, path)
+}
@@ -0,0 +1,285 @@
+use std::{
+    fmt::{Display, Formatter, Result as FormatterResult},
+    result::Result,
+};
+
+use bincode::Error as BincodeError;
+use lmdb::{Cursor, Environment, Error as LmdbError, Transaction};
+use log::info;
+use thiserror::Error as ThisError;
+
+use casper_hashing::Digest;
+use casper_node::types::{BlockHash, BlockHeader, DeployHash, DeployMetadata};
+
+use crate::{
+    common::db::{
+        BlockBodyDatabase, BlockHeaderDatabase, Database, DeployDatabase, DeployMetadataDatabase,
+        TransferDatabase,
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+};
+
+const BLOCK_LOG_INTERVAL: usize = 10_000;
+
+/// Errors encountered when verifying the references between the databases in
+/// a storage environment.
+#[derive(Debug, ThisError)]
+pub enum IntegrityError {
+    /// Errors accumulated when checking the references with "--no-failfast".
+    Accumulated(Vec<Self>),
+    /// Database operation error.
+    Database(#[from] LmdbError),
+    /// The raw key of the element at the given index in a database is not a
+    /// block hash.
+    InvalidKey(String, usize),
+    /// A block header references a body which is missing from storage.
+    MissingBlockBody {
+        block_hash: BlockHash,
+        body_hash: Digest,
+    },
+    /// A block body references a deploy which is missing from storage.
+    MissingDeploy {
+        block_hash: BlockHash,
+        deploy_hash: DeployHash,
+    },
+    /// A deploy included in a block has no metadata in storage.
+    MissingDeployMetadata {
+        block_hash: BlockHash,
+        deploy_hash: DeployHash,
+    },
+    /// The metadata of a deploy has no execution result for a block which
+    /// includes it.
+    MissingExecutionResult {
+        block_hash: BlockHash,
+        deploy_hash: DeployHash,
+    },
+    /// An entry in the transfer database is keyed by a block which is missing
+    /// from storage.
+    MissingTransferBlock(BlockHash),
+    /// Parsing error for an element related to a block in a database.
+    Parsing(BlockHash, String, BincodeError),
+}
+
+impl Display for IntegrityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FormatterResult {
+        match self {
+            Self::Database(e) => write!(f, "Error operating the database: {e}"),
+            Self::InvalidKey(db_name, idx) => write!(
+                f,
+                "Raw key of element {idx} in {db_name} DB is not a block hash"
+            ),
+            Self::MissingBlockBody {
+                block_hash,
+                body_hash,
+            } => write!(f, "Block {block_hash} is missing its body {body_hash}"),
+            Self::MissingDeploy {
+                block_hash,
+                deploy_hash,
+            } => write!(f, "Block {block_hash} is missing deploy {deploy_hash}"),
+            Self::MissingDeployMetadata {
+                block_hash,
+                deploy_hash,
+            } => write!(
+                f,
+                "Deploy {deploy_hash} included in block {block_hash} has no metadata"
+            ),
+            Self::MissingExecutionResult {
+                block_hash,
+                deploy_hash,
+            } => write!(
+                f,
+                "Deploy {deploy_hash} has no execution result for block {block_hash}"
+            ),
+            Self::MissingTransferBlock(block_hash) => {
+                write!(f, "Transfers are stored for missing block {block_hash}")
+            }
+            Self::Parsing(block_hash, db_name, inner) => write!(
+                f,
+                "Error parsing element for block hash {block_hash} in {db_name} DB: {inner}"
+            ),
+            Self::Accumulated(accumulated_errors) => {
+                writeln!(f, "Errors caught:")?;
+                for error in accumulated_errors {
+                    writeln!(f, "{error}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Either returns the first inconsistency found or buffers all of them,
+/// depending on the failfast setting.
+struct ErrorSink {
+    failfast: bool,
+    errors: Vec<IntegrityError>,
+}
+
+impl ErrorSink {
+    fn new(failfast: bool) -> Self {
+        Self {
+            failfast,
+            errors: vec![],
+        }
+    }
+
+    fn record(&mut self, error: IntegrityError) -> Result<(), IntegrityError> {
+        if self.failfast {
+            return Err(error);
+        }
+        self.errors.push(error);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), IntegrityError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(IntegrityError::Accumulated(self.errors))
+        }
+    }
+}
+
+/// Walks the block header database and verifies that all the data referenced
+/// by each block is present in storage:
+///   - the block body referenced by the header's body hash;
+///   - every deploy and transfer in the body, in the deploys database;
+///   - the metadata of every such deploy, holding an execution result for
+///     the block.
+///
+/// Additionally, every entry in the transfer database must be keyed by the
+/// hash of a block present in storage.
+pub(crate) fn check_integrity(env: &Environment, failfast: bool) -> Result<(), IntegrityError> {
+    info!("Checking references between databases.");
+    let txn = env.begin_ro_txn()?;
+    let block_header_db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name()))? };
+    let block_body_db = unsafe { txn.open_db(Some(BlockBodyDatabase::db_name()))? };
+    let deploys_db = unsafe { txn.open_db(Some(DeployDatabase::db_name()))? };
+    let deploy_metadata_db = unsafe { txn.open_db(Some(DeployMetadataDatabase::db_name()))? };
+    let transfer_db = unsafe { txn.open_db(Some(TransferDatabase::db_name()))? };
+
+    let mut sink = ErrorSink::new(failfast);
+
+    if let Ok(mut cursor) = txn.open_ro_cursor(block_header_db) {
+        for (idx, (block_hash_raw, raw_val)) in cursor.iter().enumerate() {
+            if idx % BLOCK_LOG_INTERVAL == 0 {
+                info!("Checked references of {} blocks...", idx);
+            }
+            let block_hash = match Digest::try_from(block_hash_raw) {
+                Ok(digest) => BlockHash::new(digest),
+                Err(_) => {
+                    sink.record(IntegrityError::InvalidKey(
+                        BlockHeaderDatabase::db_name().to_string(),
+                        idx,
+                    ))?;
+                    continue;
+                }
+            };
+            let header: BlockHeader = match bincode::deserialize(raw_val) {
+                Ok(header) => header,
+                Err(bincode_err) => {
+                    sink.record(IntegrityError::Parsing(
+                        block_hash,
+                        BlockHeaderDatabase::db_name().to_string(),
+                        bincode_err,
+                    ))?;
+                    continue;
+                }
+            };
+
+            let block_body_raw = match txn.get(block_body_db, header.body_hash()) {
+                Ok(raw) => raw,
+                Err(LmdbError::NotFound) => {
+                    sink.record(IntegrityError::MissingBlockBody {
+                        block_hash,
+                        body_hash: *header.body_hash(),
+                    })?;
+                    continue;
+                }
+                Err(lmdb_err) => return Err(lmdb_err.into()),
+            };
+            let block_body: BlockBody = match bincode::deserialize(block_body_raw) {
+                Ok(body) => body,
+                Err(bincode_err) => {
+                    sink.record(IntegrityError::Parsing(
+                        block_hash,
+                        BlockBodyDatabase::db_name().to_string(),
+                        bincode_err,
+                    ))?;
+                    continue;
+                }
+            };
+
+            // Transfers are deploys too, so both lists must be present in the
+            // deploys database and have execution results for this block.
+            for deploy_hash in block_body
+                .deploy_hashes()
+                .iter()
+                .chain(block_body.transfer_hashes())
+            {
+                match txn.get(deploys_db, deploy_hash) {
+                    Ok(_) => {}
+                    Err(LmdbError::NotFound) => sink.record(IntegrityError::MissingDeploy {
+                        block_hash,
+                        deploy_hash: *deploy_hash,
+                    })?,
+                    Err(lmdb_err) => return Err(lmdb_err.into()),
+                }
+
+                let metadata_raw = match txn.get(deploy_metadata_db, deploy_hash) {
+                    Ok(raw) => raw,
+                    Err(LmdbError::NotFound) => {
+                        sink.record(IntegrityError::MissingDeployMetadata {
+                            block_hash,
+                            deploy_hash: *deploy_hash,
+                        })?;
+                        continue;
+                    }
+                    Err(lmdb_err) => return Err(lmdb_err.into()),
+                };
+                let metadata: DeployMetadata = match bincode::deserialize(metadata_raw) {
+                    Ok(metadata) => metadata,
+                    Err(bincode_err) => {
+                        sink.record(IntegrityError::Parsing(
+                            block_hash,
+                            DeployMetadataDatabase::db_name().to_string(),
+                            bincode_err,
+                        ))?;
+                        continue;
+                    }
+                };
+                if !metadata.execution_results.contains_key(&block_hash) {
+                    sink.record(IntegrityError::MissingExecutionResult {
+                        block_hash,
+                        deploy_hash: *deploy_hash,
+                    })?;
+                }
+            }
+        }
+    }
+
+    if let Ok(mut cursor) = txn.open_ro_cursor(transfer_db) {
+        for (idx, (block_hash_raw, _raw_val)) in cursor.iter().enumerate() {
+            let block_hash = match Digest::try_from(block_hash_raw) {
+                Ok(digest) => BlockHash::new(digest),
+                Err(_) => {
+                    sink.record(IntegrityError::InvalidKey(
+                        TransferDatabase::db_name().to_string(),
+                        idx,
+                    ))?;
+                    continue;
+                }
+            };
+            match txn.get(block_header_db, &block_hash) {
+                Ok(_) => {}
+                Err(LmdbError::NotFound) => {
+                    sink.record(IntegrityError::MissingTransferBlock(block_hash))?
+                }
+                Err(lmdb_err) => return Err(lmdb_err.into()),
+            }
+        }
+    }
+
+    info!("Reference check complete.");
+    sink.finish()
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::BTreeSet,
+    result::Result,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use lmdb::Environment;
+use log::info;
+
+use crate::common::{
+    db::{Error as DbError, KeyRange},
+    schema::SchemaSelection,
+};
+
+use super::{check_named_db, checkpoint::Checkpoint, Error};
+
+/// Result of a task which was run, `None` for tasks skipped after a failure
+/// with failfast.
+pub(crate) type TaskResult = Option<Result<Result<usize, DbError>, Error>>;
+
+/// A database, or a key range of it, to be checked by a worker.
+pub(crate) struct CheckTask<'a> {
+    pub(crate) db_name: &'a str,
+    pub(crate) range: KeyRange,
+    /// Identifies the task in the checkpoint file. Derived from the original
+    /// range, so it stays the same when the task is resumed.
+    pub(crate) checkpoint_key: String,
+}
+
+impl<'a> CheckTask<'a> {
+    /// Creates the tasks which check every database in `db_names`, each one
+    /// split into `shards` key ranges.
+    pub(crate) fn split(db_names: &[&'a str], shards: usize) -> Vec<Self> {
+        let ranges = KeyRange::shards(shards);
+        db_names
+            .iter()
+            .flat_map(|&db_name| {
+                ranges
+                    .iter()
+                    .map(move |range| Self::new(db_name, range.clone()))
+            })
+            .collect()
+    }
+
+    pub(crate) fn new(db_name: &'a str, range: KeyRange) -> Self {
+        let checkpoint_key = if range.is_full() {
+            db_name.to_string()
+        } else {
+            format!("{db_name}{range}")
+        };
+        Self {
+            db_name,
+            range,
+            checkpoint_key,
+        }
+    }
+}
+
+/// Runs the check tasks on `jobs` worker threads, each with its own read
+/// transaction, and returns the results in the order of the tasks. The
+/// progress of each task is recorded in `checkpoint`, and entries are decoded
+/// with the layouts selected by `schema`.
+pub(crate) fn run_tasks(
+    env: &Environment,
+    tasks: &[CheckTask],
+    jobs: usize,
+    failfast: bool,
+    start_at: usize,
+    checkpoint: &Checkpoint,
+    schema: SchemaSelection,
+) -> Vec<TaskResult> {
+    // `mdb_dbi_open` must not be called concurrently for a database which
+    // isn't open yet, so open all the handles before spawning the workers.
+    // Failures are reported by the tasks themselves.
+    let db_names: BTreeSet<&str> = tasks.iter().map(|task| task.db_name).collect();
+    for db_name in db_names {
+        let _ = env.open_db(Some(db_name));
+    }
+
+    let jobs = jobs.clamp(1, tasks.len().max(1));
+    if jobs > 1 {
+        info!("Checking {} tasks on {} threads.", tasks.len(), jobs);
+    }
+    let next_task = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let results: Mutex<Vec<TaskResult>> = Mutex::new((0..tasks.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let task_idx = next_task.fetch_add(1, Ordering::SeqCst);
+                let task = match tasks.get(task_idx) {
+                    Some(task) => task,
+                    None => break,
+                };
+                let result = check_named_db(
+                    env,
+                    task.db_name,
+                    failfast,
+                    start_at,
+                    &task.range,
+                    schema,
+                    &mut |raw_key| checkpoint.record(task, raw_key),
+                );
+                // With "--no-failfast", parsing errors don't stop the check of
+                // the remaining entries.
+                let complete = matches!(result, Ok(Ok(_)) | Ok(Err(DbError::Accumulated(_, _))));
+                if complete {
+                    checkpoint.complete(task);
+                }
+                let failed = match &result {
+                    Ok(check_result) => failfast && check_result.is_err(),
+                    Err(_) => true,
+                };
+                if failed {
+                    stop.store(true, Ordering::SeqCst);
+                }
+                results.lock().expect("results lock should not be poisoned")[task_idx] =
+                    Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .expect("results lock should not be poisoned")
+}
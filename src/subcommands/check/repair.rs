@@ -0,0 +1,133 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufWriter, Error as IoError, Write},
+    path::Path,
+    result::Result,
+};
+
+use lmdb::{Environment, Error as LmdbError, Transaction};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Error as JsonSerializationError;
+use thiserror::Error as ThisError;
+
+use crate::common::db::Error as DbError;
+
+/// Name of the JSON-lines file holding the quarantined entries, created in
+/// the quarantine directory.
+pub(crate) const QUARANTINE_FILE_NAME: &str = "quarantine.jsonl";
+
+#[derive(Debug, ThisError)]
+pub enum RepairError {
+    #[error("Error operating the {0} database: {1}")]
+    Database(String, LmdbError),
+    #[error("Error writing quarantine file: {0}")]
+    Output(#[from] IoError),
+    #[error("Error serializing quarantined entry: {0}")]
+    Serialize(#[from] JsonSerializationError),
+}
+
+/// An entry moved out of a database because it failed to parse.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct QuarantinedEntry {
+    /// Name of the database the entry was removed from.
+    pub(crate) db_name: String,
+    /// Hex encoded raw key of the entry.
+    pub(crate) raw_key: String,
+    /// Hex encoded raw value of the entry.
+    pub(crate) raw_value: String,
+    /// Reason the entry failed to parse.
+    pub(crate) error: String,
+}
+
+/// Collects the raw keys of the entries which failed to parse, along with the
+/// reason, from the result of `Database::check_db`.
+pub(crate) fn faulty_entries(result: &Result<usize, DbError>) -> Vec<(Vec<u8>, String)> {
+    let errors: Vec<&DbError> = match result {
+        Ok(_) => vec![],
+        Err(DbError::Accumulated(_, errors)) => errors.iter().collect(),
+        Err(error) => vec![error],
+    };
+    errors
+        .into_iter()
+        .filter_map(|error| match error {
+            DbError::Parsing(_, raw_key, parsing_err) => {
+                Some((raw_key.clone(), parsing_err.to_string()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Moves the faulty entries of a database to the quarantine file in
+/// `quarantine_dir` and deletes them from the database, in a single write
+/// transaction. The quarantine file is synced to disk before the deletions are
+/// committed.
+///
+/// With `dry_run`, the entries are only logged and nothing is written.
+/// Returns the number of entries quarantined, or which would be quarantined.
+pub(crate) fn quarantine_entries<P: AsRef<Path>>(
+    env: &Environment,
+    db_name: &str,
+    faulty_entries: &[(Vec<u8>, String)],
+    quarantine_dir: P,
+    dry_run: bool,
+) -> Result<usize, RepairError> {
+    if faulty_entries.is_empty() {
+        return Ok(0);
+    }
+    let db_err = |lmdb_err| RepairError::Database(db_name.to_string(), lmdb_err);
+
+    if dry_run {
+        for (raw_key, error) in faulty_entries {
+            info!(
+                "Would quarantine entry {} of {}: {}",
+                hex::encode(raw_key),
+                db_name,
+                error
+            );
+        }
+        return Ok(faulty_entries.len());
+    }
+
+    fs::create_dir_all(quarantine_dir.as_ref())?;
+    let quarantine_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(quarantine_dir.as_ref().join(QUARANTINE_FILE_NAME))?;
+    let mut writer = BufWriter::new(quarantine_file);
+
+    let db = env.open_db(Some(db_name)).map_err(db_err)?;
+    let mut txn = env.begin_rw_txn().map_err(db_err)?;
+    let mut quarantined = 0;
+    for (raw_key, error) in faulty_entries {
+        let raw_value = match txn.get(db, raw_key) {
+            Ok(raw_value) => raw_value.to_vec(),
+            // Already removed, e.g. by an earlier repair.
+            Err(LmdbError::NotFound) => continue,
+            Err(lmdb_err) => return Err(db_err(lmdb_err)),
+        };
+        let entry = QuarantinedEntry {
+            db_name: db_name.to_string(),
+            raw_key: hex::encode(raw_key),
+            raw_value: hex::encode(&raw_value),
+            error: error.clone(),
+        };
+        serde_json::to_writer(&mut writer, &entry)?;
+        writeln!(writer)?;
+        txn.del(db, raw_key, None).map_err(db_err)?;
+        quarantined += 1;
+    }
+    // Make sure the entries are safely stored before deleting them.
+    let quarantine_file = writer.into_inner().map_err(|err| err.into_error())?;
+    quarantine_file.sync_all()?;
+    txn.commit().map_err(db_err)?;
+
+    info!(
+        "Quarantined {} entries of {} to {}.",
+        quarantined,
+        db_name,
+        quarantine_dir.as_ref().display()
+    );
+    Ok(quarantined)
+}
@@ -0,0 +1,142 @@
+use std::{io::Write, result::Result};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Error as JsonSerializationError;
+
+use crate::common::db::{CorruptionEstimate, DeserializationError, Error as DbError, SampleStats};
+
+use super::structure::StructureReport;
+
+/// Description of an entry which failed to parse.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct EntryFailure {
+    /// Index of the entry in the database.
+    pub(crate) index: usize,
+    /// Hex encoded raw key of the entry.
+    pub(crate) raw_key: String,
+    /// Name of the `DeserializationError` variant.
+    pub(crate) error_kind: String,
+    /// Message of the underlying deserialization error.
+    pub(crate) message: String,
+}
+
+impl EntryFailure {
+    fn new(index: usize, raw_key: &[u8], error: &DeserializationError) -> Self {
+        let (error_kind, message) = match error {
+            DeserializationError::BincodeError(bincode_err) => {
+                ("BincodeError", bincode_err.to_string())
+            }
+            DeserializationError::BytesreprError(bytesrepr_err) => {
+                ("BytesreprError", bytesrepr_err.clone())
+            }
+            DeserializationError::KeyLength(_, _) => ("KeyLength", error.to_string()),
+            DeserializationError::KeyMismatch(_) => ("KeyMismatch", error.to_string()),
+        };
+        Self {
+            index,
+            raw_key: hex::encode(raw_key),
+            error_kind: error_kind.to_string(),
+            message,
+        }
+    }
+}
+
+/// Outcome of checking a single database.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct DatabaseReport {
+    /// Name of the database.
+    pub(crate) db_name: String,
+    /// Number of entries parsed in the database.
+    pub(crate) entries_scanned: usize,
+    /// Entries which failed to parse.
+    pub(crate) failures: Vec<EntryFailure>,
+    /// Error which prevented the check from completing, if any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) error: Option<String>,
+    /// Estimated corruption rate, when only a sample of the entries was
+    /// parsed.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) estimate: Option<CorruptionEstimate>,
+}
+
+impl DatabaseReport {
+    /// Builds the report of a database from the result of `Database::check_db`.
+    /// `start_at` is the index of the first entry which was parsed.
+    pub(crate) fn new(db_name: &str, start_at: usize, result: &Result<usize, DbError>) -> Self {
+        let mut report = Self {
+            db_name: db_name.to_string(),
+            entries_scanned: 0,
+            failures: vec![],
+            error: None,
+            estimate: None,
+        };
+        match result {
+            Ok(entries_parsed) => report.entries_scanned = *entries_parsed,
+            Err(DbError::Accumulated(entries_parsed, errors)) => {
+                report.entries_scanned = *entries_parsed;
+                for error in errors {
+                    report.add_error(error);
+                }
+            }
+            Err(parsing_err @ DbError::Parsing(idx, _, _)) => {
+                // With failfast, parsing stops at the first faulty entry.
+                report.entries_scanned = idx + 1 - start_at;
+                report.add_error(parsing_err);
+            }
+            Err(db_err @ DbError::Database(_)) => report.error = Some(db_err.to_string()),
+        }
+        report
+    }
+
+    /// Builds the report of a database from the outcome of
+    /// `Database::sample_db`. Failure indices are sample numbers.
+    pub(crate) fn from_sample(db_name: &str, stats: &SampleStats) -> Self {
+        let mut report = Self {
+            db_name: db_name.to_string(),
+            entries_scanned: stats.entries_sampled,
+            failures: vec![],
+            error: None,
+            estimate: stats.estimate(),
+        };
+        for error in &stats.errors {
+            report.add_error(error);
+        }
+        report
+    }
+
+    /// Merges the report of another key range of the same database into this
+    /// one.
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.entries_scanned += other.entries_scanned;
+        self.failures.extend(other.failures);
+        if self.error.is_none() {
+            self.error = other.error;
+        }
+    }
+
+    fn add_error(&mut self, error: &DbError) {
+        match error {
+            DbError::Parsing(idx, raw_key, parsing_err) => {
+                self.failures
+                    .push(EntryFailure::new(*idx, raw_key, parsing_err))
+            }
+            other => self.error = Some(other.to_string()),
+        }
+    }
+}
+
+/// Report of a `check` run over one or more databases.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct CheckReport {
+    pub(crate) databases: Vec<DatabaseReport>,
+    /// Outcome of the LMDB page structure verification, if it was run.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) structure: Option<StructureReport>,
+}
+
+pub(crate) fn dump_report<W: Write + ?Sized>(
+    report: &CheckReport,
+    out_writer: Box<W>,
+) -> Result<(), JsonSerializationError> {
+    serde_json::to_writer_pretty(out_writer, report)
+}
@@ -0,0 +1,687 @@
+// The page walk is only compiled for the targets whose layout it reads.
+#![cfg_attr(
+    not(all(target_endian = "little", target_pointer_width = "64")),
+    allow(dead_code, unused_imports)
+)]
+
+use std::{
+    fs::File,
+    io::{Error as IoError, Read, Seek, SeekFrom},
+    path::Path,
+    result::Result,
+};
+
+use lmdb::{Environment, Error as LmdbError, Transaction};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+use crate::common::lmdb_utils;
+
+/// Name under which the unnamed database, holding the records of the named
+/// ones, is reported.
+pub(crate) const MAIN_DB_NAME: &str = "(main)";
+/// Name under which the free list database is reported.
+pub(crate) const FREE_DB_NAME: &str = "(free)";
+/// Maximum number of problems recorded for a single database, the others
+/// are only counted.
+const MAX_PROBLEMS_PER_DB: usize = 1_000;
+
+// Layout of the LMDB data file, see `mdb.c` in the LMDB sources. Integers are
+// stored in native byte order and page numbers are as wide as pointers, so the
+// layout below only holds on little-endian 64-bit targets.
+
+const PAGE_HEADER_SIZE: usize = 16;
+const NODE_HEADER_SIZE: usize = 8;
+const DB_RECORD_SIZE: usize = 48;
+const META_MAGIC: u32 = 0xBEEF_C0DE;
+const META_MAGIC_OFFSET: usize = PAGE_HEADER_SIZE;
+const META_FREE_DB_OFFSET: usize = PAGE_HEADER_SIZE + 24;
+const META_MAIN_DB_OFFSET: usize = META_FREE_DB_OFFSET + DB_RECORD_SIZE;
+const META_TXNID_OFFSET: usize = META_MAIN_DB_OFFSET + DB_RECORD_SIZE + 8;
+const META_PAGE_COUNT: u64 = 2;
+const INVALID_PAGE: u64 = u64::MAX;
+
+const P_BRANCH: u16 = 0x01;
+const P_LEAF: u16 = 0x02;
+const P_OVERFLOW: u16 = 0x04;
+const P_META: u16 = 0x08;
+const P_LEAF2: u16 = 0x20;
+
+const F_BIGDATA: u16 = 0x01;
+const F_SUBDATA: u16 = 0x02;
+
+const MDB_REVERSEKEY: u16 = 0x02;
+const MDB_DUPSORT: u16 = 0x04;
+const MDB_INTEGERKEY: u16 = 0x08;
+
+#[derive(Debug, ThisError)]
+pub enum StructureError {
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+    #[error("Error reading the data file: {0}")]
+    Read(#[from] IoError),
+    #[cfg(not(all(target_endian = "little", target_pointer_width = "64")))]
+    #[error("The LMDB page structure can only be verified on little-endian 64-bit targets")]
+    UnsupportedTarget,
+}
+
+/// Outcome of walking the page tree of a single database.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct TreeReport {
+    /// Name of the database.
+    pub(crate) db_name: String,
+    /// Number of the root page, `None` for an empty database.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) root_page: Option<u64>,
+    /// Depth of the tree reached by the walk.
+    pub(crate) depth: u16,
+    pub(crate) branch_pages: u64,
+    pub(crate) leaf_pages: u64,
+    pub(crate) overflow_pages: u64,
+    pub(crate) entries: u64,
+    /// Inconsistencies found in the pages of the database.
+    pub(crate) problems: Vec<String>,
+    /// Number of problems found past `MAX_PROBLEMS_PER_DB`.
+    pub(crate) omitted_problems: usize,
+}
+
+impl TreeReport {
+    fn add_problem(&mut self, problem: String) {
+        if self.problems.len() < MAX_PROBLEMS_PER_DB {
+            warn!("{}: {}", self.db_name, problem);
+            self.problems.push(problem);
+        } else {
+            self.omitted_problems += 1;
+        }
+    }
+
+    fn problem_count(&self) -> usize {
+        self.problems.len() + self.omitted_problems
+    }
+}
+
+/// Outcome of verifying the page structure of an LMDB environment.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct StructureReport {
+    pub(crate) page_size: usize,
+    /// Number of the last page in use in the data file.
+    pub(crate) last_page: u64,
+    /// Reports of the main database, each named database and the free list.
+    pub(crate) databases: Vec<TreeReport>,
+    /// Number of pages listed in the free list.
+    pub(crate) free_pages: usize,
+    /// Number of pages neither reachable from a database nor listed as free.
+    pub(crate) unaccounted_pages: u64,
+    /// Problems found in the meta pages.
+    pub(crate) problems: Vec<String>,
+}
+
+impl StructureReport {
+    /// Returns the total number of problems found.
+    pub(crate) fn problem_count(&self) -> usize {
+        self.problems.len()
+            + self
+                .databases
+                .iter()
+                .map(TreeReport::problem_count)
+                .sum::<usize>()
+    }
+}
+
+/// Record of a database as stored in the meta pages and the main database.
+#[derive(Clone, Copy, Debug)]
+struct DbRecord {
+    flags: u16,
+    depth: u16,
+    branch_pages: u64,
+    leaf_pages: u64,
+    overflow_pages: u64,
+    entries: u64,
+    root: u64,
+}
+
+impl DbRecord {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < DB_RECORD_SIZE {
+            return None;
+        }
+        Some(Self {
+            flags: read_u16(bytes, 4),
+            depth: read_u16(bytes, 6),
+            branch_pages: read_u64(bytes, 8),
+            leaf_pages: read_u64(bytes, 16),
+            overflow_pages: read_u64(bytes, 24),
+            entries: read_u64(bytes, 32),
+            root: read_u64(bytes, 40),
+        })
+    }
+}
+
+/// Roots of the free list and the main database of the latest transaction.
+struct Meta {
+    free_db: DbRecord,
+    main_db: DbRecord,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// Set of page numbers up to the last page of the data file, with one bit per
+/// page.
+struct PageSet {
+    bits: Vec<u64>,
+    len: u64,
+}
+
+impl PageSet {
+    fn new(last_page: u64) -> Self {
+        Self {
+            bits: vec![0; (last_page / 64 + 1) as usize],
+            len: 0,
+        }
+    }
+
+    /// Adds a page to the set, returns whether it wasn't in the set already.
+    fn insert(&mut self, page_number: u64) -> bool {
+        let word = &mut self.bits[(page_number / 64) as usize];
+        let mask = 1 << (page_number % 64);
+        if *word & mask != 0 {
+            return false;
+        }
+        *word |= mask;
+        self.len += 1;
+        true
+    }
+
+    fn contains(&self, page_number: u64) -> bool {
+        self.bits[(page_number / 64) as usize] & (1 << (page_number % 64)) != 0
+    }
+}
+
+/// State of the walk of a single tree.
+struct TreeWalk {
+    record: DbRecord,
+    /// Whether keys are expected in lexicographic order.
+    check_order: bool,
+    /// Whether the values of the leaf nodes are kept, with their node flags.
+    collect_values: bool,
+    values: Vec<(Vec<u8>, u16, Vec<u8>)>,
+    last_key: Option<Vec<u8>>,
+    report: TreeReport,
+}
+
+struct Walker {
+    file: File,
+    page_size: usize,
+    last_page: u64,
+    /// Pages reached from any tree so far, to detect pages shared between
+    /// trees or referenced twice.
+    visited: PageSet,
+}
+
+impl Walker {
+    fn read_pages(&mut self, page_number: u64, count: usize) -> Result<Vec<u8>, IoError> {
+        let mut buf = vec![0u8; self.page_size * count];
+        self.file
+            .seek(SeekFrom::Start(page_number * self.page_size as u64))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads both meta pages and returns the one of the latest transaction,
+    /// or `None` if neither is valid.
+    fn read_meta(&mut self, problems: &mut Vec<String>) -> Result<Option<Meta>, IoError> {
+        let mut latest: Option<(u64, Meta)> = None;
+        for page_number in 0..META_PAGE_COUNT {
+            let page = self.read_pages(page_number, 1)?;
+            let flags = read_u16(&page, 10);
+            let magic = read_u32(&page, META_MAGIC_OFFSET);
+            if flags & P_META == 0 || magic != META_MAGIC {
+                problems.push(format!(
+                    "meta page {page_number} has flags {flags:#06x} and magic {magic:#010x}"
+                ));
+                continue;
+            }
+            let txnid = read_u64(&page, META_TXNID_OFFSET);
+            let meta = Meta {
+                free_db: DbRecord::parse(&page[META_FREE_DB_OFFSET..])
+                    .expect("meta page should hold the free list record"),
+                main_db: DbRecord::parse(&page[META_MAIN_DB_OFFSET..])
+                    .expect("meta page should hold the main database record"),
+            };
+            if latest
+                .as_ref()
+                .map_or(true, |(latest_txnid, _)| txnid > *latest_txnid)
+            {
+                latest = Some((txnid, meta));
+            }
+        }
+        Ok(latest.map(|(_, meta)| meta))
+    }
+
+    fn walk_tree(
+        &mut self,
+        db_name: &str,
+        record: DbRecord,
+        collect_values: bool,
+    ) -> Result<TreeWalk, IoError> {
+        let mut tree = TreeWalk {
+            record,
+            check_order: record.flags & (MDB_REVERSEKEY | MDB_INTEGERKEY) == 0,
+            collect_values,
+            values: vec![],
+            last_key: None,
+            report: TreeReport {
+                db_name: db_name.to_string(),
+                ..Default::default()
+            },
+        };
+        if record.root == INVALID_PAGE {
+            if record.entries != 0 {
+                tree.report.add_problem(format!(
+                    "no root page but {} entries recorded",
+                    record.entries
+                ));
+            }
+            return Ok(tree);
+        }
+        tree.report.root_page = Some(record.root);
+        // Children are walked depth first and in key order, so that the keys
+        // of consecutive leaves can be compared.
+        let mut pages = vec![(record.root, 1)];
+        while let Some((page_number, depth)) = pages.pop() {
+            let children = self.walk_page(&mut tree, page_number, depth)?;
+            pages.extend(children.into_iter().rev());
+        }
+
+        // Duplicates live in sub-trees which aren't walked, so the counts of
+        // such databases can't be compared.
+        if record.flags & MDB_DUPSORT != 0 {
+            return Ok(tree);
+        }
+        let counts = [
+            ("depth", tree.report.depth as u64, record.depth as u64),
+            (
+                "branch pages",
+                tree.report.branch_pages,
+                record.branch_pages,
+            ),
+            ("leaf pages", tree.report.leaf_pages, record.leaf_pages),
+            (
+                "overflow pages",
+                tree.report.overflow_pages,
+                record.overflow_pages,
+            ),
+            ("entries", tree.report.entries, record.entries),
+        ];
+        for (what, walked, recorded) in counts {
+            if walked != recorded {
+                tree.report.add_problem(format!(
+                    "walked {walked} {what} but {recorded} are recorded"
+                ));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Checks a page of a tree and returns the child pages of a branch page,
+    /// along with their depth.
+    fn walk_page(
+        &mut self,
+        tree: &mut TreeWalk,
+        page_number: u64,
+        depth: u16,
+    ) -> Result<Vec<(u64, u16)>, IoError> {
+        if page_number > self.last_page {
+            tree.report.add_problem(format!(
+                "page {page_number} is past the last page {}",
+                self.last_page
+            ));
+            return Ok(vec![]);
+        }
+        if !self.visited.insert(page_number) {
+            tree.report
+                .add_problem(format!("page {page_number} is referenced more than once"));
+            return Ok(vec![]);
+        }
+        let page = self.read_pages(page_number, 1)?;
+        let header_page_number = read_u64(&page, 0);
+        if header_page_number != page_number {
+            tree.report.add_problem(format!(
+                "page {page_number} has page number {header_page_number} in its header"
+            ));
+        }
+        let flags = read_u16(&page, 10);
+        let is_branch = flags & P_BRANCH != 0;
+        let is_leaf = flags & P_LEAF != 0;
+        if is_branch == is_leaf || flags & (P_OVERFLOW | P_META) != 0 {
+            tree.report
+                .add_problem(format!("page {page_number} has invalid flags {flags:#06x}"));
+            return Ok(vec![]);
+        }
+        tree.report.depth = tree.report.depth.max(depth);
+        if is_branch {
+            tree.report.branch_pages += 1;
+        } else {
+            tree.report.leaf_pages += 1;
+        }
+        if is_leaf != (depth == tree.record.depth) {
+            tree.report.add_problem(format!(
+                "{} page {page_number} is at depth {depth} of a tree of depth {}",
+                if is_leaf { "leaf" } else { "branch" },
+                tree.record.depth
+            ));
+        }
+        // Fixed size duplicates, only found in the sub-trees of duplicates.
+        if flags & P_LEAF2 != 0 {
+            return Ok(vec![]);
+        }
+
+        let lower = read_u16(&page, 12) as usize;
+        let upper = read_u16(&page, 14) as usize;
+        if lower < PAGE_HEADER_SIZE
+            || lower > upper
+            || upper > self.page_size
+            || (lower - PAGE_HEADER_SIZE) % 2 != 0
+        {
+            tree.report.add_problem(format!(
+                "page {page_number} has invalid free space bounds {lower}..{upper}"
+            ));
+            return Ok(vec![]);
+        }
+        let node_count = (lower - PAGE_HEADER_SIZE) / 2;
+        if node_count == 0 {
+            tree.report
+                .add_problem(format!("page {page_number} has no nodes"));
+            return Ok(vec![]);
+        }
+
+        let mut children = vec![];
+        let mut previous_key: Option<&[u8]> = None;
+        for index in 0..node_count {
+            let offset = read_u16(&page, PAGE_HEADER_SIZE + 2 * index) as usize;
+            if offset < upper || offset + NODE_HEADER_SIZE > self.page_size {
+                tree.report.add_problem(format!(
+                    "node {index} of page {page_number} has invalid offset {offset}"
+                ));
+                continue;
+            }
+            let low = read_u16(&page, offset) as u64;
+            let high = read_u16(&page, offset + 2) as u64;
+            let node_flags = read_u16(&page, offset + 4);
+            let key_size = read_u16(&page, offset + 6) as usize;
+            let key_start = offset + NODE_HEADER_SIZE;
+            let key_end = key_start + key_size;
+            if key_end > self.page_size {
+                tree.report.add_problem(format!(
+                    "key of node {index} of page {page_number} overruns the page"
+                ));
+                continue;
+            }
+            let key = &page[key_start..key_end];
+
+            if is_branch {
+                // The key of the first node of a branch page is implicitly
+                // lower than all the others and isn't stored.
+                if index > 0 && tree.check_order {
+                    if previous_key.map_or(false, |previous| key <= previous) {
+                        tree.report.add_problem(format!(
+                            "keys of branch page {page_number} are out of order at node {index}"
+                        ));
+                    }
+                    previous_key = Some(key);
+                }
+                let child = low | high << 16 | (node_flags as u64) << 32;
+                children.push((child, depth + 1));
+                continue;
+            }
+
+            tree.report.entries += 1;
+            if tree.check_order {
+                if let Some(last_key) = tree.last_key.as_ref() {
+                    if key <= last_key.as_slice() {
+                        tree.report.add_problem(format!(
+                            "key {} of page {page_number} isn't greater than the previous key {}",
+                            hex::encode(key),
+                            hex::encode(last_key)
+                        ));
+                    }
+                }
+                tree.last_key = Some(key.to_vec());
+            }
+            let data_size = (low | high << 16) as usize;
+            let value = if node_flags & F_BIGDATA != 0 {
+                if key_end + 8 > self.page_size {
+                    tree.report.add_problem(format!(
+                        "overflow page number of node {index} of page {page_number} overruns \
+                        the page"
+                    ));
+                    continue;
+                }
+                let overflow_page = read_u64(&page, key_end);
+                match self.walk_overflow(tree, overflow_page, data_size)? {
+                    Some(value) => value,
+                    None => continue,
+                }
+            } else {
+                if key_end + data_size > self.page_size {
+                    tree.report.add_problem(format!(
+                        "value of node {index} of page {page_number} overruns the page"
+                    ));
+                    continue;
+                }
+                page[key_end..key_end + data_size].to_vec()
+            };
+            if tree.collect_values {
+                tree.values.push((key.to_vec(), node_flags, value));
+            }
+        }
+        Ok(children)
+    }
+
+    /// Checks the run of overflow pages holding a value of `data_size` bytes.
+    /// Returns the value if it's collected, an empty one if not, and `None`
+    /// if the run is invalid.
+    fn walk_overflow(
+        &mut self,
+        tree: &mut TreeWalk,
+        page_number: u64,
+        data_size: usize,
+    ) -> Result<Option<Vec<u8>>, IoError> {
+        if page_number > self.last_page {
+            tree.report.add_problem(format!(
+                "overflow page {page_number} is past the last page {}",
+                self.last_page
+            ));
+            return Ok(None);
+        }
+        let header = self.read_pages(page_number, 1)?;
+        let header_page_number = read_u64(&header, 0);
+        if header_page_number != page_number {
+            tree.report.add_problem(format!(
+                "overflow page {page_number} has page number {header_page_number} in its header"
+            ));
+        }
+        let flags = read_u16(&header, 10);
+        if flags & P_OVERFLOW == 0 {
+            tree.report.add_problem(format!(
+                "page {page_number} should be an overflow page but has flags {flags:#06x}"
+            ));
+            return Ok(None);
+        }
+        let page_count = read_u32(&header, 12) as u64;
+        if page_count == 0 || page_number + page_count - 1 > self.last_page {
+            tree.report.add_problem(format!(
+                "overflow run of {page_count} pages at page {page_number} is past the last page \
+                {}",
+                self.last_page
+            ));
+            return Ok(None);
+        }
+        for overflow_page in page_number..page_number + page_count {
+            if !self.visited.insert(overflow_page) {
+                tree.report
+                    .add_problem(format!("page {overflow_page} is referenced more than once"));
+            }
+        }
+        tree.report.overflow_pages += page_count;
+        // A run can be larger than needed when a value is overwritten in place
+        // by a smaller one.
+        let pages_needed =
+            ((PAGE_HEADER_SIZE + data_size + self.page_size - 1) / self.page_size) as u64;
+        if page_count < pages_needed {
+            tree.report.add_problem(format!(
+                "overflow run at page {page_number} spans {page_count} pages but its \
+                {data_size} byte value needs {pages_needed}"
+            ));
+            return Ok(None);
+        }
+        if !tree.collect_values {
+            return Ok(Some(vec![]));
+        }
+        let pages = self.read_pages(page_number, page_count as usize)?;
+        Ok(Some(
+            pages[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + data_size].to_vec(),
+        ))
+    }
+}
+
+/// Walks the page trees of the main database, every named database and the
+/// free list of the environment stored at `data_path`, reading its pages
+/// directly from the data file.
+///
+/// Page headers, key ordering and overflow page runs are validated, and the
+/// page and entry counts of each tree are compared with those recorded for it.
+/// Inconsistencies are collected in the report rather than aborting the walk,
+/// only failing to read the file is an error.
+#[cfg(all(target_endian = "little", target_pointer_width = "64"))]
+pub(crate) fn check_structure<P: AsRef<Path>>(
+    env: &Environment,
+    data_path: P,
+) -> Result<StructureReport, StructureError> {
+    // Keeps the pages of the latest snapshot from being reused while they are
+    // read.
+    let txn = env.begin_ro_txn()?;
+    let page_size = env.stat()?.page_size() as usize;
+    let last_page = lmdb_utils::last_page_number(env)? as u64;
+    let mut walker = Walker {
+        file: File::open(data_path.as_ref())?,
+        page_size,
+        last_page,
+        visited: PageSet::new(last_page),
+    };
+    for page_number in 0..META_PAGE_COUNT {
+        walker.visited.insert(page_number);
+    }
+    let mut report = StructureReport {
+        page_size,
+        last_page,
+        ..Default::default()
+    };
+    let meta = match walker.read_meta(&mut report.problems)? {
+        Some(meta) => meta,
+        None => return Ok(report),
+    };
+
+    let mut main_tree = walker.walk_tree(MAIN_DB_NAME, meta.main_db, true)?;
+    let mut named_dbs = vec![];
+    for (raw_key, node_flags, value) in main_tree.values.drain(..) {
+        if node_flags & F_SUBDATA == 0 {
+            continue;
+        }
+        let db_name = String::from_utf8_lossy(&raw_key).to_string();
+        match DbRecord::parse(&value) {
+            Some(record) => named_dbs.push((db_name, record)),
+            None => main_tree.report.add_problem(format!(
+                "record of the {db_name} database is {} bytes long",
+                value.len()
+            )),
+        }
+    }
+    report.databases.push(main_tree.report);
+    for (db_name, record) in named_dbs {
+        let tree = walker.walk_tree(&db_name, record, false)?;
+        info!(
+            "Walked {} database: {} entries in {} branch, {} leaf and {} overflow pages, {} \
+            problems.",
+            db_name,
+            tree.report.entries,
+            tree.report.branch_pages,
+            tree.report.leaf_pages,
+            tree.report.overflow_pages,
+            tree.report.problem_count()
+        );
+        report.databases.push(tree.report);
+    }
+
+    // Each free list entry holds the number of pages followed by their
+    // numbers.
+    let mut free_tree = walker.walk_tree(FREE_DB_NAME, meta.free_db, true)?;
+    let mut free_pages = PageSet::new(last_page);
+    for (raw_key, _, value) in free_tree.values.drain(..) {
+        let count = if value.len() >= 8 {
+            read_u64(&value, 0) as usize
+        } else {
+            0
+        };
+        if value.len() < 8 || (value.len() / 8 - 1) < count {
+            free_tree.report.add_problem(format!(
+                "free list entry {} of {} bytes is truncated",
+                hex::encode(raw_key),
+                value.len()
+            ));
+            continue;
+        }
+        for index in 1..=count {
+            let page_number = read_u64(&value, index * 8);
+            if page_number > last_page {
+                free_tree.report.add_problem(format!(
+                    "free page {page_number} is past the last page {last_page}"
+                ));
+            } else if walker.visited.contains(page_number) {
+                free_tree
+                    .report
+                    .add_problem(format!("page {page_number} is both free and in use"));
+            } else if !free_pages.insert(page_number) {
+                free_tree.report.add_problem(format!(
+                    "page {page_number} is listed as free more than once"
+                ));
+            }
+        }
+    }
+    report.databases.push(free_tree.report);
+    report.free_pages = free_pages.len as usize;
+    report.unaccounted_pages = (last_page + 1).saturating_sub(walker.visited.len + free_pages.len);
+    if report.unaccounted_pages > 0 {
+        warn!(
+            "{} pages are neither in use nor free.",
+            report.unaccounted_pages
+        );
+    }
+    txn.commit()?;
+    Ok(report)
+}
+
+/// Fails, as the layout of the data file read by the page walk only holds on
+/// little-endian 64-bit targets.
+#[cfg(not(all(target_endian = "little", target_pointer_width = "64")))]
+pub(crate) fn check_structure<P: AsRef<Path>>(
+    _env: &Environment,
+    _data_path: P,
+) -> Result<StructureReport, StructureError> {
+    Err(StructureError::UnsupportedTarget)
+}
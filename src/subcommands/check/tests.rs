@@ -0,0 +1,535 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+};
+
+use casper_node::types::{BlockHash, Deploy, DeployHash, DeployMetadata};
+use lmdb::{Transaction, WriteFlags};
+
+use crate::{
+    common::{
+        db::{
+            BlockBodyDatabase, BlockHeaderDatabase, Database, DeployDatabase,
+            DeployMetadataDatabase, DeserializationError, Error as DbError, KeyRange,
+            TransferDatabase, STORAGE_FILE_NAME,
+        },
+        schema::SchemaSelection,
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+    test_utils::{
+        mock_block_header, mock_deploy, mock_deploy_metadata, LmdbTestFixture, MockBlockHeader,
+    },
+};
+
+use super::{
+    checkpoint::{Checkpoint, CHECKPOINT_FILE_NAME},
+    integrity::{self, IntegrityError},
+    jobs::{self, CheckTask},
+    repair::{self, QuarantinedEntry, QUARANTINE_FILE_NAME},
+    report::{DatabaseReport, EntryFailure},
+    structure::{self, FREE_DB_NAME, MAIN_DB_NAME},
+};
+
+const BLOCK_COUNT: usize = 2;
+const DEPLOY_COUNT: usize = 3;
+
+struct MockStorage {
+    fixture: LmdbTestFixture,
+    block_headers: Vec<(BlockHash, MockBlockHeader)>,
+    deploy_hashes: Vec<DeployHash>,
+}
+
+// Populates a storage fixture with 2 blocks, the first one holding deploys 0
+// and 1 and the second one holding deploy 2, along with the metadata of all
+// deploys.
+fn populate_consistent_storage() -> MockStorage {
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            DeployMetadataDatabase::db_name(),
+            DeployDatabase::db_name(),
+            TransferDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    );
+    let deploys: Vec<Deploy> = (0..DEPLOY_COUNT as u8).map(mock_deploy).collect();
+    let deploy_hashes: Vec<DeployHash> = deploys.iter().map(|deploy| *deploy.id()).collect();
+    let block_bodies = [
+        BlockBody::new(vec![deploy_hashes[0], deploy_hashes[1]]),
+        BlockBody::new(vec![deploy_hashes[2]]),
+    ];
+    let block_headers: Vec<(BlockHash, MockBlockHeader)> = block_bodies
+        .iter()
+        .enumerate()
+        .map(|(idx, block_body)| {
+            let (block_hash, mut block_header) = mock_block_header(idx as u8);
+            block_header.body_hash = block_body.hash_v1().unwrap();
+            (block_hash, block_header)
+        })
+        .collect();
+    let deploy_metadatas = [
+        mock_deploy_metadata(&[block_headers[0].0]),
+        mock_deploy_metadata(&[block_headers[0].0]),
+        mock_deploy_metadata(&[block_headers[1].0]),
+    ];
+
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for (i, (block_hash, block_header)) in block_headers.iter().enumerate() {
+        txn.put(
+            *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap(),
+            block_hash,
+            &bincode::serialize(block_header).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.put(
+            *fixture.db(Some(BlockBodyDatabase::db_name())).unwrap(),
+            &block_header.body_hash,
+            &bincode::serialize(&block_bodies[i]).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    }
+    for ((deploy_hash, deploy), deploy_metadata) in deploy_hashes
+        .iter()
+        .zip(deploys.iter())
+        .zip(deploy_metadatas.iter())
+    {
+        txn.put(
+            *fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+            deploy_hash,
+            &bincode::serialize(deploy).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.put(
+            *fixture.db(Some(DeployMetadataDatabase::db_name())).unwrap(),
+            deploy_hash,
+            &bincode::serialize(deploy_metadata).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    }
+    txn.commit().unwrap();
+
+    MockStorage {
+        fixture,
+        block_headers,
+        deploy_hashes,
+    }
+}
+
+#[test]
+fn consistent_storage_should_pass_integrity_check() {
+    let storage = populate_consistent_storage();
+    assert!(integrity::check_integrity(&storage.fixture.env, true).is_ok());
+    assert!(integrity::check_integrity(&storage.fixture.env, false).is_ok());
+}
+
+#[test]
+fn missing_deploy_should_fail_integrity_check() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    {
+        let mut txn = fixture.env.begin_rw_txn().unwrap();
+        txn.del(
+            *fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+            &storage.deploy_hashes[1],
+            None,
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+
+    match integrity::check_integrity(&fixture.env, true) {
+        Err(IntegrityError::MissingDeploy {
+            block_hash,
+            deploy_hash,
+        }) => {
+            assert_eq!(block_hash, storage.block_headers[0].0);
+            assert_eq!(deploy_hash, storage.deploy_hashes[1]);
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn missing_body_and_execution_result_should_accumulate() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    {
+        let mut txn = fixture.env.begin_rw_txn().unwrap();
+        // Remove the body of the first block.
+        txn.del(
+            *fixture.db(Some(BlockBodyDatabase::db_name())).unwrap(),
+            &storage.block_headers[0].1.body_hash,
+            None,
+        )
+        .unwrap();
+        // Store the metadata of the last deploy without an execution result
+        // for the second block.
+        txn.put(
+            *fixture.db(Some(DeployMetadataDatabase::db_name())).unwrap(),
+            &storage.deploy_hashes[2],
+            &bincode::serialize(&DeployMetadata::default()).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        // Store transfers under a block which doesn't exist.
+        let missing_block_hash = mock_block_header(BLOCK_COUNT as u8).0;
+        txn.put(
+            *fixture.db(Some(TransferDatabase::db_name())).unwrap(),
+            &missing_block_hash,
+            &bincode::serialize(&missing_block_hash).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+
+    assert!(integrity::check_integrity(&fixture.env, true).is_err());
+    match integrity::check_integrity(&fixture.env, false) {
+        Err(IntegrityError::Accumulated(errors)) => {
+            assert_eq!(errors.len(), 3);
+            assert!(errors.iter().any(|error| matches!(
+                error,
+                IntegrityError::MissingBlockBody { block_hash, .. }
+                    if *block_hash == storage.block_headers[0].0
+            )));
+            assert!(errors.iter().any(|error| matches!(
+                error,
+                IntegrityError::MissingExecutionResult { deploy_hash, .. }
+                    if *deploy_hash == storage.deploy_hashes[2]
+            )));
+            assert!(errors
+                .iter()
+                .any(|error| matches!(error, IntegrityError::MissingTransferBlock(_))));
+        }
+        other => panic!("unexpected result {other:?}"),
+    }
+}
+
+#[test]
+fn database_report_from_check_results() {
+    let report = DatabaseReport::new(DeployDatabase::db_name(), 0, &Ok(7));
+    assert_eq!(report.db_name, DeployDatabase::db_name());
+    assert_eq!(report.entries_scanned, 7);
+    assert!(report.failures.is_empty());
+    assert!(report.error.is_none());
+
+    let failfast_result = Err(DbError::Parsing(
+        5,
+        vec![0xab, 0xcd],
+        DeserializationError::BytesreprError("formatting error".to_string()),
+    ));
+    let report = DatabaseReport::new(DeployDatabase::db_name(), 2, &failfast_result);
+    assert_eq!(report.entries_scanned, 4);
+    assert_eq!(
+        report.failures,
+        vec![EntryFailure {
+            index: 5,
+            raw_key: "abcd".to_string(),
+            error_kind: "BytesreprError".to_string(),
+            message: "formatting error".to_string(),
+        }]
+    );
+
+    let accumulated_result = Err(DbError::Accumulated(
+        10,
+        vec![
+            DbError::Parsing(
+                1,
+                vec![1],
+                DeserializationError::BytesreprError("first".to_string()),
+            ),
+            DbError::Parsing(
+                8,
+                vec![8],
+                DeserializationError::BytesreprError("second".to_string()),
+            ),
+        ],
+    ));
+    let report = DatabaseReport::new(DeployDatabase::db_name(), 0, &accumulated_result);
+    assert_eq!(report.entries_scanned, 10);
+    assert_eq!(
+        report
+            .failures
+            .iter()
+            .map(|failure| (failure.index, failure.raw_key.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(1, "01"), (8, "08")]
+    );
+    assert!(report.error.is_none());
+}
+
+#[test]
+fn sharded_tasks_should_cover_all_entries() {
+    let storage = populate_consistent_storage();
+    let db_names = [DeployDatabase::db_name(), DeployMetadataDatabase::db_name()];
+
+    let tasks = CheckTask::split(&db_names, 4);
+    assert_eq!(tasks.len(), 8);
+    assert!(tasks[..4]
+        .iter()
+        .all(|task| task.db_name == DeployDatabase::db_name()));
+
+    let checkpoint_dir = tempfile::tempdir().unwrap();
+    let checkpoint = Checkpoint::new(checkpoint_dir.path().join(CHECKPOINT_FILE_NAME));
+    let results = jobs::run_tasks(
+        &storage.fixture.env,
+        &tasks,
+        3,
+        true,
+        0,
+        &checkpoint,
+        SchemaSelection::Auto,
+    );
+    let parsed: usize = results
+        .into_iter()
+        .map(|result| result.unwrap().unwrap().unwrap())
+        .sum();
+    assert_eq!(parsed, 2 * DEPLOY_COUNT);
+    assert!(checkpoint
+        .state()
+        .tasks
+        .values()
+        .all(|progress| progress.complete));
+}
+
+#[test]
+fn block_bodies_should_be_keyed_by_body_hash() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    let body_db = *fixture.db(Some(BlockBodyDatabase::db_name())).unwrap();
+    let check = || {
+        BlockBodyDatabase::check_db(
+            &fixture.env,
+            true,
+            0,
+            &KeyRange::default(),
+            SchemaSelection::Auto,
+            &mut |_| {},
+        )
+    };
+    assert_eq!(check().unwrap(), BLOCK_COUNT);
+
+    // Bodies keyed by their Merkle root belong in `block_body_merkle`.
+    let merkle_body = BlockBody::new(vec![storage.deploy_hashes[0]]);
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    txn.put(
+        body_db,
+        &merkle_body.merkle_root().unwrap(),
+        &bincode::serialize(&merkle_body).unwrap(),
+        WriteFlags::empty(),
+    )
+    .unwrap();
+    txn.commit().unwrap();
+    assert!(matches!(
+        check(),
+        Err(DbError::Parsing(_, _, DeserializationError::KeyMismatch(_)))
+    ));
+}
+
+#[test]
+fn checkpoint_should_resume_after_last_key() {
+    let checkpoint_dir = tempfile::tempdir().unwrap();
+    let checkpoint_path = checkpoint_dir.path().join(CHECKPOINT_FILE_NAME);
+    let tasks = CheckTask::split(
+        &[DeployDatabase::db_name(), DeployMetadataDatabase::db_name()],
+        1,
+    );
+
+    let checkpoint = Checkpoint::new(&checkpoint_path);
+    checkpoint.record(&tasks[0], &[1, 2]);
+    checkpoint.complete(&tasks[0]);
+    checkpoint.record(&tasks[1], &[3, 4]);
+
+    let resumed = Checkpoint::load(&checkpoint_path).unwrap();
+    assert_eq!(resumed.state(), checkpoint.state());
+    assert_eq!(resumed.remaining_range(&tasks[0]).unwrap(), None);
+    assert_eq!(
+        resumed.remaining_range(&tasks[1]).unwrap(),
+        Some(KeyRange {
+            start: Some(vec![3, 4, 0]),
+            end: None
+        })
+    );
+
+    // Tasks missing from the checkpoint are checked entirely.
+    let other_task = CheckTask::new(DeployDatabase::db_name(), KeyRange::shards(2)[0].clone());
+    assert_eq!(
+        resumed.remaining_range(&other_task).unwrap(),
+        Some(other_task.range.clone())
+    );
+
+    // Checkpoints kept in memory don't write any file.
+    let in_memory = Checkpoint::in_memory();
+    in_memory.record(&tasks[0], &[5, 6]);
+    in_memory.complete(&tasks[0]);
+    assert_eq!(in_memory.state().tasks.len(), 1);
+    assert_eq!(fs::read_dir(checkpoint_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn repair_should_quarantine_faulty_entries() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    let faulty_key = [0xffu8; 32];
+    let faulty_value = [1u8, 2, 3];
+    {
+        let mut txn = fixture.env.begin_rw_txn().unwrap();
+        txn.put(
+            *fixture.db(Some(DeployMetadataDatabase::db_name())).unwrap(),
+            &faulty_key,
+            &faulty_value,
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.commit().unwrap();
+    }
+    let check = || {
+        DeployMetadataDatabase::check_db(
+            &fixture.env,
+            false,
+            0,
+            &KeyRange::default(),
+            SchemaSelection::Auto,
+            &mut |_| {},
+        )
+    };
+
+    let faulty_entries = repair::faulty_entries(&check());
+    assert_eq!(faulty_entries.len(), 1);
+    assert_eq!(faulty_entries[0].0, faulty_key.to_vec());
+
+    let quarantine_dir = tempfile::tempdir().unwrap();
+    let db_name = DeployMetadataDatabase::db_name();
+    // A dry run leaves the database untouched.
+    assert_eq!(
+        repair::quarantine_entries(
+            &fixture.env,
+            db_name,
+            &faulty_entries,
+            &quarantine_dir,
+            true
+        )
+        .unwrap(),
+        1
+    );
+    assert!(check().is_err());
+    assert!(!quarantine_dir.path().join(QUARANTINE_FILE_NAME).exists());
+
+    assert_eq!(
+        repair::quarantine_entries(
+            &fixture.env,
+            db_name,
+            &faulty_entries,
+            &quarantine_dir,
+            false
+        )
+        .unwrap(),
+        1
+    );
+    assert_eq!(check().unwrap(), DEPLOY_COUNT);
+    let quarantined: Vec<QuarantinedEntry> =
+        fs::read_to_string(quarantine_dir.path().join(QUARANTINE_FILE_NAME))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].db_name, db_name);
+    assert_eq!(quarantined[0].raw_key, hex::encode(faulty_key));
+    assert_eq!(quarantined[0].raw_value, hex::encode(faulty_value));
+}
+
+#[test]
+#[cfg(all(target_endian = "little", target_pointer_width = "64"))]
+fn lmdb_structure_of_consistent_storage_should_be_valid() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    // Large enough to be stored in overflow pages.
+    let big_value = vec![7u8; 10_000];
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    txn.put(
+        *fixture.db(Some(TransferDatabase::db_name())).unwrap(),
+        &[1u8; 32],
+        &big_value,
+        WriteFlags::empty(),
+    )
+    .unwrap();
+    txn.commit().unwrap();
+
+    let report = structure::check_structure(&fixture.env, &fixture.file_path).unwrap();
+    assert_eq!(report.problem_count(), 0);
+    let db_report = |db_name: &str| {
+        report
+            .databases
+            .iter()
+            .find(|tree| tree.db_name == db_name)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(db_report(MAIN_DB_NAME).entries, 5);
+    assert_eq!(
+        db_report(BlockHeaderDatabase::db_name()).entries,
+        BLOCK_COUNT as u64
+    );
+    assert_eq!(
+        db_report(DeployMetadataDatabase::db_name()).entries,
+        DEPLOY_COUNT as u64
+    );
+    let transfer_report = db_report(TransferDatabase::db_name());
+    assert_eq!(transfer_report.entries, 1);
+    assert_eq!(transfer_report.overflow_pages, 3);
+    assert!(report
+        .databases
+        .iter()
+        .any(|tree| tree.db_name == FREE_DB_NAME));
+}
+
+#[test]
+#[cfg(all(target_endian = "little", target_pointer_width = "64"))]
+fn lmdb_structure_problems_should_be_reported() {
+    let storage = populate_consistent_storage();
+    let fixture = &storage.fixture;
+    let report = structure::check_structure(&fixture.env, &fixture.file_path).unwrap();
+    let root_page = report
+        .databases
+        .iter()
+        .find(|tree| tree.db_name == DeployMetadataDatabase::db_name())
+        .and_then(|tree| tree.root_page)
+        .unwrap();
+
+    // Swap the offsets of the first two nodes of the single leaf page, so
+    // that its keys are out of order.
+    let page_offset = root_page * report.page_size as u64;
+    let mut data_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&fixture.file_path)
+        .unwrap();
+    let page = fs::read(&fixture.file_path).unwrap();
+    let node_offsets = &page[page_offset as usize + 16..page_offset as usize + 20];
+    let swapped = [
+        node_offsets[2],
+        node_offsets[3],
+        node_offsets[0],
+        node_offsets[1],
+    ];
+    data_file.seek(SeekFrom::Start(page_offset + 16)).unwrap();
+    data_file.write_all(&swapped).unwrap();
+    data_file.sync_all().unwrap();
+
+    let report = structure::check_structure(&fixture.env, &fixture.file_path).unwrap();
+    let tree = report
+        .databases
+        .iter()
+        .find(|tree| tree.db_name == DeployMetadataDatabase::db_name())
+        .unwrap();
+    assert_eq!(tree.entries, DEPLOY_COUNT as u64);
+    assert_eq!(tree.problems.len(), 1);
+    assert!(tree.problems[0].contains("isn't greater than the previous key"));
+    assert_eq!(report.problem_count(), 1);
+}
@@ -0,0 +1,305 @@
+mod audit;
+#[cfg(test)]
+mod tests;
+mod verify;
+
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::{self, Error as IoError, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Error as AnyError;
+use bincode::Error as BincodeError;
+use clap::{Arg, ArgMatches, Command};
+use lmdb::{Cursor, Error as LmdbError, Transaction};
+use log::info;
+use serde_json::Error as SerializationError;
+use thiserror::Error as ThisError;
+
+use casper_hashing::Digest;
+use casper_node::types::BlockHeader;
+
+use crate::{
+    common::db::{self, BlockHeaderDatabase, Database, STORAGE_FILE_NAME},
+    subcommands::{
+        integer_arg,
+        trie_compact::{load_execution_engine, DEFAULT_MAX_DB_SIZE_ARG},
+    },
+};
+
+pub use verify::TrieProblem;
+use verify::TrieVerifier;
+
+pub const COMMAND_NAME: &str = "check-trie";
+const AUDIT_HISTORY: &str = "audit-history";
+const MAX_CACHED_NODES: &str = "max-cached-nodes";
+const DEFAULT_MAX_CACHED_NODES: &str = "10000000";
+const MAX_DB_SIZE: &str = "max-db-size";
+const NO_FAILFAST: &str = "no-failfast";
+const OUTPUT: &str = "output";
+const OVERWRITE: &str = "overwrite";
+const STATE_ROOT_HASH: &str = "state-root-hash";
+const STORAGE_PATH: &str = "storage-path";
+const TRIE_PATH: &str = "trie-path";
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Error reading block headers: {0}")]
+    BlockHeaders(LmdbError),
+    #[error("Storage database not found at {0}")]
+    MissingStorage(PathBuf),
+    #[error("Invalid trie node: {0}")]
+    Node(TrieProblem),
+    #[error("Error loading the execution engine: {0}")]
+    OpenTrie(AnyError),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
+    #[error("Error parsing block header {0}: {1}")]
+    Parsing(usize, BincodeError),
+    #[error("Found {0} invalid trie nodes")]
+    Problems(usize),
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] SerializationError),
+    #[error("Error operating the trie store: {0}")]
+    TrieStore(LmdbError),
+}
+
+enum DisplayOrder {
+    TriePath,
+    StoragePath,
+    StateRootHash,
+    NoFailfast,
+    MaxDbSize,
+    MaxCachedNodes,
+    AuditHistory,
+    Output,
+    Overwrite,
+}
+
+pub fn command(display_order: usize) -> Command<'static> {
+    Command::new(COMMAND_NAME)
+        .display_order(display_order)
+        .about(
+            "Checks the integrity of the trie store by walking the tries of state roots, \
+            verifying that every referenced node exists, deserializes and matches its hash.",
+        )
+        .arg(
+            Arg::new(TRIE_PATH)
+                .display_order(DisplayOrder::TriePath as usize)
+                .required(true)
+                .short('t')
+                .long(TRIE_PATH)
+                .takes_value(true)
+                .value_name("TRIE_STORE_DIR_PATH")
+                .help("Path of the directory with the `data.lmdb` file."),
+        )
+        .arg(
+            Arg::new(STORAGE_PATH)
+                .display_order(DisplayOrder::StoragePath as usize)
+                .required_unless_present(STATE_ROOT_HASH)
+                .short('b')
+                .long(STORAGE_PATH)
+                .takes_value(true)
+                .value_name("STORAGE_DIR_PATH")
+                .help(
+                    "Path of the directory with the `storage.lmdb` file. The state roots of \
+                    all the block headers are checked, from the highest block to the lowest.",
+                ),
+        )
+        .arg(
+            Arg::new(STATE_ROOT_HASH)
+                .display_order(DisplayOrder::StateRootHash as usize)
+                .short('s')
+                .long(STATE_ROOT_HASH)
+                .takes_value(true)
+                .value_name("STATE_ROOT_HASH")
+                .conflicts_with(STORAGE_PATH)
+                .help("Hex encoded state root hash whose trie is checked."),
+        )
+        .arg(
+            Arg::new(NO_FAILFAST)
+                .display_order(DisplayOrder::NoFailfast as usize)
+                .short('f')
+                .long(NO_FAILFAST)
+                .takes_value(false)
+                .help("Program will not terminate at the first invalid trie node."),
+        )
+        .arg(
+            Arg::new(MAX_DB_SIZE)
+                .display_order(DisplayOrder::MaxDbSize as usize)
+                .short('m')
+                .long(MAX_DB_SIZE)
+                .takes_value(true)
+                .default_value(&DEFAULT_MAX_DB_SIZE_ARG)
+                .value_name("MAX_DB_SIZE")
+                .help("Maximum size the DB files are allowed to be, in bytes."),
+        )
+        .arg(
+            Arg::new(MAX_CACHED_NODES)
+                .display_order(DisplayOrder::MaxCachedNodes as usize)
+                .long(MAX_CACHED_NODES)
+                .takes_value(true)
+                .default_value(DEFAULT_MAX_CACHED_NODES)
+                .value_name("NODE_COUNT")
+                .help(
+                    "Maximum number of verified nodes remembered, so that the subtries shared \
+                    between state roots are only verified once. Each takes about 80 bytes of \
+                    memory. Once the limit is reached, the verified nodes are forgotten and \
+                    the shared subtries of the next state roots are verified again.",
+                ),
+        )
+        .arg(
+            Arg::new(AUDIT_HISTORY)
+                .display_order(DisplayOrder::AuditHistory as usize)
+                .long(AUDIT_HISTORY)
+                .takes_value(false)
+                .requires(STORAGE_PATH)
+                .help(
+                    "Instead of failing on invalid nodes, audit the state root of every block \
+                    and output, in JSON format, the ranges of block heights whose global state \
+                    is complete, incomplete or missing in the trie store.",
+                ),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .requires(AUDIT_HISTORY)
+                .help(
+                    "Path to where the program will output the audit report. \
+                    If unspecified, defaults to standard output.",
+                ),
+        )
+        .arg(
+            Arg::new(OVERWRITE)
+                .display_order(DisplayOrder::Overwrite as usize)
+                .required(false)
+                .short('w')
+                .long(OVERWRITE)
+                .takes_value(false)
+                .requires(OUTPUT)
+                .help("Overwrite an already existing output file."),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let trie_path = matches
+        .value_of(TRIE_PATH)
+        .expect("should have trie-path arg");
+    let failfast = !matches.is_present(NO_FAILFAST);
+    let max_db_size = integer_arg(matches, MAX_DB_SIZE).expect("should have a default");
+    let max_cached_nodes = integer_arg(matches, MAX_CACHED_NODES).expect("should have a default");
+    if matches.is_present(AUDIT_HISTORY) {
+        let roots_by_height = block_state_roots(
+            matches
+                .value_of(STORAGE_PATH)
+                .expect("should be required by --audit-history"),
+        )?;
+        let overwrite = matches.is_present(OVERWRITE);
+        let out_writer: Box<dyn Write> = match matches.value_of(OUTPUT) {
+            Some(out_path) => Box::new(
+                OpenOptions::new()
+                    .create_new(!overwrite)
+                    .write(true)
+                    .open(out_path)?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+        let (engine_state, _env) =
+            load_execution_engine(trie_path, max_db_size, Digest::default(), true)
+                .map_err(Error::OpenTrie)?;
+        let report = audit::audit_history(&engine_state, &roots_by_height, max_cached_nodes)?;
+        serde_json::to_writer_pretty(out_writer, &report)?;
+        return Ok(());
+    }
+
+    let state_roots = match matches.value_of(STATE_ROOT_HASH) {
+        Some(state_root_hash_str) => vec![Digest::from_hex(state_root_hash_str)
+            .expect("should parse state root hash to hex format")],
+        None => distinct_state_roots(&block_state_roots(
+            matches
+                .value_of(STORAGE_PATH)
+                .expect("should have storage-path arg"),
+        )?),
+    };
+
+    check_trie(
+        trie_path,
+        &state_roots,
+        max_db_size,
+        failfast,
+        max_cached_nodes,
+    )
+}
+
+/// Returns the heights and state root hashes of the block headers in
+/// storage, ordered from the highest block to the lowest.
+fn block_state_roots<P: AsRef<Path>>(storage_path: P) -> Result<Vec<(u64, Digest)>, Error> {
+    let storage_file = storage_path.as_ref().join(STORAGE_FILE_NAME);
+    let env = db::existing_db_env(&storage_file)
+        .map_err(Error::BlockHeaders)?
+        .ok_or(Error::MissingStorage(storage_file))?;
+    let txn = env.begin_ro_txn().map_err(Error::BlockHeaders)?;
+    let db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name())) }
+        .map_err(Error::BlockHeaders)?;
+    let mut cursor = txn.open_ro_cursor(db).map_err(Error::BlockHeaders)?;
+    let mut roots_by_height = vec![];
+    for (idx, (_raw_key, raw_val)) in cursor.iter().enumerate() {
+        let header: BlockHeader = bincode::deserialize(raw_val)
+            .map_err(|bincode_err| Error::Parsing(idx, bincode_err))?;
+        roots_by_height.push((header.height(), *header.state_root_hash()));
+    }
+    drop(cursor);
+    txn.commit().map_err(Error::BlockHeaders)?;
+
+    roots_by_height.sort_unstable_by(|(height, _), (other_height, _)| other_height.cmp(height));
+    Ok(roots_by_height)
+}
+
+/// Returns the distinct state root hashes, keeping the order of their first
+/// occurrence.
+fn distinct_state_roots(roots_by_height: &[(u64, Digest)]) -> Vec<Digest> {
+    let mut seen_roots = HashSet::new();
+    roots_by_height
+        .iter()
+        .filter_map(|(_height, state_root)| seen_roots.insert(*state_root).then_some(*state_root))
+        .collect()
+}
+
+/// Verifies the tries of the state roots in the trie store at `trie_path`.
+fn check_trie<P: AsRef<Path>>(
+    trie_path: P,
+    state_roots: &[Digest],
+    max_db_size: usize,
+    failfast: bool,
+    max_cached_nodes: usize,
+) -> Result<(), Error> {
+    let (engine_state, _env) =
+        load_execution_engine(trie_path, max_db_size, Digest::default(), true)
+            .map_err(Error::OpenTrie)?;
+    let mut verifier = TrieVerifier::new(failfast, max_cached_nodes);
+    let mut problem_count = 0;
+    for state_root in state_roots {
+        let summary = verifier.verify_state_root(*state_root, &engine_state)?;
+        info!(
+            "Checked state root {}: {} new nodes of which {} leaves, {} bytes, {} problems.",
+            state_root,
+            summary.nodes_checked,
+            summary.leaves,
+            summary.bytes,
+            summary.problems.len()
+        );
+        problem_count += summary.problems.len();
+    }
+    info!("Finished checking {} state roots.", state_roots.len());
+
+    match problem_count {
+        0 => Ok(()),
+        _ => Err(Error::Problems(problem_count)),
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::{BTreeMap, HashMap};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use casper_execution_engine::{
+    core::engine_state::EngineState, storage::global_state::lmdb::LmdbGlobalState,
+};
+use casper_hashing::Digest;
+
+use super::{
+    verify::{StateAvailability, TrieVerifier},
+    Error,
+};
+
+/// Consecutive block heights sharing the same state availability.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct HeightRange {
+    pub(crate) start: u64,
+    /// Last height of the range, inclusive.
+    pub(crate) end: u64,
+    pub(crate) availability: StateAvailability,
+}
+
+/// Availability of the global state over the block history.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct AuditReport {
+    pub(crate) ranges: Vec<HeightRange>,
+}
+
+/// Merges the state availability at each height, given in any order, into
+/// ranges of consecutive heights from genesis to the highest block. Heights
+/// without a block are reported as such, and when a height has several
+/// blocks, the best availability is kept.
+pub(crate) fn height_ranges(availabilities: &[(u64, StateAvailability)]) -> Vec<HeightRange> {
+    let mut by_height: BTreeMap<u64, StateAvailability> = BTreeMap::new();
+    for (height, availability) in availabilities {
+        by_height
+            .entry(*height)
+            .and_modify(|best| *best = (*best).min(*availability))
+            .or_insert(*availability);
+    }
+
+    let mut ranges: Vec<HeightRange> = vec![];
+    let mut extend = |start: u64, end: u64, availability: StateAvailability| match ranges.last_mut()
+    {
+        Some(last) if last.availability == availability && last.end + 1 == start => last.end = end,
+        _ => ranges.push(HeightRange {
+            start,
+            end,
+            availability,
+        }),
+    };
+    let mut next_height = 0;
+    for (height, availability) in by_height {
+        if height > next_height {
+            extend(next_height, height - 1, StateAvailability::NoBlock);
+        }
+        extend(height, height, availability);
+        next_height = height + 1;
+    }
+    ranges
+}
+
+/// Verifies the state root of every block, given from the highest to the
+/// lowest as in `trie_compact`, without copying anything, and reports the
+/// ranges of heights whose global state is complete, incomplete or missing.
+///
+/// Problems in the tries are logged but don't interrupt the audit.
+pub(crate) fn audit_history(
+    engine_state: &EngineState<LmdbGlobalState>,
+    roots_by_height: &[(u64, Digest)],
+    max_cached_nodes: usize,
+) -> Result<AuditReport, Error> {
+    let mut verifier = TrieVerifier::new(false, max_cached_nodes);
+    let mut root_availability: HashMap<Digest, StateAvailability> = HashMap::new();
+    let mut availabilities = vec![];
+    for (height, state_root) in roots_by_height {
+        let availability = match root_availability.get(state_root) {
+            Some(availability) => *availability,
+            None => {
+                let summary = verifier.verify_state_root(*state_root, engine_state)?;
+                info!(
+                    "State root {} of block {} is {:?}, {} new nodes checked.",
+                    state_root, height, summary.availability, summary.nodes_checked
+                );
+                root_availability.insert(*state_root, summary.availability);
+                summary.availability
+            }
+        };
+        availabilities.push((*height, availability));
+    }
+
+    let report = AuditReport {
+        ranges: height_ranges(&availabilities),
+    };
+    for range in &report.ranges {
+        info!(
+            "Heights {} to {}: {:?}",
+            range.start, range.end, range.availability
+        );
+    }
+    Ok(report)
+}
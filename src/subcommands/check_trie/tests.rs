@@ -0,0 +1,315 @@
+use lmdb::{DatabaseFlags, WriteFlags};
+use tempfile::{tempdir, TempDir};
+
+use casper_execution_engine::storage::{
+    store::StoreExt,
+    transaction_source::{lmdb::LmdbEnvironment, Transaction, TransactionSource, Writable},
+    trie::{Pointer, PointerBlock, Trie},
+    trie_store::lmdb::LmdbTrieStore,
+};
+use casper_hashing::Digest;
+use casper_types::{account::AccountHash, bytesrepr::ToBytes, CLValue, Key, StoredValue};
+
+use crate::{
+    common::db::{BlockHeaderDatabase, Database, DEFAULT_MAX_DB_SIZE, STORAGE_FILE_NAME},
+    subcommands::trie_compact::load_execution_engine,
+    test_utils::{mock_block_header, LmdbTestFixture},
+};
+
+use super::{
+    audit::{self, HeightRange},
+    block_state_roots, distinct_state_roots,
+    verify::{StateAvailability, TrieVerifier},
+    Error, TrieProblem,
+};
+
+const MAX_CACHED_NODES: usize = 1_000;
+
+struct StateData {
+    state_root: Digest,
+    leaf_hashes: Vec<Digest>,
+    tries: Vec<(Digest, Trie<Key, StoredValue>)>,
+}
+
+// A node pointing to 2 leaves.
+fn create_state_data() -> StateData {
+    let leaves: Vec<Trie<Key, StoredValue>> = (0..2u8)
+        .map(|idx| Trie::Leaf {
+            key: Key::Account(AccountHash::new([idx; 32])),
+            value: StoredValue::CLValue(CLValue::from_t(idx as u64).unwrap()),
+        })
+        .collect();
+    let leaf_hashes: Vec<Digest> = leaves
+        .iter()
+        .map(|leaf| Digest::hash(leaf.to_bytes().unwrap()))
+        .collect();
+    let mut pointer_block = PointerBlock::new();
+    pointer_block[0] = Some(Pointer::LeafPointer(leaf_hashes[0]));
+    pointer_block[1] = Some(Pointer::LeafPointer(leaf_hashes[1]));
+    let node: Trie<Key, StoredValue> = Trie::Node {
+        pointer_block: Box::new(pointer_block),
+    };
+    let state_root = Digest::hash(node.to_bytes().unwrap());
+
+    let mut tries: Vec<(Digest, Trie<Key, StoredValue>)> =
+        leaf_hashes.iter().copied().zip(leaves).collect();
+    tries.push((state_root, node));
+    StateData {
+        state_root,
+        leaf_hashes,
+        tries,
+    }
+}
+
+// Stores the tries of the state data, except those with the skipped hashes,
+// followed by the raw entries.
+fn create_trie_store(
+    data: &StateData,
+    skipped: &[Digest],
+    raw_entries: &[(Digest, Vec<u8>)],
+) -> TempDir {
+    let tmp_dir = tempdir().unwrap();
+    let env = LmdbEnvironment::new(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
+    let store = LmdbTrieStore::new(&env, None, DatabaseFlags::empty()).unwrap();
+    let mut txn = env.create_read_write_txn().unwrap();
+    let items = data
+        .tries
+        .iter()
+        .filter(|(digest, _)| !skipped.contains(digest))
+        .map(|(digest, trie)| (digest, trie));
+    store.put_many(&mut txn, items).unwrap();
+    for (digest, raw_value) in raw_entries {
+        txn.write(store.get_db(), &digest.value(), raw_value)
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    env.env().sync(true).unwrap();
+    tmp_dir
+}
+
+fn verify(
+    tmp_dir: &TempDir,
+    state_root: Digest,
+    failfast: bool,
+) -> Result<super::verify::RootSummary, Error> {
+    let (engine_state, _env) =
+        load_execution_engine(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    TrieVerifier::new(failfast, MAX_CACHED_NODES).verify_state_root(state_root, &engine_state)
+}
+
+#[test]
+fn valid_trie_should_pass() {
+    let data = create_state_data();
+    let tmp_dir = create_trie_store(&data, &[], &[]);
+    let (engine_state, _env) =
+        load_execution_engine(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    let mut verifier = TrieVerifier::new(true, MAX_CACHED_NODES);
+
+    let summary = verifier
+        .verify_state_root(data.state_root, &engine_state)
+        .unwrap();
+    assert_eq!(summary.nodes_checked, 3);
+    assert_eq!(summary.leaves, 2);
+    assert!(summary.problems.is_empty());
+
+    // Nodes already verified aren't read again.
+    let summary = verifier
+        .verify_state_root(data.state_root, &engine_state)
+        .unwrap();
+    assert_eq!(summary.nodes_checked, 0);
+}
+
+#[test]
+fn verified_nodes_should_be_forgotten_past_limit() {
+    let data = create_state_data();
+    let tmp_dir = create_trie_store(&data, &[], &[]);
+    let (engine_state, _env) =
+        load_execution_engine(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    let mut verifier = TrieVerifier::new(true, 1);
+
+    // The trie is verified in full each time, as no node is remembered.
+    for _ in 0..2 {
+        let summary = verifier
+            .verify_state_root(data.state_root, &engine_state)
+            .unwrap();
+        assert_eq!(summary.nodes_checked, 3);
+        assert!(summary.problems.is_empty());
+    }
+}
+
+#[test]
+fn missing_node_should_fail() {
+    let data = create_state_data();
+    let missing_leaf = data.leaf_hashes[1];
+    let tmp_dir = create_trie_store(&data, &[missing_leaf], &[]);
+
+    assert!(matches!(
+        verify(&tmp_dir, data.state_root, true),
+        Err(Error::Node(TrieProblem::Missing(digest))) if digest == missing_leaf
+    ));
+
+    let summary = verify(&tmp_dir, data.state_root, false).unwrap();
+    assert_eq!(summary.nodes_checked, 2);
+    assert_eq!(summary.problems.len(), 1);
+    assert!(matches!(
+        summary.problems[0],
+        TrieProblem::Missing(digest) if digest == missing_leaf
+    ));
+}
+
+#[test]
+fn corrupted_nodes_should_be_reported() {
+    let data = create_state_data();
+    let [first_leaf, second_leaf] = [data.leaf_hashes[0], data.leaf_hashes[1]];
+    let second_leaf_bytes = data.tries[1].1.to_bytes().unwrap();
+    // The first leaf holds the second one, which still deserializes, and the
+    // second one holds garbage.
+    let tmp_dir = create_trie_store(
+        &data,
+        &[first_leaf, second_leaf],
+        &[(first_leaf, second_leaf_bytes), (second_leaf, vec![255u8])],
+    );
+
+    let summary = verify(&tmp_dir, data.state_root, false).unwrap();
+    assert_eq!(summary.nodes_checked, 3);
+    assert_eq!(summary.leaves, 1);
+    assert_eq!(summary.problems.len(), 3);
+    assert!(summary.problems.iter().any(|problem| matches!(
+        problem,
+        TrieProblem::HashMismatch { key, digest } if *key == first_leaf && *digest == second_leaf
+    )));
+    assert!(summary.problems.iter().any(|problem| matches!(
+        problem,
+        TrieProblem::HashMismatch { key, .. } if *key == second_leaf
+    )));
+    assert!(summary.problems.iter().any(|problem| matches!(
+        problem,
+        TrieProblem::Deserialization(key, _) if *key == second_leaf
+    )));
+}
+
+#[test]
+fn state_roots_should_be_ordered_by_height() {
+    let fixture = LmdbTestFixture::new(
+        vec![BlockHeaderDatabase::db_name()],
+        Some(STORAGE_FILE_NAME),
+    );
+    let db = *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap();
+    let first_root: Digest = [1u8; Digest::LENGTH].into();
+    let second_root: Digest = [2u8; Digest::LENGTH].into();
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for (idx, state_root) in [first_root, second_root, first_root]
+        .into_iter()
+        .enumerate()
+    {
+        let (block_hash, mut block_header) = mock_block_header(idx as u8);
+        block_header.height = idx as u64;
+        block_header.state_root_hash = state_root;
+        txn.put(
+            db,
+            &block_hash,
+            &bincode::serialize(&block_header).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    }
+    txn.commit().unwrap();
+
+    let roots_by_height = block_state_roots(fixture.tmp_dir.path()).unwrap();
+    assert_eq!(
+        roots_by_height,
+        vec![(2, first_root), (1, second_root), (0, first_root)]
+    );
+    assert_eq!(
+        distinct_state_roots(&roots_by_height),
+        vec![first_root, second_root]
+    );
+
+    let empty_dir = tempdir().unwrap();
+    assert!(matches!(
+        block_state_roots(empty_dir.path()),
+        Err(Error::MissingStorage(_))
+    ));
+}
+
+#[test]
+fn height_ranges_should_merge_consecutive_heights() {
+    use StateAvailability::*;
+
+    assert!(audit::height_ranges(&[]).is_empty());
+
+    let range = |start, end, availability| HeightRange {
+        start,
+        end,
+        availability,
+    };
+    // Heights are given in any order, with a gap at 4 and two blocks at 6.
+    let availabilities = [
+        (6, Missing),
+        (7, Missing),
+        (2, Complete),
+        (6, Complete),
+        (5, Incomplete),
+        (3, Complete),
+    ];
+    assert_eq!(
+        audit::height_ranges(&availabilities),
+        vec![
+            range(0, 1, NoBlock),
+            range(2, 3, Complete),
+            range(4, 4, NoBlock),
+            range(5, 5, Incomplete),
+            range(6, 6, Complete),
+            range(7, 7, Missing),
+        ]
+    );
+}
+
+#[test]
+fn audit_should_report_state_availability_by_height() {
+    use StateAvailability::*;
+
+    let data = create_state_data();
+    // A second root whose node points to a present leaf and a missing one.
+    let missing_leaf: Digest = [7u8; Digest::LENGTH].into();
+    let mut pointer_block = PointerBlock::new();
+    pointer_block[0] = Some(Pointer::LeafPointer(data.leaf_hashes[0]));
+    pointer_block[1] = Some(Pointer::LeafPointer(missing_leaf));
+    let incomplete_node: Trie<Key, StoredValue> = Trie::Node {
+        pointer_block: Box::new(pointer_block),
+    };
+    let incomplete_node_bytes = incomplete_node.to_bytes().unwrap();
+    let incomplete_root = Digest::hash(&incomplete_node_bytes);
+    let missing_root: Digest = [8u8; Digest::LENGTH].into();
+    let tmp_dir = create_trie_store(&data, &[], &[(incomplete_root, incomplete_node_bytes)]);
+    let (engine_state, _env) =
+        load_execution_engine(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+
+    let roots_by_height = [
+        (5, data.state_root),
+        (4, incomplete_root),
+        (2, missing_root),
+        (1, data.state_root),
+        (0, data.state_root),
+    ];
+    let report = audit::audit_history(&engine_state, &roots_by_height, MAX_CACHED_NODES).unwrap();
+    let range = |start, end, availability| HeightRange {
+        start,
+        end,
+        availability,
+    };
+    assert_eq!(
+        report.ranges,
+        vec![
+            range(0, 1, Complete),
+            range(2, 2, Missing),
+            range(3, 3, NoBlock),
+            range(4, 4, Incomplete),
+            range(5, 5, Complete),
+        ]
+    );
+}
@@ -0,0 +1,272 @@
+use std::{collections::HashMap, time::Instant};
+
+use lmdb::RoTransaction;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error as ThisError;
+
+use casper_execution_engine::{
+    core::engine_state::EngineState,
+    storage::{
+        global_state::lmdb::LmdbGlobalState,
+        transaction_source::{Readable, Transaction, TransactionSource},
+        trie::{Pointer, Trie},
+        trie_store::lmdb::LmdbTrieStore,
+    },
+};
+use casper_hashing::Digest;
+use casper_types::{bytesrepr, Key, StoredValue};
+
+use super::Error;
+
+/// Interval between progress logs, in seconds.
+const HEARTBEAT_INTERVAL_SECS: u64 = 10;
+
+/// Inconsistency found in a node of the trie store.
+#[derive(Debug, ThisError)]
+pub enum TrieProblem {
+    #[error("node {0} is missing")]
+    Missing(Digest),
+    #[error("node {key} hashes to {digest}")]
+    HashMismatch { key: Digest, digest: Digest },
+    #[error("node {0} couldn't be deserialized: {1}")]
+    Deserialization(Digest, String),
+}
+
+/// Availability of the trie of a state root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum StateAvailability {
+    /// Every node of the trie is present and valid.
+    Complete,
+    /// The root node is present, but some node under it is missing or
+    /// invalid.
+    Incomplete,
+    /// The root node is missing.
+    Missing,
+    /// No block is stored at the height, so there is no state root to check.
+    NoBlock,
+}
+
+/// Outcome of verifying the trie under a state root.
+#[derive(Debug)]
+pub(crate) struct RootSummary {
+    pub(crate) availability: StateAvailability,
+    /// Number of nodes read, excluding those already verified under a
+    /// previous state root.
+    pub(crate) nodes_checked: u64,
+    /// Number of leaves among the nodes read.
+    pub(crate) leaves: u64,
+    /// Total size of the nodes read.
+    pub(crate) bytes: u64,
+    pub(crate) problems: Vec<TrieProblem>,
+}
+
+/// Node whose subtrie is being verified.
+struct Frame {
+    trie_key: Digest,
+    children: Vec<Digest>,
+    next_child: usize,
+    /// Whether the node and the children verified so far are valid.
+    valid: bool,
+}
+
+/// Outcome of reaching a node during the walk.
+enum Visit {
+    /// The node was verified earlier, with the validity of its subtrie.
+    Known(bool),
+    /// The node was read and its children remain to be verified.
+    New(Frame),
+}
+
+/// Read-only walker of the trie store verifying that every node reachable
+/// from a state root exists, deserializes and is keyed by its own hash.
+///
+/// Nodes are shared between the tries of successive state roots, so the
+/// digests of the nodes already verified are kept in memory, along with the
+/// validity of their subtries, and their subtries aren't walked again. At
+/// most `max_cached_nodes` nodes are kept, the verified nodes being forgotten
+/// once the limit is reached, so that the subtries shared with the next state
+/// roots may be walked again.
+pub(crate) struct TrieVerifier {
+    verified: HashMap<Digest, bool>,
+    failfast: bool,
+    max_cached_nodes: usize,
+}
+
+impl TrieVerifier {
+    pub(crate) fn new(failfast: bool, max_cached_nodes: usize) -> Self {
+        Self {
+            verified: HashMap::new(),
+            failfast,
+            max_cached_nodes,
+        }
+    }
+
+    pub(crate) fn verify_state_root(
+        &mut self,
+        state_root: Digest,
+        engine_state: &EngineState<LmdbGlobalState>,
+    ) -> Result<RootSummary, Error> {
+        let trie_store = engine_state.get_state().trie_store();
+        let txn = engine_state
+            .get_state()
+            .environment()
+            .create_read_txn()
+            .map_err(Error::TrieStore)?;
+        let mut summary = RootSummary {
+            availability: StateAvailability::Complete,
+            nodes_checked: 0,
+            leaves: 0,
+            bytes: 0,
+            problems: vec![],
+        };
+        let mut heartbeat_interval = Instant::now();
+
+        // Subtries are walked depth first, a node being valid once all its
+        // children are.
+        let mut stack = match self.visit(state_root, &txn, trie_store, &mut summary)? {
+            Visit::Known(true) => vec![],
+            Visit::Known(false) => {
+                let root_missing = summary.problems.iter().any(
+                    |problem| matches!(problem, TrieProblem::Missing(digest) if *digest == state_root),
+                );
+                summary.availability = if root_missing {
+                    StateAvailability::Missing
+                } else {
+                    StateAvailability::Incomplete
+                };
+                vec![]
+            }
+            Visit::New(frame) => vec![frame],
+        };
+        while let Some(frame) = stack.last_mut() {
+            if heartbeat_interval.elapsed().as_secs() > HEARTBEAT_INTERVAL_SECS {
+                info!(
+                    "trie verification progress: nodes checked {}, bytes read {}",
+                    summary.nodes_checked, summary.bytes
+                );
+                heartbeat_interval = Instant::now();
+            }
+            if let Some(child) = frame.children.get(frame.next_child).copied() {
+                frame.next_child += 1;
+                match self.visit(child, &txn, trie_store, &mut summary)? {
+                    Visit::Known(valid) => frame.valid &= valid,
+                    Visit::New(child_frame) => stack.push(child_frame),
+                }
+                continue;
+            }
+            let frame = stack.pop().expect("should have a frame");
+            self.verified.insert(frame.trie_key, frame.valid);
+            if self.verified.len() >= self.max_cached_nodes {
+                self.forget_verified(&stack);
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.valid &= frame.valid,
+                None if !frame.valid => summary.availability = StateAvailability::Incomplete,
+                None => (),
+            }
+        }
+        txn.commit().map_err(Error::TrieStore)?;
+        Ok(summary)
+    }
+
+    /// Reads and verifies a single node, unless it was verified earlier.
+    fn visit(
+        &mut self,
+        trie_key: Digest,
+        txn: &RoTransaction<'_>,
+        trie_store: &LmdbTrieStore,
+        summary: &mut RootSummary,
+    ) -> Result<Visit, Error> {
+        if let Some(valid) = self.verified.get(&trie_key) {
+            return Ok(Visit::Known(*valid));
+        }
+        // Provisionally valid, so that a corrupted node pointing back to one
+        // of its ancestors doesn't lead to an endless walk.
+        self.verified.insert(trie_key, true);
+
+        let value_bytes = match txn
+            .read(trie_store.get_db(), &trie_key.value())
+            .map_err(Error::TrieStore)?
+        {
+            Some(value_bytes) => value_bytes,
+            None => {
+                self.record(summary, TrieProblem::Missing(trie_key))?;
+                self.verified.insert(trie_key, false);
+                return Ok(Visit::Known(false));
+            }
+        };
+        summary.nodes_checked += 1;
+        summary.bytes += value_bytes.len() as u64;
+
+        let mut frame = Frame {
+            trie_key,
+            children: vec![],
+            next_child: 0,
+            valid: true,
+        };
+        let digest = Digest::hash(&value_bytes[..]);
+        if digest != trie_key {
+            frame.valid = false;
+            self.record(
+                summary,
+                TrieProblem::HashMismatch {
+                    key: trie_key,
+                    digest,
+                },
+            )?;
+        }
+        match bytesrepr::deserialize::<Trie<Key, StoredValue>>(value_bytes.into()) {
+            Ok(Trie::Leaf { .. }) => summary.leaves += 1,
+            Ok(Trie::Node { pointer_block }) => {
+                frame.children = pointer_block
+                    .as_indexed_pointers()
+                    .map(|(_index, pointer)| pointer_digest(pointer))
+                    .collect();
+            }
+            Ok(Trie::Extension { affix: _, pointer }) => {
+                frame.children.push(pointer_digest(pointer))
+            }
+            Err(bytesrepr_err) => {
+                frame.valid = false;
+                self.record(
+                    summary,
+                    TrieProblem::Deserialization(trie_key, bytesrepr_err.to_string()),
+                )?;
+            }
+        }
+        Ok(Visit::New(frame))
+    }
+
+    /// Forgets the nodes verified so far, except the nodes whose subtries are
+    /// being walked, which are kept to detect cycles.
+    fn forget_verified(&mut self, stack: &[Frame]) {
+        info!(
+            "Forgetting {} verified nodes to stay within the limit of {}.",
+            self.verified.len(),
+            self.max_cached_nodes
+        );
+        self.verified.clear();
+        for frame in stack {
+            self.verified.insert(frame.trie_key, true);
+        }
+    }
+
+    /// Fails with the problem in failfast mode, otherwise logs it and adds it
+    /// to the summary.
+    fn record(&self, summary: &mut RootSummary, problem: TrieProblem) -> Result<(), Error> {
+        if self.failfast {
+            return Err(Error::Node(problem));
+        }
+        warn!("Invalid trie node: {}", problem);
+        summary.problems.push(problem);
+        Ok(())
+    }
+}
+
+fn pointer_digest(pointer: Pointer) -> Digest {
+    match pointer {
+        Pointer::LeafPointer(digest) | Pointer::NodePointer(digest) => digest,
+    }
+}
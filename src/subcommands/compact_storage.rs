@@ -0,0 +1,227 @@
+mod copy;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs,
+    io::Error as IoError,
+    path::{Path, PathBuf},
+};
+
+use bincode::Error as BincodeError;
+use casper_node::types::BlockHash;
+use clap::{Arg, ArgMatches, Command};
+use lmdb::{Error as LmdbError, Transaction};
+use log::info;
+use thiserror::Error as ThisError;
+
+use crate::{
+    common::{
+        block_index::{self, BlockIndex},
+        db::{self, DeserializationError, STORAGE_FILE_NAME},
+    },
+    subcommands::{integer_arg, trie_compact::RetainedBlocks},
+};
+
+pub(crate) use copy::{CopyStats, StorageCopier};
+
+pub const COMMAND_NAME: &str = "compact-storage";
+const DB_PATH: &str = "db-path";
+const FROM_HEIGHT: &str = "from-height";
+const KEEP_ERA_BOUNDARIES: &str = "keep-era-boundaries";
+const KEEP_LAST: &str = "keep-last";
+const OUTPUT: &str = "output";
+const TO_HEIGHT: &str = "to-height";
+
+/// Number of blocks copied per write transaction.
+const BLOCK_BATCH_SIZE: usize = 1_000;
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Error (de)serializing items with bincode: {0}")]
+    Bincode(#[from] BincodeError),
+    #[error("Error indexing the blocks: {0}")]
+    BlockIndex(#[from] block_index::Error),
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+    #[error("Invalid height window: \"--from-height\" {0} is above \"--to-height\" {1}")]
+    InvalidHeightWindow(u64, u64),
+    #[error("Storage database not found at {0}")]
+    MissingStorage(PathBuf),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
+    #[error("Output storage database already exists at {0}")]
+    OutputExists(PathBuf),
+    #[error("Error parsing element {1} in {0} DB: {2}")]
+    Parsing(String, String, DeserializationError),
+}
+
+enum DisplayOrder {
+    DbPath,
+    Output,
+    KeepLast,
+    FromHeight,
+    ToHeight,
+    KeepEraBoundaries,
+}
+
+pub fn command(display_order: usize) -> Command<'static> {
+    Command::new(COMMAND_NAME)
+        .display_order(display_order)
+        .about(
+            "Writes a new `storage.lmdb` file with only the blocks in a height window, along \
+            with their bodies, finality signatures, transfers, deploys, finalized approvals \
+            and the execution results of these deploys in the retained blocks.",
+        )
+        .arg(
+            Arg::new(DB_PATH)
+                .display_order(DisplayOrder::DbPath as usize)
+                .required(true)
+                .short('d')
+                .long(DB_PATH)
+                .takes_value(true)
+                .value_name("DB_PATH")
+                .help("Path of the directory with the source `storage.lmdb` file."),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .required(true)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("OUTPUT_DB_PATH")
+                .help(
+                    "Path of the directory where the compacted `storage.lmdb` file will be \
+                    created. The file must not exist.",
+                ),
+        )
+        .arg(
+            Arg::new(KEEP_LAST)
+                .display_order(DisplayOrder::KeepLast as usize)
+                .long(KEEP_LAST)
+                .takes_value(true)
+                .value_name("BLOCK_COUNT")
+                .help("Only retain the given number of most recent blocks."),
+        )
+        .arg(
+            Arg::new(FROM_HEIGHT)
+                .display_order(DisplayOrder::FromHeight as usize)
+                .long(FROM_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .help("Only retain the blocks at or above the given height."),
+        )
+        .arg(
+            Arg::new(TO_HEIGHT)
+                .display_order(DisplayOrder::ToHeight as usize)
+                .long(TO_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .help("Only retain the blocks at or below the given height."),
+        )
+        .arg(
+            Arg::new(KEEP_ERA_BOUNDARIES)
+                .display_order(DisplayOrder::KeepEraBoundaries as usize)
+                .long(KEEP_ERA_BOUNDARIES)
+                .takes_value(false)
+                .help("Also retain all switch blocks, including those outside the height window."),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let path = Path::new(matches.value_of(DB_PATH).expect("should have db-path arg"));
+    let output = Path::new(matches.value_of(OUTPUT).expect("should have output arg"));
+    let retained = RetainedBlocks {
+        keep_last: integer_arg(matches, KEEP_LAST),
+        from_height: integer_arg(matches, FROM_HEIGHT),
+        to_height: integer_arg(matches, TO_HEIGHT),
+        keep_era_boundaries: matches.is_present(KEEP_ERA_BOUNDARIES),
+    };
+    if let (Some(from_height), Some(to_height)) = (retained.from_height, retained.to_height) {
+        if from_height > to_height {
+            return Err(Error::InvalidHeightWindow(from_height, to_height));
+        }
+    }
+
+    let stats = compact_storage(path, output, retained)?;
+    info!(
+        "Copied {} blocks and {} deploys, {} deploys were missing from the source and {} \
+        execution results of blocks which weren't retained were dropped.",
+        stats.blocks, stats.deploys, stats.missing_deploys, stats.dropped_execution_results
+    );
+    Ok(())
+}
+
+/// Returns the hashes of the retained blocks, from the lowest to the highest.
+fn retained_blocks(index: &BlockIndex, retained: &RetainedBlocks) -> Vec<BlockHash> {
+    let highest_height = match index.highest_height() {
+        Some(highest_height) => highest_height,
+        None => return vec![],
+    };
+    let window = retained.window(highest_height);
+    index
+        .blocks_in(0..=highest_height)
+        .filter(|(height, block)| {
+            window
+                .as_ref()
+                .map_or(false, |window| window.contains(height))
+                || (retained.keep_era_boundaries && block.is_switch_block)
+        })
+        .map(|(_height, block)| block.block_hash)
+        .collect()
+}
+
+/// Copies the retained blocks of the `storage.lmdb` file in `db_path` and
+/// the entries related to them into a new `storage.lmdb` file in `output`.
+pub(crate) fn compact_storage<P1: AsRef<Path>, P2: AsRef<Path>>(
+    db_path: P1,
+    output: P2,
+    retained: RetainedBlocks,
+) -> Result<CopyStats, Error> {
+    let source_path = db_path.as_ref().join(STORAGE_FILE_NAME);
+    let source_env = match db::existing_db_env(&source_path)? {
+        Some(source_env) => source_env,
+        None => return Err(Error::MissingStorage(source_path)),
+    };
+    let destination_path = output.as_ref().join(STORAGE_FILE_NAME);
+    if destination_path.exists() {
+        return Err(Error::OutputExists(destination_path));
+    }
+    fs::create_dir_all(&output)?;
+
+    let destination_env = db::db_env(&destination_path)?;
+
+    let source_txn = source_env.begin_ro_txn()?;
+    let index = BlockIndex::read(&source_txn)?;
+    let block_hashes = retained_blocks(&index, &retained);
+    info!(
+        "Copying {} blocks from {} to {}.",
+        block_hashes.len(),
+        source_path.to_string_lossy(),
+        destination_path.to_string_lossy()
+    );
+
+    let mut copier = StorageCopier::new(
+        &source_txn,
+        &destination_env,
+        block_hashes.iter().copied().collect(),
+    )?;
+    for (batch_idx, batch) in block_hashes.chunks(BLOCK_BATCH_SIZE).enumerate() {
+        let mut txn = destination_env.begin_rw_txn()?;
+        for block_hash in batch {
+            copier.copy_block(&mut txn, block_hash)?;
+        }
+        txn.commit()?;
+        info!(
+            "Copied {} of {} blocks...",
+            batch_idx * BLOCK_BATCH_SIZE + batch.len(),
+            block_hashes.len()
+        );
+    }
+    let stats = copier.stats;
+    source_txn.commit()?;
+    destination_env.sync(true)?;
+    info!("Storage compaction complete.");
+    Ok(stats)
+}
@@ -0,0 +1,275 @@
+use std::collections::{HashMap, HashSet};
+
+use lmdb::{
+    Database as LmdbDatabase, DatabaseFlags, Environment, Error as LmdbError, RoTransaction,
+    RwTransaction, Transaction, WriteFlags,
+};
+
+use casper_hashing::Digest;
+use casper_node::types::{BlockHash, BlockHeader, DeployMetadata};
+use casper_types::{bytesrepr::FromBytes, DeployHash as RawDeployHash};
+
+use crate::{
+    common::{
+        db::{
+            BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase, BlockMetadataDatabase,
+            Database, DeployDatabase, DeployHashesDatabase, DeployMetadataDatabase,
+            DeserializationError, FinalizedApprovalsDatabase, ProposerDatabase, TransferDatabase,
+            TransferHashesDatabase,
+        },
+        lmdb_utils,
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+};
+
+use super::Error;
+
+/// Entries copied to the compacted storage.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CopyStats {
+    pub(crate) blocks: usize,
+    pub(crate) deploys: usize,
+    /// Deploys of the retained blocks which are missing from the source.
+    pub(crate) missing_deploys: usize,
+    /// Execution results removed from the metadata of the copied deploys, as
+    /// they belong to blocks which aren't retained.
+    pub(crate) dropped_execution_results: usize,
+}
+
+/// Returns the names of the databases whose entries related to the retained
+/// blocks are copied.
+pub(crate) fn copied_databases() -> [&'static str; 11] {
+    [
+        BlockHeaderDatabase::db_name(),
+        BlockBodyDatabase::db_name(),
+        BlockBodyMerkleDatabase::db_name(),
+        DeployHashesDatabase::db_name(),
+        TransferHashesDatabase::db_name(),
+        ProposerDatabase::db_name(),
+        BlockMetadataDatabase::db_name(),
+        TransferDatabase::db_name(),
+        DeployDatabase::db_name(),
+        DeployMetadataDatabase::db_name(),
+        FinalizedApprovalsDatabase::db_name(),
+    ]
+}
+
+fn parsing_err(db_name: &str, key: &Digest, error: DeserializationError) -> Error {
+    Error::Parsing(db_name.to_string(), key.to_string(), error)
+}
+
+/// Copies the entries related to the retained blocks from the source storage
+/// to the destination, each at most once.
+pub(crate) struct StorageCopier<'a> {
+    source_txn: &'a RoTransaction<'a>,
+    /// Handles of the copied databases in the source and in the destination.
+    /// Databases missing from the source, in older versions of the storage
+    /// schema, aren't created in the destination.
+    dbs: HashMap<&'static str, (LmdbDatabase, LmdbDatabase)>,
+    retained_blocks: HashSet<BlockHash>,
+    copied_bodies: HashSet<Digest>,
+    copied_deploys: HashSet<Digest>,
+    pub(crate) stats: CopyStats,
+}
+
+impl<'a> StorageCopier<'a> {
+    /// Creates the copied databases of the source in the destination.
+    pub(crate) fn new(
+        source_txn: &'a RoTransaction<'a>,
+        destination_env: &Environment,
+        retained_blocks: HashSet<BlockHash>,
+    ) -> Result<Self, LmdbError> {
+        let mut dbs = HashMap::new();
+        for db_name in copied_databases() {
+            if let Some(source_db) = lmdb_utils::open_db_if_present(source_txn, db_name)? {
+                let destination_db =
+                    destination_env.create_db(Some(db_name), DatabaseFlags::empty())?;
+                dbs.insert(db_name, (source_db, destination_db));
+            }
+        }
+        Ok(Self {
+            source_txn,
+            dbs,
+            retained_blocks,
+            copied_bodies: HashSet::new(),
+            copied_deploys: HashSet::new(),
+            stats: CopyStats::default(),
+        })
+    }
+
+    /// Copies the entry under `key` if it's in the source, and returns its
+    /// value.
+    fn copy_entry(
+        &self,
+        txn: &mut RwTransaction,
+        db_name: &str,
+        key: &Digest,
+    ) -> Result<Option<&'a [u8]>, LmdbError> {
+        let (source_db, destination_db) = match self.dbs.get(db_name) {
+            Some(dbs) => *dbs,
+            None => return Ok(None),
+        };
+        let value = match self.source_txn.get(source_db, key) {
+            Ok(value) => value,
+            Err(LmdbError::NotFound) => return Ok(None),
+            Err(lmdb_err) => return Err(lmdb_err),
+        };
+        txn.put(destination_db, key, &value, WriteFlags::empty())?;
+        Ok(Some(value))
+    }
+
+    /// Copies a block header along with the body, transfers, finality
+    /// signatures and deploys of the block. Returns the state root hash of
+    /// the block.
+    pub(crate) fn copy_block(
+        &mut self,
+        txn: &mut RwTransaction,
+        block_hash: &BlockHash,
+    ) -> Result<Digest, Error> {
+        let raw_header = self
+            .copy_entry(txn, BlockHeaderDatabase::db_name(), block_hash.inner())?
+            .ok_or(LmdbError::NotFound)?;
+        let header: BlockHeader = bincode::deserialize(raw_header).map_err(|bincode_err| {
+            parsing_err(
+                BlockHeaderDatabase::db_name(),
+                block_hash.inner(),
+                bincode_err.into(),
+            )
+        })?;
+        self.copy_entry(txn, TransferDatabase::db_name(), block_hash.inner())?;
+        self.copy_entry(txn, BlockMetadataDatabase::db_name(), block_hash.inner())?;
+        self.stats.blocks += 1;
+
+        let body_hash = *header.body_hash();
+        // Blocks may share a body, whose deploys were already copied.
+        if self.copied_bodies.insert(body_hash) {
+            for deploy_hash in self.copy_body(txn, &body_hash)? {
+                self.copy_deploy(txn, &deploy_hash)?;
+            }
+        }
+        Ok(*header.state_root_hash())
+    }
+
+    /// Copies a block body, either whole or as the Merkle linked list of its
+    /// parts, and returns the hashes of its deploys and transfers.
+    fn copy_body(&self, txn: &mut RwTransaction, body_hash: &Digest) -> Result<Vec<Digest>, Error> {
+        if let Some(raw_body) = self.copy_entry(txn, BlockBodyDatabase::db_name(), body_hash)? {
+            let body: BlockBody = bincode::deserialize(raw_body).map_err(|bincode_err| {
+                parsing_err(BlockBodyDatabase::db_name(), body_hash, bincode_err.into())
+            })?;
+            return Ok(body
+                .deploy_hashes()
+                .iter()
+                .chain(body.transfer_hashes())
+                .map(|deploy_hash| *deploy_hash.inner())
+                .collect());
+        }
+
+        let mut deploy_hashes = vec![];
+        let mut visited_nodes = HashSet::new();
+        let mut merkle_node = *body_hash;
+        while let Some(raw_node) =
+            self.copy_entry(txn, BlockBodyMerkleDatabase::db_name(), &merkle_node)?
+        {
+            if !visited_nodes.insert(merkle_node) {
+                break;
+            }
+            let (value_hash, next_node): (Digest, Digest) = FromBytes::from_bytes(raw_node)
+                .map(|(node, _remainder)| node)
+                .map_err(|bytesrepr_err| {
+                    parsing_err(
+                        BlockBodyMerkleDatabase::db_name(),
+                        &merkle_node,
+                        bytesrepr_err.into(),
+                    )
+                })?;
+            // The values of the list are the hashes of the body parts: the
+            // deploy hashes, the transfer hashes and the proposer.
+            self.copy_entry(txn, ProposerDatabase::db_name(), &value_hash)?;
+            for db_name in [
+                DeployHashesDatabase::db_name(),
+                TransferHashesDatabase::db_name(),
+            ] {
+                if let Some(raw_hashes) = self.copy_entry(txn, db_name, &value_hash)? {
+                    let part: Vec<RawDeployHash> = FromBytes::from_bytes(raw_hashes)
+                        .map(|(part, _remainder)| part)
+                        .map_err(|bytesrepr_err| {
+                            parsing_err(db_name, &value_hash, bytesrepr_err.into())
+                        })?;
+                    deploy_hashes.extend(
+                        part.into_iter()
+                            .map(|deploy_hash| Digest::from(deploy_hash.value())),
+                    );
+                }
+            }
+            merkle_node = next_node;
+        }
+        Ok(deploy_hashes)
+    }
+
+    /// Copies a deploy, its finalized approvals and its metadata, keeping
+    /// only the execution results of the retained blocks. Results already in
+    /// the destination, as when appending to it, are kept too.
+    fn copy_deploy(&mut self, txn: &mut RwTransaction, deploy_hash: &Digest) -> Result<(), Error> {
+        if !self.copied_deploys.insert(*deploy_hash) {
+            return Ok(());
+        }
+        match self.copy_entry(txn, DeployDatabase::db_name(), deploy_hash)? {
+            Some(_) => self.stats.deploys += 1,
+            None => self.stats.missing_deploys += 1,
+        }
+        self.copy_entry(txn, FinalizedApprovalsDatabase::db_name(), deploy_hash)?;
+
+        let (source_db, destination_db) = match self.dbs.get(DeployMetadataDatabase::db_name()) {
+            Some(dbs) => *dbs,
+            None => return Ok(()),
+        };
+        let raw_metadata = match self.source_txn.get(source_db, deploy_hash) {
+            Ok(raw_metadata) => raw_metadata,
+            Err(LmdbError::NotFound) => return Ok(()),
+            Err(lmdb_err) => return Err(lmdb_err.into()),
+        };
+        let mut metadata: DeployMetadata =
+            bincode::deserialize(raw_metadata).map_err(|bincode_err| {
+                parsing_err(
+                    DeployMetadataDatabase::db_name(),
+                    deploy_hash,
+                    bincode_err.into(),
+                )
+            })?;
+        let execution_results = metadata.execution_results.len();
+        let retained_blocks = &self.retained_blocks;
+        metadata
+            .execution_results
+            .retain(|block_hash, _| retained_blocks.contains(block_hash));
+        self.stats.dropped_execution_results +=
+            execution_results - metadata.execution_results.len();
+        match txn.get(destination_db, deploy_hash) {
+            Ok(raw_existing_metadata) => {
+                let existing_metadata: DeployMetadata = bincode::deserialize(raw_existing_metadata)
+                    .map_err(|bincode_err| {
+                        parsing_err(
+                            DeployMetadataDatabase::db_name(),
+                            deploy_hash,
+                            bincode_err.into(),
+                        )
+                    })?;
+                for (block_hash, execution_result) in existing_metadata.execution_results {
+                    metadata
+                        .execution_results
+                        .entry(block_hash)
+                        .or_insert(execution_result);
+                }
+            }
+            Err(LmdbError::NotFound) => {}
+            Err(lmdb_err) => return Err(lmdb_err.into()),
+        }
+        txn.put(
+            destination_db,
+            deploy_hash,
+            &bincode::serialize(&metadata)?,
+            WriteFlags::empty(),
+        )?;
+        Ok(())
+    }
+}
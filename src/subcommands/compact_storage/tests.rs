@@ -0,0 +1,277 @@
+use std::slice;
+
+use casper_node::types::{BlockHash, DeployHash, DeployMetadata};
+use casper_types::{bytesrepr::ToBytes, PublicKey};
+use lmdb::{Error as LmdbError, Transaction, WriteFlags};
+
+use crate::{
+    common::db::{
+        self, BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase, Database,
+        DeployDatabase, DeployHashesDatabase, DeployMetadataDatabase, ProposerDatabase,
+        TransferHashesDatabase, STORAGE_FILE_NAME,
+    },
+    subcommands::{execution_results_summary::block_body::BlockBody, trie_compact::RetainedBlocks},
+    test_utils::{mock_block_header, mock_deploy_hash, mock_deploy_metadata, LmdbTestFixture},
+};
+
+use super::{compact_storage, copy::CopyStats, Error};
+
+#[test]
+fn compact_storage_should_keep_retained_blocks() {
+    const BLOCK_COUNT: u8 = 3;
+    // Deploy included in the first and the last blocks.
+    const SHARED_DEPLOY: u8 = 9;
+
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            DeployDatabase::db_name(),
+            DeployMetadataDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    );
+    let blocks: Vec<_> = (0..BLOCK_COUNT)
+        .map(|idx| {
+            let (block_hash, mut block_header) = mock_block_header(idx);
+            block_header.height = idx as u64;
+            (block_hash, block_header)
+        })
+        .collect();
+    let block_hashes: Vec<BlockHash> = blocks.iter().map(|(block_hash, _)| *block_hash).collect();
+    let shared_deploy = mock_deploy_hash(SHARED_DEPLOY);
+
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for (idx, (block_hash, block_header)) in blocks.iter().enumerate() {
+        let deploy_hash = mock_deploy_hash(idx as u8);
+        let mut deploy_hashes = vec![deploy_hash];
+        if idx == 0 || idx == BLOCK_COUNT as usize - 1 {
+            deploy_hashes.push(shared_deploy);
+        }
+        txn.put(
+            *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap(),
+            block_hash,
+            &bincode::serialize(block_header).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.put(
+            *fixture.db(Some(BlockBodyDatabase::db_name())).unwrap(),
+            &block_header.body_hash,
+            &bincode::serialize(&BlockBody::new(deploy_hashes)).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.put(
+            *fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+            &deploy_hash,
+            &[idx as u8],
+            WriteFlags::empty(),
+        )
+        .unwrap();
+        txn.put(
+            *fixture.db(Some(DeployMetadataDatabase::db_name())).unwrap(),
+            &deploy_hash,
+            &bincode::serialize(&mock_deploy_metadata(slice::from_ref(block_hash))).unwrap(),
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    }
+    txn.put(
+        *fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+        &shared_deploy,
+        &[SHARED_DEPLOY],
+        WriteFlags::empty(),
+    )
+    .unwrap();
+    txn.put(
+        *fixture.db(Some(DeployMetadataDatabase::db_name())).unwrap(),
+        &shared_deploy,
+        &bincode::serialize(&mock_deploy_metadata(&[
+            block_hashes[0],
+            block_hashes[BLOCK_COUNT as usize - 1],
+        ]))
+        .unwrap(),
+        WriteFlags::empty(),
+    )
+    .unwrap();
+    txn.commit().unwrap();
+
+    let output_dir = tempfile::tempdir().unwrap();
+    let retained = RetainedBlocks {
+        keep_last: Some(2),
+        ..Default::default()
+    };
+    let stats = compact_storage(fixture.tmp_dir.path(), output_dir.path(), retained).unwrap();
+    assert_eq!(
+        stats,
+        CopyStats {
+            blocks: 2,
+            deploys: 3,
+            missing_deploys: 0,
+            dropped_execution_results: 1,
+        }
+    );
+
+    let env = db::db_env(output_dir.path().join(STORAGE_FILE_NAME)).unwrap();
+    let txn = env.begin_ro_txn().unwrap();
+    let header_db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name())).unwrap() };
+    let deploy_db = unsafe { txn.open_db(Some(DeployDatabase::db_name())).unwrap() };
+    let metadata_db = unsafe {
+        txn.open_db(Some(DeployMetadataDatabase::db_name()))
+            .unwrap()
+    };
+    assert_eq!(
+        txn.get(header_db, &block_hashes[0]).unwrap_err(),
+        LmdbError::NotFound
+    );
+    assert!(txn.get(header_db, &block_hashes[1]).is_ok());
+    assert!(txn.get(header_db, &block_hashes[2]).is_ok());
+    let copied_deploys: Vec<DeployHash> = [0, 1, 2, SHARED_DEPLOY]
+        .into_iter()
+        .map(mock_deploy_hash)
+        .filter(|deploy_hash| txn.get(deploy_db, deploy_hash).is_ok())
+        .collect();
+    assert_eq!(
+        copied_deploys,
+        vec![
+            mock_deploy_hash(1),
+            mock_deploy_hash(2),
+            mock_deploy_hash(SHARED_DEPLOY)
+        ]
+    );
+    let shared_metadata: DeployMetadata =
+        bincode::deserialize(txn.get(metadata_db, &shared_deploy).unwrap()).unwrap();
+    assert_eq!(
+        shared_metadata
+            .execution_results
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        vec![block_hashes[BLOCK_COUNT as usize - 1]]
+    );
+    txn.commit().unwrap();
+}
+
+#[test]
+fn compact_storage_should_copy_merkle_body_parts() {
+    let fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+            DeployHashesDatabase::db_name(),
+            TransferHashesDatabase::db_name(),
+            ProposerDatabase::db_name(),
+            DeployDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    );
+    let deploy_hash = mock_deploy_hash(1);
+    let transfer_hash = mock_deploy_hash(2);
+    let mut body = BlockBody::new(vec![deploy_hash]);
+    body.transfer_hashes = vec![transfer_hash];
+    let (block_hash, mut block_header) = mock_block_header(0);
+    block_header.body_hash = body.merkle_root().unwrap();
+    let [deploy_hashes_hash, transfer_hashes_hash, proposer_hash] = body.part_hashes().unwrap();
+    let merkle_nodes = body.merkle_nodes();
+
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    let mut put = |db_name: &str, key: &[u8], value: &[u8]| {
+        txn.put(
+            *fixture.db(Some(db_name)).unwrap(),
+            &key,
+            &value,
+            WriteFlags::empty(),
+        )
+        .unwrap();
+    };
+    put(
+        BlockHeaderDatabase::db_name(),
+        block_hash.as_ref(),
+        &bincode::serialize(&block_header).unwrap(),
+    );
+    // The body is only stored as the Merkle linked list of its parts.
+    for (node_key, node) in &merkle_nodes {
+        put(
+            BlockBodyMerkleDatabase::db_name(),
+            node_key.as_ref(),
+            &node.to_bytes().unwrap(),
+        );
+    }
+    put(
+        DeployHashesDatabase::db_name(),
+        deploy_hashes_hash.as_ref(),
+        &body.deploy_hashes.to_bytes().unwrap(),
+    );
+    put(
+        TransferHashesDatabase::db_name(),
+        transfer_hashes_hash.as_ref(),
+        &body.transfer_hashes.to_bytes().unwrap(),
+    );
+    put(
+        ProposerDatabase::db_name(),
+        proposer_hash.as_ref(),
+        &PublicKey::System.to_bytes().unwrap(),
+    );
+    put(DeployDatabase::db_name(), deploy_hash.as_ref(), &[1]);
+    put(DeployDatabase::db_name(), transfer_hash.as_ref(), &[2]);
+    txn.commit().unwrap();
+
+    let output_dir = tempfile::tempdir().unwrap();
+    let stats = compact_storage(
+        fixture.tmp_dir.path(),
+        output_dir.path(),
+        RetainedBlocks::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        stats,
+        CopyStats {
+            blocks: 1,
+            deploys: 2,
+            missing_deploys: 0,
+            dropped_execution_results: 0,
+        }
+    );
+
+    let env = db::db_env(output_dir.path().join(STORAGE_FILE_NAME)).unwrap();
+    let txn = env.begin_ro_txn().unwrap();
+    let copied = |db_name: &str, key: &[u8]| {
+        let db = unsafe { txn.open_db(Some(db_name)).unwrap() };
+        txn.get(db, &key).is_ok()
+    };
+    assert_eq!(merkle_nodes.len(), 3);
+    for (node_key, _node) in &merkle_nodes {
+        assert!(copied(
+            BlockBodyMerkleDatabase::db_name(),
+            node_key.as_ref()
+        ));
+    }
+    assert!(copied(
+        DeployHashesDatabase::db_name(),
+        deploy_hashes_hash.as_ref()
+    ));
+    assert!(copied(
+        TransferHashesDatabase::db_name(),
+        transfer_hashes_hash.as_ref()
+    ));
+    assert!(copied(ProposerDatabase::db_name(), proposer_hash.as_ref()));
+    assert!(copied(DeployDatabase::db_name(), deploy_hash.as_ref()));
+    assert!(copied(DeployDatabase::db_name(), transfer_hash.as_ref()));
+    txn.commit().unwrap();
+}
+
+#[test]
+fn compact_storage_should_fail_without_storage() {
+    let source_dir = tempfile::tempdir().unwrap();
+    let output_dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        compact_storage(
+            source_dir.path(),
+            output_dir.path(),
+            RetainedBlocks::default()
+        ),
+        Err(Error::MissingStorage(_))
+    ));
+}
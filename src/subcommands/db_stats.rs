@@ -0,0 +1,164 @@
+mod stats;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Error as IoError, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Arg, ArgMatches, Command};
+use lmdb::Error as LmdbError;
+use log::warn;
+use serde_json::Error as SerializationError;
+use thiserror::Error as ThisError;
+
+use crate::common::db::{self, STORAGE_FILE_NAME, TRIE_STORE_FILE_NAME};
+
+use stats::StatsReport;
+
+pub const COMMAND_NAME: &str = "db-stats";
+const DB_PATH: &str = "db-path";
+const FORMAT: &str = "format";
+const NO_HISTOGRAMS: &str = "no-histograms";
+const OUTPUT: &str = "output";
+const OVERWRITE: &str = "overwrite";
+
+const FORMAT_JSON: &str = "json";
+const FORMAT_TABLE: &str = "table";
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Error reading statistics of {0}: {1}")]
+    Database(String, LmdbError),
+    #[error("Neither `storage.lmdb` nor `data.lmdb` found in {0}")]
+    NoDatabase(PathBuf),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] SerializationError),
+}
+
+enum DisplayOrder {
+    DbPath,
+    Format,
+    NoHistograms,
+    Output,
+    Overwrite,
+}
+
+pub fn command(display_order: usize) -> Command<'static> {
+    Command::new(COMMAND_NAME)
+        .display_order(display_order)
+        .about(
+            "Outputs the entry count, tree depth, page counts, estimated size and key and value \
+            size histograms of every database in `storage.lmdb` and `data.lmdb`, along with \
+            the map size, last page used and free pages of each file.",
+        )
+        .arg(
+            Arg::new(DB_PATH)
+                .display_order(DisplayOrder::DbPath as usize)
+                .required(true)
+                .short('d')
+                .long(DB_PATH)
+                .takes_value(true)
+                .value_name("DB_PATH")
+                .help("Path of the directory with the `storage.lmdb` and `data.lmdb` files."),
+        )
+        .arg(
+            Arg::new(FORMAT)
+                .display_order(DisplayOrder::Format as usize)
+                .long(FORMAT)
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values([FORMAT_TABLE, FORMAT_JSON])
+                .default_value(FORMAT_TABLE)
+                .help("Format of the statistics."),
+        )
+        .arg(
+            Arg::new(NO_HISTOGRAMS)
+                .display_order(DisplayOrder::NoHistograms as usize)
+                .long(NO_HISTOGRAMS)
+                .takes_value(false)
+                .help(
+                    "Skip the key and value size histograms, which require reading every \
+                    entry, and only report the statistics kept by LMDB.",
+                ),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .help(
+                    "Path to where the program will output the statistics. \
+                    If unspecified, defaults to standard output.",
+                ),
+        )
+        .arg(
+            Arg::new(OVERWRITE)
+                .display_order(DisplayOrder::Overwrite as usize)
+                .required(false)
+                .short('w')
+                .long(OVERWRITE)
+                .takes_value(false)
+                .requires(OUTPUT)
+                .help(
+                    "Overwrite an already existing output file in destination \
+                    directory.",
+                ),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let path = Path::new(matches.value_of(DB_PATH).expect("should have db-path arg"));
+    let output = matches.value_of(OUTPUT).map(Path::new);
+    let overwrite = matches.is_present(OVERWRITE);
+    let histograms = !matches.is_present(NO_HISTOGRAMS);
+    let json = matches.value_of(FORMAT) == Some(FORMAT_JSON);
+
+    // Collected first so that failing doesn't leave an empty output file.
+    let report = collect_stats(path, histograms)?;
+    let mut out_writer: Box<dyn Write> = match output {
+        Some(out_path) => Box::new(
+            OpenOptions::new()
+                .create_new(!overwrite)
+                .write(true)
+                .open(out_path)?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    if json {
+        serde_json::to_writer_pretty(out_writer, &report)?;
+    } else {
+        stats::write_table(&report, &mut out_writer)?;
+    }
+    Ok(())
+}
+
+/// Collects the statistics of the storage and trie store files found in the
+/// directory.
+fn collect_stats<P: AsRef<Path>>(path: P, histograms: bool) -> Result<StatsReport, Error> {
+    let mut report = StatsReport::default();
+    for file_name in [STORAGE_FILE_NAME, TRIE_STORE_FILE_NAME] {
+        let file_path = path.as_ref().join(file_name);
+        let db_err = |lmdb_err| Error::Database(file_name.to_string(), lmdb_err);
+        let env = match db::existing_db_env(&file_path).map_err(db_err)? {
+            Some(env) => env,
+            None => {
+                warn!("No {} in {}, skipping.", file_name, path.as_ref().display());
+                continue;
+            }
+        };
+        report
+            .environments
+            .push(stats::env_stats(&env, file_name, histograms).map_err(db_err)?);
+    }
+    if report.environments.is_empty() {
+        return Err(Error::NoDatabase(path.as_ref().to_path_buf()));
+    }
+    Ok(report)
+}
@@ -0,0 +1,231 @@
+use std::{io::Write, result::Result};
+
+use lmdb::{Cursor, Environment, Error as LmdbError, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{lmdb_utils, schema};
+
+/// Count of the sizes falling in `[min_size, max_size]`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct HistogramBucket {
+    pub(crate) min_size: usize,
+    pub(crate) max_size: usize,
+    pub(crate) count: usize,
+}
+
+/// Histogram of sizes in power of two buckets, only the non-empty buckets
+/// being kept.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct SizeHistogram {
+    pub(crate) total_bytes: u64,
+    pub(crate) min_size: usize,
+    pub(crate) max_size: usize,
+    pub(crate) buckets: Vec<HistogramBucket>,
+}
+
+impl SizeHistogram {
+    pub(crate) fn add(&mut self, size: usize) {
+        if self.buckets.is_empty() || size < self.min_size {
+            self.min_size = size;
+        }
+        self.max_size = self.max_size.max(size);
+        self.total_bytes += size as u64;
+
+        let bits = usize::BITS - size.leading_zeros();
+        let (min_size, max_size) = match bits {
+            0 => (0, 0),
+            _ => (1usize << (bits - 1), usize::MAX >> (usize::BITS - bits)),
+        };
+        match self
+            .buckets
+            .binary_search_by_key(&min_size, |bucket| bucket.min_size)
+        {
+            Ok(idx) => self.buckets[idx].count += 1,
+            Err(idx) => self.buckets.insert(
+                idx,
+                HistogramBucket {
+                    min_size,
+                    max_size,
+                    count: 1,
+                },
+            ),
+        }
+    }
+
+    /// Returns the number of sizes added.
+    pub(crate) fn count(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.count).sum()
+    }
+
+    /// Returns the average size, or 0 if no size was added.
+    pub(crate) fn average(&self) -> u64 {
+        match self.count() {
+            0 => 0,
+            count => self.total_bytes / count as u64,
+        }
+    }
+}
+
+/// Statistics of a named database.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct DbStats {
+    pub(crate) db_name: String,
+    pub(crate) entries: usize,
+    pub(crate) depth: u32,
+    pub(crate) branch_pages: usize,
+    pub(crate) leaf_pages: usize,
+    pub(crate) overflow_pages: usize,
+    /// Size of all the pages of the database.
+    pub(crate) estimated_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) key_sizes: Option<SizeHistogram>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) value_sizes: Option<SizeHistogram>,
+}
+
+/// Statistics of an LMDB environment and its named databases.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct EnvStats {
+    pub(crate) file_name: String,
+    pub(crate) map_size: usize,
+    pub(crate) page_size: u32,
+    pub(crate) last_page: usize,
+    pub(crate) free_pages: usize,
+    pub(crate) databases: Vec<DbStats>,
+}
+
+/// Statistics of all the environments in a database directory.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct StatsReport {
+    pub(crate) environments: Vec<EnvStats>,
+}
+
+/// Collects the statistics of the environment and every named database in it.
+/// With `histograms`, every entry is read to build the histograms of the key
+/// and value sizes.
+pub(crate) fn env_stats(
+    env: &Environment,
+    file_name: &str,
+    histograms: bool,
+) -> Result<EnvStats, LmdbError> {
+    let db_names = schema::database_names(env)?;
+    let info = lmdb_utils::env_info(env)?;
+    let page_size = env.stat()?.page_size();
+    let txn = env.begin_ro_txn()?;
+    let mut stats = EnvStats {
+        file_name: file_name.to_string(),
+        map_size: info.me_mapsize,
+        page_size,
+        last_page: info.me_last_pgno,
+        free_pages: lmdb_utils::free_page_count(&txn)?,
+        databases: vec![],
+    };
+    for db_name in db_names {
+        let db = unsafe { txn.open_db(Some(&db_name))? };
+        let stat = lmdb_utils::db_stat(&txn, db)?;
+        let page_count = stat.ms_branch_pages + stat.ms_leaf_pages + stat.ms_overflow_pages;
+        let mut db_stats = DbStats {
+            db_name,
+            entries: stat.ms_entries,
+            depth: stat.ms_depth,
+            branch_pages: stat.ms_branch_pages,
+            leaf_pages: stat.ms_leaf_pages,
+            overflow_pages: stat.ms_overflow_pages,
+            estimated_bytes: page_count as u64 * page_size as u64,
+            key_sizes: None,
+            value_sizes: None,
+        };
+        if histograms {
+            let mut key_sizes = SizeHistogram::default();
+            let mut value_sizes = SizeHistogram::default();
+            let mut cursor = txn.open_ro_cursor(db)?;
+            for (raw_key, raw_value) in cursor.iter() {
+                key_sizes.add(raw_key.len());
+                value_sizes.add(raw_value.len());
+            }
+            db_stats.key_sizes = Some(key_sizes);
+            db_stats.value_sizes = Some(value_sizes);
+        }
+        stats.databases.push(db_stats);
+    }
+    txn.commit()?;
+    Ok(stats)
+}
+
+fn format_histogram(histogram: &SizeHistogram) -> String {
+    histogram
+        .buckets
+        .iter()
+        .map(|bucket| format!("{}-{}: {}", bucket.min_size, bucket.max_size, bucket.count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes the report as plain text tables, one per environment.
+pub(crate) fn write_table<W: Write + ?Sized>(
+    report: &StatsReport,
+    out_writer: &mut W,
+) -> Result<(), std::io::Error> {
+    for env_stats in &report.environments {
+        writeln!(
+            out_writer,
+            "{}: map size {} bytes, page size {} bytes, last page {}, {} free pages",
+            env_stats.file_name,
+            env_stats.map_size,
+            env_stats.page_size,
+            env_stats.last_page,
+            env_stats.free_pages
+        )?;
+        writeln!(
+            out_writer,
+            "{:<24} {:>12} {:>5} {:>10} {:>12} {:>12} {:>16} {:>8} {:>10}",
+            "DATABASE",
+            "ENTRIES",
+            "DEPTH",
+            "BRANCH",
+            "LEAF",
+            "OVERFLOW",
+            "EST. BYTES",
+            "AVG KEY",
+            "AVG VALUE"
+        )?;
+        for db_stats in &env_stats.databases {
+            let average = |histogram: &Option<SizeHistogram>| {
+                histogram
+                    .as_ref()
+                    .map_or_else(|| "-".to_string(), |sizes| sizes.average().to_string())
+            };
+            writeln!(
+                out_writer,
+                "{:<24} {:>12} {:>5} {:>10} {:>12} {:>12} {:>16} {:>8} {:>10}",
+                db_stats.db_name,
+                db_stats.entries,
+                db_stats.depth,
+                db_stats.branch_pages,
+                db_stats.leaf_pages,
+                db_stats.overflow_pages,
+                db_stats.estimated_bytes,
+                average(&db_stats.key_sizes),
+                average(&db_stats.value_sizes)
+            )?;
+        }
+        for db_stats in &env_stats.databases {
+            if let (Some(key_sizes), Some(value_sizes)) =
+                (db_stats.key_sizes.as_ref(), db_stats.value_sizes.as_ref())
+            {
+                if key_sizes.count() == 0 {
+                    continue;
+                }
+                writeln!(out_writer, "{}:", db_stats.db_name)?;
+                writeln!(out_writer, "  key sizes   {}", format_histogram(key_sizes))?;
+                writeln!(
+                    out_writer,
+                    "  value sizes {}",
+                    format_histogram(value_sizes)
+                )?;
+            }
+        }
+        writeln!(out_writer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use lmdb::{Transaction, WriteFlags};
+
+use crate::{
+    common::db::{STORAGE_FILE_NAME, TRIE_STORE_FILE_NAME},
+    test_utils::LmdbTestFixture,
+};
+
+use super::{
+    collect_stats, command, run,
+    stats::{self, HistogramBucket, SizeHistogram},
+    Error, COMMAND_NAME,
+};
+
+#[test]
+fn size_histogram_buckets() {
+    let mut histogram = SizeHistogram::default();
+    for size in [0, 1, 3, 2, 32, 33, 63, 64] {
+        histogram.add(size);
+    }
+    assert_eq!(histogram.min_size, 0);
+    assert_eq!(histogram.max_size, 64);
+    assert_eq!(histogram.total_bytes, 198);
+    assert_eq!(histogram.count(), 8);
+    assert_eq!(histogram.average(), 24);
+    let bucket = |min_size, max_size, count| HistogramBucket {
+        min_size,
+        max_size,
+        count,
+    };
+    assert_eq!(
+        histogram.buckets,
+        vec![
+            bucket(0, 0, 1),
+            bucket(1, 1, 1),
+            bucket(2, 3, 2),
+            bucket(32, 63, 3),
+            bucket(64, 127, 1),
+        ]
+    );
+    assert_eq!(SizeHistogram::default().average(), 0);
+}
+
+#[test]
+fn env_stats_should_describe_named_dbs() {
+    let fixture = LmdbTestFixture::new(vec!["small", "large"], Some(STORAGE_FILE_NAME));
+    let small_db = *fixture.db(Some("small")).unwrap();
+    let large_db = *fixture.db(Some("large")).unwrap();
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for idx in 0..10u8 {
+        txn.put(small_db, &[idx; 4], &[idx; 10], WriteFlags::empty())
+            .unwrap();
+    }
+    // Stored in overflow pages.
+    txn.put(large_db, &[0u8; 32], &[0u8; 10_000], WriteFlags::empty())
+        .unwrap();
+    txn.commit().unwrap();
+
+    let env_stats = stats::env_stats(&fixture.env, STORAGE_FILE_NAME, true).unwrap();
+    assert_eq!(env_stats.file_name, STORAGE_FILE_NAME);
+    assert!(env_stats.last_page > 0);
+    let db_names: Vec<&str> = env_stats
+        .databases
+        .iter()
+        .map(|db_stats| db_stats.db_name.as_str())
+        .collect();
+    assert_eq!(db_names, vec!["large", "small"]);
+
+    let large_stats = &env_stats.databases[0];
+    assert_eq!(large_stats.entries, 1);
+    assert!(large_stats.overflow_pages > 0);
+    assert_eq!(
+        large_stats.estimated_bytes,
+        (large_stats.leaf_pages + large_stats.overflow_pages) as u64 * env_stats.page_size as u64
+    );
+    assert_eq!(large_stats.value_sizes.as_ref().unwrap().max_size, 10_000);
+
+    let small_stats = &env_stats.databases[1];
+    assert_eq!(small_stats.entries, 10);
+    assert_eq!(small_stats.depth, 1);
+    assert_eq!(small_stats.overflow_pages, 0);
+    let key_sizes = small_stats.key_sizes.as_ref().unwrap();
+    assert_eq!(key_sizes.total_bytes, 40);
+    assert_eq!(key_sizes.buckets.len(), 1);
+    assert_eq!(small_stats.value_sizes.as_ref().unwrap().average(), 10);
+
+    let env_stats = stats::env_stats(&fixture.env, STORAGE_FILE_NAME, false).unwrap();
+    assert!(env_stats.databases[0].key_sizes.is_none());
+}
+
+#[test]
+fn deleted_entries_should_free_pages() {
+    let fixture = LmdbTestFixture::new(vec!["db"], Some(STORAGE_FILE_NAME));
+    let db = *fixture.db(Some("db")).unwrap();
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for idx in 0..100u8 {
+        txn.put(db, &[idx; 32], &[idx; 1_000], WriteFlags::empty())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    txn.clear_db(db).unwrap();
+    txn.commit().unwrap();
+
+    let env_stats = stats::env_stats(&fixture.env, STORAGE_FILE_NAME, true).unwrap();
+    assert!(env_stats.free_pages > 0);
+    assert_eq!(env_stats.databases[0].entries, 0);
+}
+
+#[test]
+fn missing_files_should_be_skipped() {
+    let fixture = LmdbTestFixture::new(vec!["db"], Some(STORAGE_FILE_NAME));
+    let report = collect_stats(fixture.tmp_dir.path(), false).unwrap();
+    assert_eq!(report.environments.len(), 1);
+    assert_eq!(report.environments[0].file_name, STORAGE_FILE_NAME);
+    assert!(!fixture.tmp_dir.path().join(TRIE_STORE_FILE_NAME).exists());
+
+    let empty_dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        collect_stats(empty_dir.path(), false),
+        Err(Error::NoDatabase(_))
+    ));
+}
+
+#[test]
+fn failed_run_should_not_create_output() {
+    let empty_dir = tempfile::tempdir().unwrap();
+    let output = empty_dir.path().join("stats.json");
+    let matches = command(0).get_matches_from([
+        COMMAND_NAME,
+        "-d",
+        empty_dir.path().to_str().unwrap(),
+        "-o",
+        output.to_str().unwrap(),
+    ]);
+    assert!(matches!(run(&matches), Err(Error::NoDatabase(_))));
+    assert!(!output.exists());
+}
@@ -0,0 +1,102 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Error as IoError, Write},
+    path::Path,
+};
+
+use clap::{Arg, ArgMatches, Command};
+use lmdb::Error as LmdbError;
+use serde_json::Error as SerializationError;
+use thiserror::Error as ThisError;
+
+use crate::common::{
+    db::{self, STORAGE_FILE_NAME},
+    schema::{self, Error as SchemaError},
+};
+
+pub const COMMAND_NAME: &str = "detect-version";
+const DB_PATH: &str = "db-path";
+const OUTPUT: &str = "output";
+const OVERWRITE: &str = "overwrite";
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
+    #[error("Error detecting schema: {0}")]
+    Schema(#[from] SchemaError),
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] SerializationError),
+}
+
+enum DisplayOrder {
+    DbPath,
+    Output,
+    Overwrite,
+}
+
+pub fn command(display_order: usize) -> Command<'static> {
+    Command::new(COMMAND_NAME)
+        .display_order(display_order)
+        .about(
+            "Outputs the storage schema generation of a database, along with the protocol \
+            versions of a sample of its blocks, in JSON format.",
+        )
+        .arg(
+            Arg::new(DB_PATH)
+                .display_order(DisplayOrder::DbPath as usize)
+                .required(true)
+                .short('d')
+                .long(DB_PATH)
+                .takes_value(true)
+                .value_name("DB_PATH")
+                .help("Path of the directory with the `storage.lmdb` file."),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .help(
+                    "Path to where the program will output the schema report. \
+                    If unspecified, defaults to standard output.",
+                ),
+        )
+        .arg(
+            Arg::new(OVERWRITE)
+                .display_order(DisplayOrder::Overwrite as usize)
+                .required(false)
+                .short('w')
+                .long(OVERWRITE)
+                .takes_value(false)
+                .requires(OUTPUT)
+                .help(
+                    "Overwrite an already existing output file in destination \
+                    directory.",
+                ),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let path = Path::new(matches.value_of(DB_PATH).expect("should have db-path arg"));
+    let output = matches.value_of(OUTPUT).map(Path::new);
+    let overwrite = matches.is_present(OVERWRITE);
+
+    let out_writer: Box<dyn Write> = match output {
+        Some(out_path) => Box::new(
+            OpenOptions::new()
+                .create_new(!overwrite)
+                .write(true)
+                .open(out_path)?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    let env = db::db_env(path.join(STORAGE_FILE_NAME))?;
+    let report = schema::detect_schema(&env)?;
+    serde_json::to_writer_pretty(out_writer, &report)?;
+    Ok(())
+}
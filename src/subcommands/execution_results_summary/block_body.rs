@@ -4,7 +4,10 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 
 use casper_hashing::Digest;
 use casper_node::types::DeployHash;
-use casper_types::PublicKey;
+use casper_types::{
+    bytesrepr::{Error as BytesreprError, ToBytes},
+    PublicKey,
+};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
 
@@ -34,6 +37,53 @@ impl BlockBody {
     pub(crate) fn deploy_hashes(&self) -> &Vec<DeployHash> {
         &self.deploy_hashes
     }
+
+    /// Retrieves the transfer hashes within the block.
+    pub(crate) fn transfer_hashes(&self) -> &Vec<DeployHash> {
+        &self.transfer_hashes
+    }
+
+    /// Hash of the whole serialized body, which identifies the bodies of
+    /// blocks created before protocol version 1.4.
+    pub(crate) fn hash_v1(&self) -> Result<Digest, BytesreprError> {
+        let mut serialized_body = self.proposer.to_bytes()?;
+        serialized_body.extend(self.deploy_hashes.to_bytes()?);
+        serialized_body.extend(self.transfer_hashes.to_bytes()?);
+        Ok(Digest::hash(&serialized_body))
+    }
+
+    #[cfg(test)]
+    /// Hashes of the serialized deploy hashes, transfer hashes and proposer,
+    /// in the order of the Merkle linked list of the body.
+    pub(crate) fn part_hashes(&self) -> Result<[Digest; 3], BytesreprError> {
+        Ok([
+            Digest::hash(&self.deploy_hashes.to_bytes()?),
+            Digest::hash(&self.transfer_hashes.to_bytes()?),
+            Digest::hash(&self.proposer.to_bytes()?),
+        ])
+    }
+
+    #[cfg(test)]
+    /// Root of the Merkle linked list of the body parts, which identifies the
+    /// bodies of blocks created from protocol version 1.4 on.
+    pub(crate) fn merkle_root(&self) -> Result<Digest, BytesreprError> {
+        Ok(Digest::hash_slice_rfold(&self.part_hashes()?))
+    }
+
+    #[cfg(test)]
+    /// Returns the nodes of the Merkle linked list of the body parts, each
+    /// keyed by the hash of the rest of the list and holding the hash of its
+    /// part along with the key of the next node.
+    pub(crate) fn merkle_nodes(&self) -> Vec<(Digest, (Digest, Digest))> {
+        let part_hashes = self.part_hashes().unwrap();
+        (0..part_hashes.len())
+            .map(|idx| {
+                let node_key = Digest::hash_slice_rfold(&part_hashes[idx..]);
+                let next_key = Digest::hash_slice_rfold(&part_hashes[idx + 1..]);
+                (node_key, (part_hashes[idx], next_key))
+            })
+            .collect()
+    }
 }
 
 impl Display for BlockBody {
@@ -1,4 +1,3 @@
-mod db_helpers;
 mod extract;
 mod global_state;
 mod storage;
@@ -7,36 +6,59 @@ mod tests;
 
 use std::{io::Error as IoError, path::Path};
 
-use bincode::Error as BincodeError;
 use casper_hashing::Digest;
 use casper_node::types::BlockHash;
+use casper_types::EraId;
 use clap::{Arg, ArgMatches, Command};
 use lmdb::Error as LmdbError;
 use thiserror::Error as ThisError;
 
+use crate::{
+    common::block_index::Error as BlockIndexError,
+    subcommands::{
+        compact_storage::Error as CompactStorageError, integer_arg,
+        latest_block_summary::Error as LatestBlockSummaryError,
+    },
+};
+
 use self::extract::SliceIdentifier;
 
 pub const COMMAND_NAME: &str = "extract-slice";
+const APPEND: &str = "append";
 const BLOCK_HASH: &str = "block-hash";
+const BLOCK_HEIGHT: &str = "block-height";
+const ERA: &str = "era";
+const FROM_HEIGHT: &str = "from-height";
+const LATEST: &str = "latest";
 const STATE_ROOT_HASH: &str = "state-root-hash";
 const OUTPUT: &str = "output";
 const SOURCE_DB_PATH: &str = "source-db-path";
+const THREADS: &str = "threads";
+const TO_HEIGHT: &str = "to-height";
 
 /// Errors encountered when running the `extract-slice` subcommand.
 #[derive(Debug, ThisError)]
 pub enum Error {
-    #[error("Error (de)serializing items with bincode: {0}")]
-    Bincode(#[from] BincodeError),
+    #[error("Ambiguous block height {0}, found blocks {1}")]
+    AmbiguousHeight(u64, String),
+    #[error("Error indexing the blocks: {0}")]
+    BlockIndex(#[from] BlockIndexError),
+    #[error("Error copying block data: {0}")]
+    CopyBlock(#[from] CompactStorageError),
     #[error("Error creating the destination execution engine: {0}")]
     CreateExecutionEngine(anyhow::Error),
     #[error("Error operating the database: {0}")]
     Database(#[from] LmdbError),
+    #[error("No blocks found in the source storage for the slice")]
+    EmptySlice,
+    #[error("Error finding the highest block: {0}")]
+    HighestBlock(#[from] LatestBlockSummaryError),
     #[error("Error loading the source execution engine: {0}")]
     LoadExecutionEngine(anyhow::Error),
+    #[error("No block found at height {0}")]
+    MissingHeight(u64),
     #[error("Error writing output: {0}")]
     Output(#[from] IoError),
-    #[error("Error parsing element for block hash {0} in {1} DB: {2}")]
-    Parsing(BlockHash, String, BincodeError),
     #[error("Error transferring state root: {0}")]
     StateRootTransfer(anyhow::Error),
 }
@@ -44,20 +66,27 @@ pub enum Error {
 enum DisplayOrder {
     SourceDbPath,
     Output,
+    Append,
     BlockHash,
+    BlockHeight,
+    Latest,
+    FromHeight,
+    ToHeight,
+    Era,
     StateRootHash,
+    Threads,
 }
 
 pub fn command(display_order: usize) -> Command<'static> {
     Command::new(COMMAND_NAME)
         .display_order(display_order)
         .about(
-            "Reads all data for a given block hash (block, deploys, execution \
+            "Reads all data for the given blocks (blocks, deploys, execution \
                 results, global state) from a storage directory and stores \
-                them to a new directory in two LMDB files. If a state root \
-                hash is provided instead of a block hash, only the global \
-                state under that root hash will be stored in the new \
-                directory",
+                them to a new directory in two LMDB files. Blocks are given \
+                by hash, by height, by height range or by era. If a state root hash is \
+                provided instead, only the global state under that root hash \
+                will be stored in the new directory",
         )
         .arg(
             Arg::new(SOURCE_DB_PATH)
@@ -82,7 +111,20 @@ pub fn command(display_order: usize) -> Command<'static> {
                 .help(
                     "Path of the directory where the program will output the \
                     two newly created `storage.lmdb` and `data.lmdb` files. \
-                    The directory must not exist when running this command.",
+                    The directory must not exist when running this command, \
+                    unless \"--append\" is given.",
+                ),
+        )
+        .arg(
+            Arg::new(APPEND)
+                .display_order(DisplayOrder::Append as usize)
+                .short('a')
+                .long(APPEND)
+                .takes_value(false)
+                .help(
+                    "Add the slice to the `storage.lmdb` and `data.lmdb` files \
+                    already in the output directory, keeping the execution \
+                    results of the deploys in the blocks extracted before.",
                 ),
         )
         .arg(
@@ -92,8 +134,72 @@ pub fn command(display_order: usize) -> Command<'static> {
                 .long(BLOCK_HASH)
                 .takes_value(true)
                 .value_name("BLOCK_HASH")
+                .multiple_occurrences(true)
+                .conflicts_with_all(&[
+                    BLOCK_HEIGHT,
+                    LATEST,
+                    FROM_HEIGHT,
+                    TO_HEIGHT,
+                    ERA,
+                    STATE_ROOT_HASH,
+                ])
+                .help(
+                    "Hash of a block in the slice. Can be repeated to extract \
+                    several blocks.",
+                ),
+        )
+        .arg(
+            Arg::new(BLOCK_HEIGHT)
+                .display_order(DisplayOrder::BlockHeight as usize)
+                .long(BLOCK_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .conflicts_with_all(&[LATEST, FROM_HEIGHT, TO_HEIGHT, ERA, STATE_ROOT_HASH])
+                .help(
+                    "Height of the block which defines the slice. Fails if \
+                    several blocks are stored at this height, listing them.",
+                ),
+        )
+        .arg(
+            Arg::new(LATEST)
+                .display_order(DisplayOrder::Latest as usize)
+                .long(LATEST)
+                .takes_value(false)
+                .conflicts_with_all(&[FROM_HEIGHT, TO_HEIGHT, ERA, STATE_ROOT_HASH])
+                .help("Use the highest block in storage to define the slice."),
+        )
+        .arg(
+            Arg::new(FROM_HEIGHT)
+                .display_order(DisplayOrder::FromHeight as usize)
+                .long(FROM_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .conflicts_with_all(&[ERA, STATE_ROOT_HASH])
+                .help(
+                    "Lowest height of the blocks in the slice. Defaults to the \
+                    lowest block in storage if only \"--to-height\" is given.",
+                ),
+        )
+        .arg(
+            Arg::new(TO_HEIGHT)
+                .display_order(DisplayOrder::ToHeight as usize)
+                .long(TO_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .conflicts_with_all(&[ERA, STATE_ROOT_HASH])
+                .help(
+                    "Highest height of the blocks in the slice. Defaults to the \
+                    highest block in storage if only \"--from-height\" is given.",
+                ),
+        )
+        .arg(
+            Arg::new(ERA)
+                .display_order(DisplayOrder::Era as usize)
+                .long(ERA)
+                .takes_value(true)
+                .value_name("ERA_ID")
                 .conflicts_with(STATE_ROOT_HASH)
-                .help("Hash of the block which defines the slice."),
+                .help("Era whose blocks are in the slice."),
         )
         .arg(
             Arg::new(STATE_ROOT_HASH)
@@ -104,6 +210,19 @@ pub fn command(display_order: usize) -> Command<'static> {
                 .value_name("STATE_ROOT_HASH")
                 .help("State root hash to be copied over to the new database."),
         )
+        .arg(
+            Arg::new(THREADS)
+                .display_order(DisplayOrder::Threads as usize)
+                .short('t')
+                .long(THREADS)
+                .takes_value(true)
+                .default_value("1")
+                .value_name("THREAD_COUNT")
+                .help(
+                    "Number of threads reading the global state from the source \
+                    `data.lmdb` file concurrently.",
+                ),
+        )
 }
 
 pub fn run(matches: &ArgMatches) -> Result<(), Error> {
@@ -113,24 +232,43 @@ pub fn run(matches: &ArgMatches) -> Result<(), Error> {
             .expect("should have db-path arg"),
     );
     let output = Path::new(matches.value_of(OUTPUT).expect("should have output arg"));
-    let slice_identifier = matches
-        .value_of(BLOCK_HASH)
-        .map(|block_hash_str| {
-            let block_hash: BlockHash = Digest::from_hex(block_hash_str)
-                .expect("should parse block hash to hex format")
-                .into();
-            SliceIdentifier::BlockHash(block_hash)
-        })
-        .unwrap_or_else(|| {
-            matches
-                .value_of(STATE_ROOT_HASH)
-                .map(|state_root_hash_str| {
-                    let state_root_hash = Digest::from_hex(state_root_hash_str)
-                        .expect("should parse state root hash to hex format");
-                    SliceIdentifier::StateRootHash(state_root_hash)
-                })
-                .expect("should have either BLOCK_HASH or STATE_ROOT_HASH arg")
-        });
+    let slice_identifier = if let Some(block_hash_strs) = matches.values_of(BLOCK_HASH) {
+        let block_hashes: Vec<BlockHash> = block_hash_strs
+            .map(|block_hash_str| {
+                Digest::from_hex(block_hash_str)
+                    .expect("should parse block hash to hex format")
+                    .into()
+            })
+            .collect();
+        SliceIdentifier::BlockHashes(block_hashes)
+    } else if let Some(block_height) = integer_arg(matches, BLOCK_HEIGHT) {
+        SliceIdentifier::BlockHeight(block_height)
+    } else if matches.is_present(LATEST) {
+        SliceIdentifier::Latest
+    } else if matches.is_present(FROM_HEIGHT) || matches.is_present(TO_HEIGHT) {
+        SliceIdentifier::HeightRange {
+            from_height: integer_arg(matches, FROM_HEIGHT),
+            to_height: integer_arg(matches, TO_HEIGHT),
+        }
+    } else if let Some(era_id) = integer_arg(matches, ERA) {
+        SliceIdentifier::Era(EraId::new(era_id))
+    } else {
+        let state_root_hash = matches
+            .value_of(STATE_ROOT_HASH)
+            .map(|state_root_hash_str| {
+                Digest::from_hex(state_root_hash_str)
+                    .expect("should parse state root hash to hex format")
+            })
+            .expect(
+                "should have either BLOCK_HASH, BLOCK_HEIGHT, LATEST, a height range, ERA or \
+                STATE_ROOT_HASH arg",
+            );
+        SliceIdentifier::StateRootHash(state_root_hash)
+    };
+
+    let threads = integer_arg(matches, THREADS).expect("should have threads arg");
+
+    let append = matches.is_present(APPEND);
 
-    extract::extract_slice(path, output, slice_identifier)
+    extract::extract_slice(path, output, slice_identifier, threads, append)
 }
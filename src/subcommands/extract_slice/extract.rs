@@ -1,27 +1,111 @@
-use std::path::Path;
+use std::{collections::HashSet, path::Path};
 
 use casper_hashing::Digest;
 use casper_node::types::BlockHash;
+use casper_types::EraId;
 
 use super::{global_state, storage, Error};
 
 pub enum SliceIdentifier {
-    BlockHash(BlockHash),
+    BlockHashes(Vec<BlockHash>),
+    BlockHeight(u64),
+    /// The highest block in storage.
+    Latest,
+    /// Blocks between two heights, inclusive. Bounds default to the lowest
+    /// and highest blocks in storage.
+    HeightRange {
+        from_height: Option<u64>,
+        to_height: Option<u64>,
+    },
+    Era(EraId),
     StateRootHash(Digest),
 }
 
+/// Returns the hashes of the blocks in the slice. Blocks selected by height
+/// or era are ordered by height, and include every block stored at each
+/// height.
+pub(super) fn block_hashes<P: AsRef<Path>>(
+    db_path: P,
+    slice_identifier: SliceIdentifier,
+) -> Result<Vec<BlockHash>, Error> {
+    let block_hashes = match slice_identifier {
+        SliceIdentifier::BlockHashes(block_hashes) => block_hashes,
+        SliceIdentifier::BlockHeight(block_height) => {
+            let index = storage::read_block_index(db_path)?;
+            let block_hashes: Vec<BlockHash> = index
+                .blocks_in(block_height..=block_height)
+                .map(|(_height, block)| block.block_hash)
+                .collect();
+            // Forks or orphaned headers leave several blocks at the same
+            // height, and which one is meant can't be told.
+            if block_hashes.len() > 1 {
+                let candidates = block_hashes
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                return Err(Error::AmbiguousHeight(block_height, candidates));
+            }
+            if block_hashes.is_empty() {
+                return Err(Error::MissingHeight(block_height));
+            }
+            block_hashes
+        }
+        SliceIdentifier::Latest => vec![storage::highest_block_hash(db_path)?],
+        SliceIdentifier::HeightRange {
+            from_height,
+            to_height,
+        } => {
+            let index = storage::read_block_index(db_path)?;
+            let heights = from_height.unwrap_or(0)..=to_height.unwrap_or(u64::MAX);
+            index
+                .blocks_in(heights)
+                .map(|(_height, block)| block.block_hash)
+                .collect()
+        }
+        SliceIdentifier::Era(era_id) => {
+            let index = storage::read_block_index(db_path)?;
+            index
+                .blocks_in(0..=u64::MAX)
+                .filter(|(_height, block)| block.era_id == era_id)
+                .map(|(_height, block)| block.block_hash)
+                .collect()
+        }
+        SliceIdentifier::StateRootHash(_) => {
+            unreachable!("a state root hash doesn't identify blocks")
+        }
+    };
+    if block_hashes.is_empty() {
+        return Err(Error::EmptySlice);
+    }
+    Ok(block_hashes)
+}
+
 pub fn extract_slice<P1: AsRef<Path>, P2: AsRef<Path>>(
     db_path: P1,
     output: P2,
     slice_identifier: SliceIdentifier,
+    threads: usize,
+    append: bool,
 ) -> Result<(), Error> {
-    storage::create_output_db(&output)?;
-    let state_root_hash = match slice_identifier {
-        SliceIdentifier::BlockHash(block_hash) => {
-            storage::transfer_block_info(&db_path, &output, block_hash)?
+    let state_root_hashes = match slice_identifier {
+        SliceIdentifier::StateRootHash(state_root_hash) => {
+            storage::create_output_db(&output, append)?;
+            vec![state_root_hash]
+        }
+        slice_identifier => {
+            let block_hashes = block_hashes(&db_path, slice_identifier)?;
+            storage::create_output_db(&output, append)?;
+            // Consecutive blocks often share their state root.
+            let mut transferred = HashSet::new();
+            storage::transfer_block_info(&db_path, &output, &block_hashes)?
+                .into_iter()
+                .filter(|state_root_hash| transferred.insert(*state_root_hash))
+                .collect()
         }
-        SliceIdentifier::StateRootHash(state_root_hash) => state_root_hash,
     };
-    global_state::transfer_global_state(&db_path, &output, state_root_hash)?;
+    // Trie nodes already in an appended output are skipped along with their
+    // descendants.
+    global_state::transfer_global_state(&db_path, &output, &state_root_hashes, threads)?;
     Ok(())
 }
@@ -3,22 +3,25 @@ use std::{path::Path, result::Result};
 use casper_hashing::Digest;
 use log::info;
 
-use crate::subcommands::trie_compact::{
-    copy_state_root, create_execution_engine, load_execution_engine, DEFAULT_MAX_DB_SIZE,
+use crate::{
+    common::db::DEFAULT_MAX_DB_SIZE,
+    subcommands::trie_compact::{
+        copy_state_root, create_execution_engine, load_execution_engine, BatchOptions,
+    },
 };
 
 use super::Error;
 
-/// Transfers the global state under a state root hash from a trie store to a
-/// new one.
+/// Transfers the global state under the state root hashes from a trie store
+/// to a new one, reading the source with `threads` threads. Nodes shared by
+/// several state roots are copied once.
 pub(crate) fn transfer_global_state<P1: AsRef<Path>, P2: AsRef<Path>>(
     source: P1,
     destination: P2,
-    state_root_hash: Digest,
+    state_root_hashes: &[Digest],
+    threads: usize,
 ) -> Result<(), Error> {
-    let max_db_size = DEFAULT_MAX_DB_SIZE
-        .parse()
-        .expect("should be able to parse max db size");
+    let max_db_size = DEFAULT_MAX_DB_SIZE;
 
     // Load the source trie store.
     let (source_state, _env) = load_execution_engine(source, max_db_size, Digest::default(), true)
@@ -26,11 +29,20 @@ pub(crate) fn transfer_global_state<P1: AsRef<Path>, P2: AsRef<Path>>(
     // Create the destination trie store.
     let (destination_state, _env) = create_execution_engine(destination, max_db_size, true)
         .map_err(Error::CreateExecutionEngine)?;
-    info!("Starting transfer process for state root hash {state_root_hash}");
-    // Copy the state root along with missing descendants over to the new trie
-    // store.
-    copy_state_root(state_root_hash, &source_state, &destination_state)
+    for state_root_hash in state_root_hashes {
+        info!("Starting transfer process for state root hash {state_root_hash}");
+        // Copy the state root along with missing descendants over to the new
+        // trie store.
+        copy_state_root(
+            *state_root_hash,
+            &source_state,
+            &destination_state,
+            BatchOptions::default(),
+            threads,
+            None,
+        )
         .map_err(Error::StateRootTransfer)?;
+    }
     destination_state.flush_environment()?;
 
     Ok(())
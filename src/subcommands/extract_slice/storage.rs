@@ -1,23 +1,28 @@
 use std::{fs, io::ErrorKind, path::Path, result::Result};
 
 use casper_hashing::Digest;
-use lmdb::{DatabaseFlags, Error as LmdbError, Transaction};
+use lmdb::{DatabaseFlags, Transaction};
 
-use casper_node::types::{BlockHash, BlockHeader, DeployMetadata};
-use log::info;
+use casper_node::types::BlockHash;
+use log::{info, warn};
 
 use crate::{
-    common::db::{
-        self, BlockBodyDatabase, BlockHeaderDatabase, Database, DeployDatabase,
-        DeployMetadataDatabase, TransferDatabase, STORAGE_FILE_NAME,
+    common::{
+        block_index::BlockIndex,
+        db::{
+            self, BlockBodyDatabase, BlockHeaderDatabase, Database, DeployDatabase,
+            DeployMetadataDatabase, TransferDatabase, STORAGE_FILE_NAME,
+        },
     },
-    subcommands::execution_results_summary::block_body::BlockBody,
+    subcommands::{compact_storage::StorageCopier, latest_block_summary},
 };
 
-use super::{db_helpers, Error};
+use super::Error;
 
-pub(crate) fn create_output_db<P: AsRef<Path>>(output_path: P) -> Result<(), Error> {
-    if output_path.as_ref().exists() {
+/// Creates the storage database in the output directory. Unless `append` is
+/// set, the directory must not exist.
+pub(crate) fn create_output_db<P: AsRef<Path>>(output_path: P, append: bool) -> Result<(), Error> {
+    if output_path.as_ref().exists() && !append {
         return Err(Error::Output(ErrorKind::AlreadyExists.into()));
     }
     fs::create_dir_all(&output_path)?;
@@ -37,107 +42,70 @@ pub(crate) fn create_output_db<P: AsRef<Path>>(output_path: P) -> Result<(), Err
     Ok(())
 }
 
-/// Given a block hash, reads the information related to the associated block
-/// (block header, block body, deploys, transfers, execution results) and
-/// copies them over to a new database. Returns the state root hash associated
-/// with the block.
+/// Indexes the blocks of the source storage by height.
+pub(crate) fn read_block_index<P: AsRef<Path>>(source: P) -> Result<BlockIndex, Error> {
+    let source_env = db::db_env(source.as_ref().join(STORAGE_FILE_NAME))?;
+    let txn = source_env.begin_ro_txn()?;
+    let index = BlockIndex::read(&txn)?;
+    txn.commit()?;
+    Ok(index)
+}
+
+/// Finds the hash of the highest block of the source storage.
+pub(crate) fn highest_block_hash<P: AsRef<Path>>(source: P) -> Result<BlockHash, Error> {
+    let source_env = db::db_env(source.as_ref().join(STORAGE_FILE_NAME))?;
+    let (block_hash, _block_header) = latest_block_summary::get_highest_block(&source_env, true)?;
+    Ok(block_hash)
+}
+
+/// Given block hashes, reads the information related to the associated
+/// blocks (block header, block body or its Merkle entries, finality
+/// signatures, deploys, finalized approvals, transfers, execution results)
+/// and copies them over to a new database. Returns the state root hashes
+/// associated with the blocks, in the same order.
 pub(crate) fn transfer_block_info<P1: AsRef<Path>, P2: AsRef<Path>>(
     source: P1,
     destination: P2,
-    block_hash: BlockHash,
-) -> Result<Digest, Error> {
+    block_hashes: &[BlockHash],
+) -> Result<Vec<Digest>, Error> {
     let source_path = source.as_ref().join(STORAGE_FILE_NAME);
     let source_env = db::db_env(&source_path)?;
     let destination_path = destination.as_ref().join(STORAGE_FILE_NAME);
     let destination_env = db::db_env(&destination_path)?;
 
-    let mut source_txn = source_env.begin_ro_txn()?;
-    let mut destination_txn = destination_env.begin_rw_txn()?;
-
     info!(
-        "Initiating block information transfer from {} to {} for block {block_hash}",
+        "Initiating block information transfer from {} to {} for {} blocks",
         source_path.to_string_lossy(),
-        destination_path.to_string_lossy()
+        destination_path.to_string_lossy(),
+        block_hashes.len()
     );
 
-    // Read the block header associated with the given block hash.
-    let block_header_bytes = db_helpers::transfer_to_new_db(
-        &mut source_txn,
-        &mut destination_txn,
-        BlockHeaderDatabase::db_name(),
-        &block_hash,
-    )?;
-    info!("Successfully transferred block header");
-    let block_header: BlockHeader = bincode::deserialize(&block_header_bytes)?;
-
-    // Read the block body associated with the previously read block header.
-    let block_body_bytes = db_helpers::transfer_to_new_db(
-        &mut source_txn,
-        &mut destination_txn,
-        BlockBodyDatabase::db_name(),
-        block_header.body_hash(),
+    let source_txn = source_env.begin_ro_txn()?;
+    // Only the execution results of the blocks in the slice are copied.
+    let mut copier = StorageCopier::new(
+        &source_txn,
+        &destination_env,
+        block_hashes.iter().copied().collect(),
     )?;
-    info!("Successfully transferred block body");
-    let block_body: BlockBody = bincode::deserialize(&block_body_bytes)?;
-
-    // Attempt to copy over all entries in the transfer database for the given
-    // block hash. If we have no entry under the block hash, we move on.
-    match db_helpers::transfer_to_new_db(
-        &mut source_txn,
-        &mut destination_txn,
-        TransferDatabase::db_name(),
-        &block_hash,
-    ) {
-        Ok(_) => info!("Found transfers in the source DB and successfully transferred them"),
-        Err(LmdbError::NotFound) => info!("No transfers found in the source DB"),
-        Err(lmdb_error) => return Err(Error::Database(lmdb_error)),
+    let mut destination_txn = destination_env.begin_rw_txn()?;
+    let mut state_root_hashes = vec![];
+    for block_hash in block_hashes {
+        state_root_hashes.push(copier.copy_block(&mut destination_txn, block_hash)?);
+        info!("Successfully transferred block {block_hash}");
     }
-
-    // Copy over all the deploys in this block and construct the execution
-    // results to be stored in the new database.
-    let deploy_metadata_db =
-        unsafe { source_txn.open_db(Some(DeployMetadataDatabase::db_name()))? };
-    for deploy_hash in block_body.deploy_hashes() {
-        // Copy the deploy to the new database.
-        db_helpers::transfer_to_new_db(
-            &mut source_txn,
-            &mut destination_txn,
-            DeployDatabase::db_name(),
-            deploy_hash,
-        )?;
-        info!("Successfully transferred deploy {deploy_hash}");
-
-        // Get this deploy's metadata.
-        let metadata_raw = source_txn.get(deploy_metadata_db, &deploy_hash)?;
-        let mut metadata: DeployMetadata =
-            bincode::deserialize(metadata_raw).map_err(|bincode_err| {
-                Error::Parsing(
-                    block_hash,
-                    DeployMetadataDatabase::db_name().to_string(),
-                    bincode_err,
-                )
-            })?;
-        // Extract the execution result of this deploy for this block.
-        if let Some(execution_result) = metadata.execution_results.remove(&block_hash) {
-            // Construct the metadata to be stored using only the relevant
-            // execution results.
-            let mut new_metadata = DeployMetadata::default();
-            new_metadata
-                .execution_results
-                .insert(block_hash, execution_result.clone());
-            let serialized_new_metadata = bincode::serialize(&new_metadata)?;
-            db_helpers::write_to_db(
-                &mut destination_txn,
-                DeployMetadataDatabase::db_name(),
-                deploy_hash,
-                &serialized_new_metadata,
-            )?;
-            info!("Successfully transferred execution results for {deploy_hash}");
-        }
+    let stats = copier.stats;
+    if stats.missing_deploys > 0 {
+        warn!(
+            "{} deploys of the slice are missing from the source DB",
+            stats.missing_deploys
+        );
     }
     // Commit the transactions.
-    source_txn.commit()?;
     destination_txn.commit()?;
-    info!("Storage transfer complete");
-    Ok(*block_header.state_root_hash())
+    source_txn.commit()?;
+    info!(
+        "Storage transfer complete, transferred {} deploys",
+        stats.deploys
+    );
+    Ok(state_root_hashes)
 }
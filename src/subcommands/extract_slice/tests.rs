@@ -1,4 +1,4 @@
-use std::slice;
+use std::{collections::HashSet, slice};
 
 use casper_execution_engine::storage::{
     store::StoreExt,
@@ -8,26 +8,70 @@ use casper_execution_engine::storage::{
 };
 use casper_hashing::Digest;
 use casper_node::types::{BlockHash, DeployHash, DeployMetadata};
-use casper_types::bytesrepr::{Bytes, ToBytes};
-use lmdb::{DatabaseFlags, Error as LmdbError, Transaction, WriteFlags};
+use casper_types::{
+    bytesrepr::{Bytes, ToBytes},
+    PublicKey,
+};
+use lmdb::{
+    DatabaseFlags, Error as LmdbError, RoTransaction, RwTransaction, Transaction, WriteFlags,
+};
 
 use crate::{
     common::db::{
-        BlockBodyDatabase, BlockHeaderDatabase, Database, DeployDatabase, DeployMetadataDatabase,
-        TransferDatabase, STORAGE_FILE_NAME,
+        self, BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase,
+        BlockMetadataDatabase, Database, DeployDatabase, DeployHashesDatabase,
+        DeployMetadataDatabase, FinalizedApprovalsDatabase, ProposerDatabase, TransferDatabase,
+        TransferHashesDatabase, DEFAULT_MAX_DB_SIZE, STORAGE_FILE_NAME,
     },
     subcommands::{
         execution_results_summary::block_body::BlockBody,
-        extract_slice::{db_helpers, global_state, storage},
-        trie_compact::{
-            create_execution_engine, load_execution_engine, tests::create_data, DEFAULT_MAX_DB_SIZE,
+        extract_slice::{
+            extract::{self, SliceIdentifier},
+            global_state, storage, Error,
         },
+        trie_compact::{create_execution_engine, load_execution_engine, tests::create_data},
     },
     test_utils::{
         mock_block_header, mock_deploy_hash, mock_deploy_metadata, LmdbTestFixture, MockBlockHeader,
     },
 };
 
+/// Reads the value under a key in a database using the given LMDB transaction.
+fn read_from_db<K: AsRef<[u8]>>(
+    txn: &mut RoTransaction,
+    db_name: &str,
+    key: &K,
+) -> Result<Vec<u8>, LmdbError> {
+    let db = unsafe { txn.open_db(Some(db_name))? };
+    let value = txn.get(db, key)?.to_vec();
+    Ok(value)
+}
+
+/// Writes a key-value pair in a database using the given LMDB transaction.
+fn write_to_db<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+    txn: &mut RwTransaction,
+    db_name: &str,
+    key: &K,
+    value: &V,
+) -> Result<(), LmdbError> {
+    let db = unsafe { txn.open_db(Some(db_name))? };
+    txn.put(db, key, value, WriteFlags::empty())?;
+    Ok(())
+}
+
+/// Copies the value under a key from the source database to the destination
+/// database and returns the raw value bytes.
+fn transfer_to_new_db<K: AsRef<[u8]>>(
+    source_txn: &mut RoTransaction,
+    destination_txn: &mut RwTransaction,
+    db_name: &str,
+    key: &K,
+) -> Result<Vec<u8>, LmdbError> {
+    let value = read_from_db(source_txn, db_name, key)?;
+    write_to_db(destination_txn, db_name, key, &value)?;
+    Ok(value)
+}
+
 #[test]
 fn transfer_data_between_dbs() {
     const DATA_COUNT: usize = 4;
@@ -59,12 +103,11 @@ fn transfer_data_between_dbs() {
     {
         let mut source_txn = source_fixture.env.begin_ro_txn().unwrap();
         assert_eq!(
-            db_helpers::read_from_db(&mut source_txn, MOCK_DB_NAME, &0usize.to_le_bytes()).unwrap(),
+            read_from_db(&mut source_txn, MOCK_DB_NAME, &0usize.to_le_bytes()).unwrap(),
             bincode::serialize(&deploy_hashes[0]).unwrap()
         );
         assert_eq!(
-            db_helpers::read_from_db(&mut source_txn, MOCK_DB_NAME, &DATA_COUNT.to_le_bytes())
-                .unwrap_err(),
+            read_from_db(&mut source_txn, MOCK_DB_NAME, &DATA_COUNT.to_le_bytes()).unwrap_err(),
             LmdbError::NotFound
         );
         source_txn.commit().unwrap();
@@ -73,7 +116,7 @@ fn transfer_data_between_dbs() {
     {
         let mut destination_txn = destination_fixture.env.begin_rw_txn().unwrap();
         let serialized_deploy_hash = bincode::serialize(&deploy_hashes[1]).unwrap();
-        assert!(db_helpers::write_to_db(
+        assert!(write_to_db(
             &mut destination_txn,
             MOCK_DB_NAME,
             &1usize.to_le_bytes(),
@@ -87,7 +130,7 @@ fn transfer_data_between_dbs() {
         let mut source_txn = source_fixture.env.begin_ro_txn().unwrap();
         let mut destination_txn = destination_fixture.env.begin_rw_txn().unwrap();
         let serialized_deploy_hash = bincode::serialize(&deploy_hashes[2]).unwrap();
-        let copied_bytes = db_helpers::transfer_to_new_db(
+        let copied_bytes = transfer_to_new_db(
             &mut source_txn,
             &mut destination_txn,
             MOCK_DB_NAME,
@@ -96,7 +139,7 @@ fn transfer_data_between_dbs() {
         .unwrap();
         assert_eq!(serialized_deploy_hash, copied_bytes);
         assert_eq!(
-            db_helpers::transfer_to_new_db(
+            transfer_to_new_db(
                 &mut source_txn,
                 &mut destination_txn,
                 MOCK_DB_NAME,
@@ -242,13 +285,13 @@ fn transfer_blocks() {
 
     let block_hash_0 = block_headers[0].0;
     let expected_state_root_hash = block_headers[0].1.state_root_hash;
-    let actual_state_root_hash = storage::transfer_block_info(
+    let actual_state_root_hashes = storage::transfer_block_info(
         source_fixture.tmp_dir.path(),
         destination_fixture.tmp_dir.path(),
-        block_hash_0,
+        &[block_hash_0],
     )
     .unwrap();
-    assert_eq!(expected_state_root_hash, actual_state_root_hash);
+    assert_eq!(vec![expected_state_root_hash], actual_state_root_hashes);
 
     {
         let txn = destination_fixture.env.begin_ro_txn().unwrap();
@@ -347,13 +390,13 @@ fn transfer_blocks() {
     }
 
     let expected_state_root_hash = block_headers[1].1.state_root_hash;
-    let actual_state_root_hash = storage::transfer_block_info(
+    let actual_state_root_hashes = storage::transfer_block_info(
         source_fixture.tmp_dir.path(),
         destination_fixture.tmp_dir.path(),
-        block_hash_1,
+        &[block_hash_1],
     )
     .unwrap();
-    assert_eq!(expected_state_root_hash, actual_state_root_hash);
+    assert_eq!(vec![expected_state_root_hash], actual_state_root_hashes);
 
     {
         let txn = destination_fixture.env.begin_ro_txn().unwrap();
@@ -420,7 +463,16 @@ fn transfer_blocks() {
                 .execution_results
                 .remove(&block_hash_1)
                 .is_some());
-            assert!(actual_deploy_metadata.execution_results.is_empty());
+            // Results of deploys also in block 0, which was transferred
+            // before, are merged rather than overwritten.
+            assert!(actual_deploy_metadata
+                .execution_results
+                .keys()
+                .all(|block_hash| *block_hash == block_hash_0));
+            assert_eq!(
+                actual_deploy_metadata.execution_results.is_empty(),
+                !block_bodies[0].deploy_hashes().contains(deploy_hash)
+            );
         }
 
         assert_eq!(
@@ -441,9 +493,7 @@ fn transfer_blocks() {
 fn transfer_global_state_information() {
     let source_tmp_dir = tempfile::tempdir().unwrap();
     let destination_tmp_dir = tempfile::tempdir().unwrap();
-    let max_db_size = DEFAULT_MAX_DB_SIZE
-        .parse()
-        .expect("should be able to parse max db size");
+    let max_db_size = DEFAULT_MAX_DB_SIZE;
     let source_env = LmdbEnvironment::new(source_tmp_dir.path(), max_db_size, 512, true).unwrap();
     let source_store = LmdbTrieStore::new(&source_env, None, DatabaseFlags::empty()).unwrap();
     // Construct mock data.
@@ -468,7 +518,8 @@ fn transfer_global_state_information() {
     global_state::transfer_global_state(
         source_tmp_dir.path(),
         destination_tmp_dir.path(),
-        data[4].0,
+        &[data[4].0],
+        1,
     )
     .unwrap();
 
@@ -500,3 +551,360 @@ fn transfer_global_state_information() {
     source_tmp_dir.close().unwrap();
     destination_tmp_dir.close().unwrap();
 }
+
+#[test]
+fn transfer_several_blocks() {
+    const BLOCK_COUNT: usize = 2;
+
+    let db_names = vec![
+        BlockHeaderDatabase::db_name(),
+        BlockBodyDatabase::db_name(),
+        DeployMetadataDatabase::db_name(),
+        DeployDatabase::db_name(),
+        TransferDatabase::db_name(),
+    ];
+    let source_fixture = LmdbTestFixture::new(db_names.clone(), Some(STORAGE_FILE_NAME));
+
+    // Deploy 1 is executed in both blocks.
+    let deploy_hashes: Vec<DeployHash> = (0..3).map(mock_deploy_hash).collect();
+    let block_headers: Vec<(BlockHash, MockBlockHeader)> =
+        (0..BLOCK_COUNT as u8).map(mock_block_header).collect();
+    let block_bodies = vec![
+        BlockBody::new(vec![deploy_hashes[0], deploy_hashes[1]]),
+        BlockBody::new(vec![deploy_hashes[1], deploy_hashes[2]]),
+    ];
+    let deploy_metadatas = vec![
+        mock_deploy_metadata(slice::from_ref(&block_headers[0].0)),
+        mock_deploy_metadata(&[block_headers[0].0, block_headers[1].0]),
+        mock_deploy_metadata(slice::from_ref(&block_headers[1].0)),
+    ];
+
+    {
+        let mut txn = source_fixture.env.begin_rw_txn().unwrap();
+        for ((block_hash, block_header), block_body) in block_headers.iter().zip(&block_bodies) {
+            txn.put(
+                *source_fixture
+                    .db(Some(BlockHeaderDatabase::db_name()))
+                    .unwrap(),
+                block_hash,
+                &bincode::serialize(block_header).unwrap(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+            txn.put(
+                *source_fixture
+                    .db(Some(BlockBodyDatabase::db_name()))
+                    .unwrap(),
+                &block_header.body_hash,
+                &bincode::serialize(block_body).unwrap(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+        }
+        for (deploy_hash, deploy_metadata) in deploy_hashes.iter().zip(&deploy_metadatas) {
+            txn.put(
+                *source_fixture
+                    .db(Some(DeployMetadataDatabase::db_name()))
+                    .unwrap(),
+                deploy_hash,
+                &bincode::serialize(deploy_metadata).unwrap(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+            txn.put(
+                *source_fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+                deploy_hash,
+                &bincode::serialize(deploy_hash).unwrap(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    let destination_fixture = LmdbTestFixture::new(db_names, Some(STORAGE_FILE_NAME));
+    let block_hashes: Vec<BlockHash> = block_headers
+        .iter()
+        .map(|(block_hash, _)| *block_hash)
+        .collect();
+    let actual_state_root_hashes = storage::transfer_block_info(
+        source_fixture.tmp_dir.path(),
+        destination_fixture.tmp_dir.path(),
+        &block_hashes,
+    )
+    .unwrap();
+    let expected_state_root_hashes: Vec<Digest> = block_headers
+        .iter()
+        .map(|(_, block_header)| block_header.state_root_hash)
+        .collect();
+    assert_eq!(expected_state_root_hashes, actual_state_root_hashes);
+
+    let txn = destination_fixture.env.begin_ro_txn().unwrap();
+    for block_hash in &block_hashes {
+        assert!(txn
+            .get(
+                *destination_fixture
+                    .db(Some(BlockHeaderDatabase::db_name()))
+                    .unwrap(),
+                block_hash,
+            )
+            .is_ok());
+    }
+    // The execution results of a deploy in several blocks of the slice are
+    // all kept.
+    for (deploy_hash, expected_metadata) in deploy_hashes.iter().zip(&deploy_metadatas) {
+        let actual_deploy_metadata: DeployMetadata = txn
+            .get(
+                *destination_fixture
+                    .db(Some(DeployMetadataDatabase::db_name()))
+                    .unwrap(),
+                deploy_hash,
+            )
+            .map(bincode::deserialize)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            actual_deploy_metadata
+                .execution_results
+                .keys()
+                .collect::<HashSet<_>>(),
+            expected_metadata
+                .execution_results
+                .keys()
+                .collect::<HashSet<_>>()
+        );
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn block_height_should_resolve_to_single_block() {
+    let fixture = LmdbTestFixture::new(
+        vec![BlockHeaderDatabase::db_name()],
+        Some(STORAGE_FILE_NAME),
+    );
+    // Blocks 1 and 2 are both at height 1, as after a fork.
+    let block_headers: Vec<(BlockHash, MockBlockHeader)> = (0..3)
+        .map(|idx| {
+            let (block_hash, mut block_header) = mock_block_header(idx);
+            block_header.height = idx.min(1) as u64;
+            (block_hash, block_header)
+        })
+        .collect();
+    {
+        let mut txn = fixture.env.begin_rw_txn().unwrap();
+        for (block_hash, block_header) in &block_headers {
+            txn.put(
+                *fixture.db(Some(BlockHeaderDatabase::db_name())).unwrap(),
+                block_hash,
+                &bincode::serialize(block_header).unwrap(),
+                WriteFlags::empty(),
+            )
+            .unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    let block_hashes =
+        extract::block_hashes(fixture.tmp_dir.path(), SliceIdentifier::BlockHeight(0)).unwrap();
+    assert_eq!(block_hashes, vec![block_headers[0].0]);
+    let block_hashes =
+        extract::block_hashes(fixture.tmp_dir.path(), SliceIdentifier::Latest).unwrap();
+    assert_eq!(block_hashes.len(), 1);
+    assert_ne!(block_hashes[0], block_headers[0].0);
+    match extract::block_hashes(fixture.tmp_dir.path(), SliceIdentifier::BlockHeight(1)) {
+        Err(Error::AmbiguousHeight(1, candidates)) => {
+            assert!(candidates.contains(&block_headers[1].0.to_string()));
+            assert!(candidates.contains(&block_headers[2].0.to_string()));
+        }
+        _ => panic!("height 1 should be ambiguous"),
+    }
+    assert!(matches!(
+        extract::block_hashes(fixture.tmp_dir.path(), SliceIdentifier::BlockHeight(2)),
+        Err(Error::MissingHeight(2))
+    ));
+}
+
+#[test]
+fn output_db_should_exist_only_when_appending() {
+    let output_dir = tempfile::tempdir().unwrap();
+    assert!(matches!(
+        storage::create_output_db(output_dir.path(), false),
+        Err(Error::Output(_))
+    ));
+    storage::create_output_db(output_dir.path(), true).unwrap();
+    assert!(output_dir.path().join(STORAGE_FILE_NAME).exists());
+    // Appending again keeps the existing databases.
+    storage::create_output_db(output_dir.path(), true).unwrap();
+}
+
+#[test]
+fn transfer_block_metadata_and_finalized_approvals() {
+    let source_fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            BlockMetadataDatabase::db_name(),
+            DeployDatabase::db_name(),
+            DeployMetadataDatabase::db_name(),
+            FinalizedApprovalsDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    );
+    let (block_hash, block_header) = mock_block_header(0);
+    let deploy_hash = mock_deploy_hash(0);
+    {
+        let mut txn = source_fixture.env.begin_rw_txn().unwrap();
+        let mut put = |db_name, key: &[u8], value: &[u8]| {
+            txn.put(
+                *source_fixture.db(Some(db_name)).unwrap(),
+                &key,
+                &value,
+                WriteFlags::empty(),
+            )
+            .unwrap()
+        };
+        put(
+            BlockHeaderDatabase::db_name(),
+            block_hash.as_ref(),
+            &bincode::serialize(&block_header).unwrap(),
+        );
+        put(
+            BlockBodyDatabase::db_name(),
+            block_header.body_hash.as_ref(),
+            &bincode::serialize(&BlockBody::new(vec![deploy_hash])).unwrap(),
+        );
+        put(BlockMetadataDatabase::db_name(), block_hash.as_ref(), &[1]);
+        put(DeployDatabase::db_name(), deploy_hash.as_ref(), &[2]);
+        put(
+            DeployMetadataDatabase::db_name(),
+            deploy_hash.as_ref(),
+            &bincode::serialize(&mock_deploy_metadata(slice::from_ref(&block_hash))).unwrap(),
+        );
+        put(
+            FinalizedApprovalsDatabase::db_name(),
+            deploy_hash.as_ref(),
+            &[3],
+        );
+        txn.commit().unwrap();
+    }
+
+    let output_dir = tempfile::tempdir().unwrap();
+    storage::create_output_db(output_dir.path(), true).unwrap();
+    storage::transfer_block_info(
+        source_fixture.tmp_dir.path(),
+        output_dir.path(),
+        &[block_hash],
+    )
+    .unwrap();
+
+    let env = db::db_env(output_dir.path().join(STORAGE_FILE_NAME)).unwrap();
+    let txn = env.begin_ro_txn().unwrap();
+    let block_metadata_db = unsafe { txn.open_db(Some(BlockMetadataDatabase::db_name())).unwrap() };
+    assert_eq!(txn.get(block_metadata_db, &block_hash).unwrap(), &[1]);
+    let finalized_approvals_db = unsafe {
+        txn.open_db(Some(FinalizedApprovalsDatabase::db_name()))
+            .unwrap()
+    };
+    assert_eq!(txn.get(finalized_approvals_db, &deploy_hash).unwrap(), &[3]);
+    txn.commit().unwrap();
+}
+
+#[test]
+fn transfer_merkle_block_body_parts() {
+    let source_fixture = LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+            DeployHashesDatabase::db_name(),
+            TransferHashesDatabase::db_name(),
+            ProposerDatabase::db_name(),
+            DeployDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    );
+    let deploy_hash = mock_deploy_hash(0);
+    let transfer_hash = mock_deploy_hash(1);
+    let mut body = BlockBody::new(vec![deploy_hash]);
+    body.transfer_hashes = vec![transfer_hash];
+    let (block_hash, mut block_header) = mock_block_header(0);
+    block_header.body_hash = body.merkle_root().unwrap();
+    let [deploy_hashes_hash, transfer_hashes_hash, proposer_hash] = body.part_hashes().unwrap();
+    let merkle_nodes = body.merkle_nodes();
+    let parts = [
+        (
+            DeployHashesDatabase::db_name(),
+            deploy_hashes_hash,
+            body.deploy_hashes.to_bytes().unwrap(),
+        ),
+        (
+            TransferHashesDatabase::db_name(),
+            transfer_hashes_hash,
+            body.transfer_hashes.to_bytes().unwrap(),
+        ),
+        (
+            ProposerDatabase::db_name(),
+            proposer_hash,
+            PublicKey::System.to_bytes().unwrap(),
+        ),
+    ];
+    {
+        let mut txn = source_fixture.env.begin_rw_txn().unwrap();
+        write_to_db(
+            &mut txn,
+            BlockHeaderDatabase::db_name(),
+            &block_hash,
+            &bincode::serialize(&block_header).unwrap(),
+        )
+        .unwrap();
+        // The body is only stored as the Merkle linked list of its parts.
+        for (node_key, node) in &merkle_nodes {
+            write_to_db(
+                &mut txn,
+                BlockBodyMerkleDatabase::db_name(),
+                node_key,
+                &node.to_bytes().unwrap(),
+            )
+            .unwrap();
+        }
+        for (db_name, part_hash, part) in &parts {
+            write_to_db(&mut txn, db_name, part_hash, part).unwrap();
+        }
+        write_to_db(&mut txn, DeployDatabase::db_name(), &deploy_hash, &[1u8]).unwrap();
+        write_to_db(&mut txn, DeployDatabase::db_name(), &transfer_hash, &[2u8]).unwrap();
+        txn.commit().unwrap();
+    }
+
+    let output_dir = tempfile::tempdir().unwrap();
+    storage::create_output_db(output_dir.path(), true).unwrap();
+    let state_root_hashes = storage::transfer_block_info(
+        source_fixture.tmp_dir.path(),
+        output_dir.path(),
+        &[block_hash],
+    )
+    .unwrap();
+    assert_eq!(state_root_hashes, vec![block_header.state_root_hash]);
+
+    let env = db::db_env(output_dir.path().join(STORAGE_FILE_NAME)).unwrap();
+    let mut txn = env.begin_ro_txn().unwrap();
+    assert_eq!(merkle_nodes.len(), 3);
+    for (node_key, node) in &merkle_nodes {
+        assert_eq!(
+            read_from_db(&mut txn, BlockBodyMerkleDatabase::db_name(), node_key).unwrap(),
+            node.to_bytes().unwrap()
+        );
+    }
+    for (db_name, part_hash, part) in &parts {
+        assert_eq!(&read_from_db(&mut txn, db_name, part_hash).unwrap(), part);
+    }
+    assert_eq!(
+        read_from_db(&mut txn, DeployDatabase::db_name(), &deploy_hash).unwrap(),
+        vec![1]
+    );
+    assert_eq!(
+        read_from_db(&mut txn, DeployDatabase::db_name(), &transfer_hash).unwrap(),
+        vec![2]
+    );
+    txn.commit().unwrap();
+}
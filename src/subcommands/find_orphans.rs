@@ -0,0 +1,165 @@
+mod orphans;
+mod references;
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Error as IoError, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Arg, ArgMatches, Command};
+use lmdb::{Error as LmdbError, Transaction};
+use log::info;
+use serde_json::Error as SerializationError;
+use thiserror::Error as ThisError;
+
+use crate::common::{
+    db::{self, DeserializationError, STORAGE_FILE_NAME},
+    lmdb_utils,
+};
+
+use orphans::OrphanReport;
+
+pub const COMMAND_NAME: &str = "find-orphans";
+const DB_PATH: &str = "db-path";
+const OUTPUT: &str = "output";
+const OVERWRITE: &str = "overwrite";
+const PRUNE: &str = "prune";
+
+/// Number of orphans deleted per write transaction.
+const PRUNE_BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, ThisError)]
+pub enum Error {
+    #[error("Error operating the database: {0}")]
+    Database(#[from] LmdbError),
+    #[error("Storage database not found at {0}")]
+    MissingStorage(PathBuf),
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
+    #[error("Error parsing element {1} in {0} DB: {2}")]
+    Parsing(String, String, DeserializationError),
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] SerializationError),
+}
+
+enum DisplayOrder {
+    DbPath,
+    Prune,
+    Output,
+    Overwrite,
+}
+
+pub fn command(display_order: usize) -> Command<'static> {
+    Command::new(COMMAND_NAME)
+        .display_order(display_order)
+        .about(
+            "Reports the entries of `storage.lmdb` which aren't referenced by any block header, \
+            directly or through its body, in the deploys, deploy metadata, block body, block \
+            body Merkle, transfer and finalized approvals databases, along with the bytes they \
+            take up.",
+        )
+        .arg(
+            Arg::new(DB_PATH)
+                .display_order(DisplayOrder::DbPath as usize)
+                .required(true)
+                .short('d')
+                .long(DB_PATH)
+                .takes_value(true)
+                .value_name("DB_PATH")
+                .help("Path of the directory with the `storage.lmdb` file."),
+        )
+        .arg(
+            Arg::new(PRUNE)
+                .display_order(DisplayOrder::Prune as usize)
+                .long(PRUNE)
+                .takes_value(false)
+                .help(
+                    "Delete the unreferenced entries. Deploys not yet included in a block are \
+                    unreferenced too, so the node must be stopped. The file doesn't shrink \
+                    until `unsparse` is run on it.",
+                ),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .help(
+                    "Path to where the program will output the report in JSON format. \
+                    If unspecified, defaults to standard output.",
+                ),
+        )
+        .arg(
+            Arg::new(OVERWRITE)
+                .display_order(DisplayOrder::Overwrite as usize)
+                .required(false)
+                .short('w')
+                .long(OVERWRITE)
+                .takes_value(false)
+                .requires(OUTPUT)
+                .help("Overwrite an already existing output file."),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let path = Path::new(matches.value_of(DB_PATH).expect("should have db-path arg"));
+    let prune = matches.is_present(PRUNE);
+    let overwrite = matches.is_present(OVERWRITE);
+
+    let out_writer: Box<dyn Write> = match matches.value_of(OUTPUT) {
+        Some(out_path) => Box::new(
+            OpenOptions::new()
+                .create_new(!overwrite)
+                .write(true)
+                .open(out_path)?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    let report = find_orphans(path, prune)?;
+    serde_json::to_writer_pretty(out_writer, &report)?;
+    Ok(())
+}
+
+/// Finds the unreferenced entries in the storage database in `db_path`,
+/// deleting them if `prune` is set.
+fn find_orphans<P: AsRef<Path>>(db_path: P, prune: bool) -> Result<OrphanReport, Error> {
+    let storage_file = db_path.as_ref().join(STORAGE_FILE_NAME);
+    let env = db::existing_db_env(&storage_file)?.ok_or(Error::MissingStorage(storage_file))?;
+
+    let mut report = OrphanReport::default();
+    let mut orphans_by_db = vec![];
+    let txn = env.begin_ro_txn()?;
+    let references = references::collect_references(&txn)?;
+    for (db_name, referenced) in orphans::orphan_candidates(&references) {
+        let db = match lmdb_utils::open_db_if_present(&txn, db_name)? {
+            Some(db) => db,
+            None => {
+                info!("No {} database, skipping.", db_name);
+                continue;
+            }
+        };
+        let (orphan_keys, stats) = orphans::find_orphans(&txn, db, db_name, referenced)?;
+        report.reclaimable_bytes += stats.orphan_bytes;
+        report.databases.push(stats);
+        orphans_by_db.push((db, orphan_keys));
+    }
+    txn.commit()?;
+    info!(
+        "Found {} reclaimable bytes in orphaned entries.",
+        report.reclaimable_bytes
+    );
+
+    if prune {
+        for (db, orphan_keys) in &orphans_by_db {
+            orphans::prune(&env, *db, orphan_keys, PRUNE_BATCH_SIZE)?;
+        }
+        report.pruned = true;
+        info!("Pruned the orphaned entries, run `unsparse` to shrink the database file.");
+    }
+    Ok(report)
+}
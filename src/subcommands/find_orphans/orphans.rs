@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use lmdb::{Cursor, Database as LmdbDatabase, Environment, Error as LmdbError, Transaction};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use casper_hashing::Digest;
+
+use crate::common::{
+    db::{
+        BlockBodyDatabase, BlockBodyMerkleDatabase, Database, DeployDatabase,
+        DeployMetadataDatabase, FinalizedApprovalsDatabase, TransferDatabase,
+    },
+    lmdb_utils,
+};
+
+use super::references::References;
+
+/// Unreferenced entries found in a database.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct OrphanStats {
+    pub(crate) db_name: String,
+    pub(crate) entries: usize,
+    pub(crate) orphans: usize,
+    /// Size of the keys and values of the orphans, excluding the page
+    /// overhead.
+    pub(crate) orphan_bytes: u64,
+}
+
+/// Unreferenced entries found in the storage databases.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub(crate) struct OrphanReport {
+    pub(crate) databases: Vec<OrphanStats>,
+    /// Size of all the orphans, which `unsparse` can reclaim once they're
+    /// pruned.
+    pub(crate) reclaimable_bytes: u64,
+    /// Whether the orphans were deleted.
+    pub(crate) pruned: bool,
+}
+
+/// Returns the databases checked for orphans, each with the set of keys
+/// referenced in it.
+pub(crate) fn orphan_candidates(references: &References) -> Vec<(&'static str, &HashSet<Digest>)> {
+    vec![
+        (DeployDatabase::db_name(), &references.deploy_hashes),
+        (DeployMetadataDatabase::db_name(), &references.deploy_hashes),
+        (BlockBodyDatabase::db_name(), &references.body_hashes),
+        (BlockBodyMerkleDatabase::db_name(), &references.merkle_nodes),
+        (TransferDatabase::db_name(), &references.block_hashes),
+        (
+            FinalizedApprovalsDatabase::db_name(),
+            &references.deploy_hashes,
+        ),
+    ]
+}
+
+/// Returns the raw keys of the entries which aren't referenced, along with
+/// the statistics of the database. Keys which aren't hashes can't be
+/// referenced and are orphans too.
+pub(crate) fn find_orphans<T: Transaction>(
+    txn: &T,
+    db: LmdbDatabase,
+    db_name: &str,
+    referenced: &HashSet<Digest>,
+) -> Result<(Vec<Vec<u8>>, OrphanStats), LmdbError> {
+    let mut stats = OrphanStats {
+        db_name: db_name.to_string(),
+        entries: lmdb_utils::entry_count(txn, db)?,
+        orphans: 0,
+        orphan_bytes: 0,
+    };
+    let mut orphan_keys = vec![];
+    let mut cursor = txn.open_ro_cursor(db)?;
+    for (raw_key, raw_val) in cursor.iter() {
+        let is_referenced = Digest::try_from(raw_key)
+            .map(|digest| referenced.contains(&digest))
+            .unwrap_or(false);
+        if !is_referenced {
+            stats.orphans += 1;
+            stats.orphan_bytes += (raw_key.len() + raw_val.len()) as u64;
+            orphan_keys.push(raw_key.to_vec());
+        }
+    }
+    info!(
+        "Found {} orphans of {} entries in {}, {} bytes.",
+        stats.orphans, stats.entries, db_name, stats.orphan_bytes
+    );
+    Ok((orphan_keys, stats))
+}
+
+/// Deletes the entries with the given keys, committing every `batch_size`
+/// deletions so that a write transaction never holds too many dirty pages.
+pub(crate) fn prune(
+    env: &Environment,
+    db: LmdbDatabase,
+    orphan_keys: &[Vec<u8>],
+    batch_size: usize,
+) -> Result<(), LmdbError> {
+    for batch in orphan_keys.chunks(batch_size) {
+        let mut txn = env.begin_rw_txn()?;
+        for raw_key in batch {
+            txn.del(db, raw_key, None)?;
+        }
+        txn.commit()?;
+    }
+    Ok(())
+}
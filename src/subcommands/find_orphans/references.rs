@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+
+use lmdb::{Cursor, Database as LmdbDatabase, Error as LmdbError, Transaction};
+use log::info;
+
+use casper_hashing::Digest;
+use casper_node::types::BlockHeader;
+use casper_types::{bytesrepr::FromBytes, DeployHash as RawDeployHash};
+
+use crate::{
+    common::{
+        db::{
+            parse_digest_key, BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase,
+            Database, DeployHashesDatabase, DeserializationError, TransferHashesDatabase,
+        },
+        lmdb_utils::open_db_if_present,
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+};
+
+use super::Error;
+
+const BLOCK_LOG_INTERVAL: usize = 10_000;
+
+/// Keys referenced, directly or transitively, by the block headers in
+/// storage.
+#[derive(Debug, Default)]
+pub(crate) struct References {
+    /// Hashes of the stored block headers, keying the transfers.
+    pub(crate) block_hashes: HashSet<Digest>,
+    /// Body hashes of the block headers, keying the block bodies.
+    pub(crate) body_hashes: HashSet<Digest>,
+    /// Nodes of the Merkle linked lists of the bodies stored in parts.
+    pub(crate) merkle_nodes: HashSet<Digest>,
+    /// Deploys and transfers included in the block bodies, keying the
+    /// deploys, their metadata and their finalized approvals.
+    pub(crate) deploy_hashes: HashSet<Digest>,
+}
+
+fn get_if_present<'txn, T: Transaction>(
+    txn: &'txn T,
+    maybe_db: Option<LmdbDatabase>,
+    key: &Digest,
+) -> Result<Option<&'txn [u8]>, LmdbError> {
+    let db = match maybe_db {
+        Some(db) => db,
+        None => return Ok(None),
+    };
+    match txn.get(db, key) {
+        Ok(raw) => Ok(Some(raw)),
+        Err(LmdbError::NotFound) => Ok(None),
+        Err(lmdb_err) => Err(lmdb_err),
+    }
+}
+
+fn parsing_err(db_name: &str, key: &Digest, error: DeserializationError) -> Error {
+    Error::Parsing(db_name.to_string(), key.to_string(), error)
+}
+
+/// Builds the reference sets by walking every block header, then the body it
+/// references and the deploys and transfers in that body.
+///
+/// Bodies are looked up in the `block_body` database first, then as the root
+/// of a Merkle linked list in `block_body_merkle`, whose values point to the
+/// lists of deploy and transfer hashes of the body. Any entry in the chain
+/// which can't be parsed is an error, as the references it holds would be
+/// lost and the entries it points to reported as orphans.
+pub(crate) fn collect_references<T: Transaction>(txn: &T) -> Result<References, Error> {
+    info!("Collecting the references of the block headers.");
+    let block_header_db = unsafe { txn.open_db(Some(BlockHeaderDatabase::db_name()))? };
+    let block_body_db = open_db_if_present(txn, BlockBodyDatabase::db_name())?;
+    let merkle_db = open_db_if_present(txn, BlockBodyMerkleDatabase::db_name())?;
+    let deploy_hashes_db = open_db_if_present(txn, DeployHashesDatabase::db_name())?;
+    let transfer_hashes_db = open_db_if_present(txn, TransferHashesDatabase::db_name())?;
+
+    let mut references = References::default();
+    let mut cursor = txn.open_ro_cursor(block_header_db)?;
+    for (idx, (raw_key, raw_val)) in cursor.iter().enumerate() {
+        if idx % BLOCK_LOG_INTERVAL == 0 {
+            info!("Collected references of {} blocks...", idx);
+        }
+        let block_hash = parse_digest_key(raw_key).map_err(|parsing_err| {
+            Error::Parsing(
+                BlockHeaderDatabase::db_name().to_string(),
+                hex::encode(raw_key),
+                parsing_err,
+            )
+        })?;
+        let header: BlockHeader = bincode::deserialize(raw_val).map_err(|bincode_err| {
+            parsing_err(
+                BlockHeaderDatabase::db_name(),
+                &block_hash,
+                bincode_err.into(),
+            )
+        })?;
+        references.block_hashes.insert(block_hash);
+        let body_hash = *header.body_hash();
+        if !references.body_hashes.insert(body_hash) {
+            // Blocks may share a body, whose references were already added.
+            continue;
+        }
+
+        if let Some(raw_body) = get_if_present(txn, block_body_db, &body_hash)? {
+            let body: BlockBody = bincode::deserialize(raw_body).map_err(|bincode_err| {
+                parsing_err(BlockBodyDatabase::db_name(), &body_hash, bincode_err.into())
+            })?;
+            references.deploy_hashes.extend(
+                body.deploy_hashes()
+                    .iter()
+                    .chain(body.transfer_hashes())
+                    .map(|deploy_hash| *deploy_hash.inner()),
+            );
+            continue;
+        }
+
+        let mut merkle_node = body_hash;
+        while let Some(raw_node) = get_if_present(txn, merkle_db, &merkle_node)? {
+            if !references.merkle_nodes.insert(merkle_node) {
+                break;
+            }
+            let (value_hash, next_node): (Digest, Digest) = FromBytes::from_bytes(raw_node)
+                .map(|(node, _remainder)| node)
+                .map_err(|bytesrepr_err| {
+                    parsing_err(
+                        BlockBodyMerkleDatabase::db_name(),
+                        &merkle_node,
+                        bytesrepr_err.into(),
+                    )
+                })?;
+            // The values of the list are the hashes of the body parts, only
+            // the deploy and transfer hash lists holding references.
+            for (db_name, maybe_db) in [
+                (DeployHashesDatabase::db_name(), deploy_hashes_db),
+                (TransferHashesDatabase::db_name(), transfer_hashes_db),
+            ] {
+                if let Some(raw_hashes) = get_if_present(txn, maybe_db, &value_hash)? {
+                    let deploy_hashes: Vec<RawDeployHash> = FromBytes::from_bytes(raw_hashes)
+                        .map(|(deploy_hashes, _remainder)| deploy_hashes)
+                        .map_err(|bytesrepr_err| {
+                            parsing_err(db_name, &value_hash, bytesrepr_err.into())
+                        })?;
+                    references.deploy_hashes.extend(
+                        deploy_hashes
+                            .into_iter()
+                            .map(|deploy_hash| Digest::from(deploy_hash.value())),
+                    );
+                }
+            }
+            merkle_node = next_node;
+        }
+    }
+    info!(
+        "Collected references of {} blocks: {} bodies, {} deploys and transfers.",
+        references.block_hashes.len(),
+        references.body_hashes.len(),
+        references.deploy_hashes.len()
+    );
+    Ok(references)
+}
@@ -0,0 +1,231 @@
+use lmdb::{Transaction, WriteFlags};
+use tempfile::tempdir;
+
+use casper_hashing::Digest;
+use casper_types::{bytesrepr::ToBytes, DeployHash as RawDeployHash};
+
+use crate::{
+    common::{
+        db::{
+            BlockBodyDatabase, BlockBodyMerkleDatabase, BlockHeaderDatabase, Database,
+            DeployDatabase, DeployHashesDatabase, DeployMetadataDatabase,
+            FinalizedApprovalsDatabase, TransferDatabase, TransferHashesDatabase,
+            STORAGE_FILE_NAME,
+        },
+        lmdb_utils,
+    },
+    subcommands::execution_results_summary::block_body::BlockBody,
+    test_utils::{mock_block_header, mock_deploy_hash, LmdbTestFixture},
+};
+
+use super::{
+    find_orphans,
+    orphans::{self, OrphanStats},
+    Error,
+};
+
+fn storage_fixture() -> LmdbTestFixture {
+    LmdbTestFixture::new(
+        vec![
+            BlockHeaderDatabase::db_name(),
+            BlockBodyDatabase::db_name(),
+            BlockBodyMerkleDatabase::db_name(),
+            DeployDatabase::db_name(),
+            DeployHashesDatabase::db_name(),
+            DeployMetadataDatabase::db_name(),
+            FinalizedApprovalsDatabase::db_name(),
+            TransferDatabase::db_name(),
+            TransferHashesDatabase::db_name(),
+        ],
+        Some(STORAGE_FILE_NAME),
+    )
+}
+
+fn put(fixture: &LmdbTestFixture, db_name: &str, entries: &[(Digest, Vec<u8>)]) {
+    let db = *fixture.db(Some(db_name)).unwrap();
+    let mut txn = fixture.env.begin_rw_txn().unwrap();
+    for (key, value) in entries {
+        txn.put(db, key, value, WriteFlags::empty()).unwrap();
+    }
+    txn.commit().unwrap();
+}
+
+fn entry_count(fixture: &LmdbTestFixture, db_name: &str) -> usize {
+    let db = *fixture.db(Some(db_name)).unwrap();
+    let txn = fixture.env.begin_ro_txn().unwrap();
+    let count = lmdb_utils::entry_count(&txn, db).unwrap();
+    txn.commit().unwrap();
+    count
+}
+
+fn stats_of<'a>(stats: &'a [OrphanStats], db_name: &str) -> &'a OrphanStats {
+    stats.iter().find(|stats| stats.db_name == db_name).unwrap()
+}
+
+#[test]
+fn orphans_should_be_reported_and_pruned() {
+    let fixture = storage_fixture();
+    let digest = |idx: u8| -> Digest { [idx; Digest::LENGTH].into() };
+    let (block_hash, block_header) = mock_block_header(1);
+    let mut block_body = BlockBody::new(vec![mock_deploy_hash(1)]);
+    block_body.transfer_hashes.push(mock_deploy_hash(2));
+    put(
+        &fixture,
+        BlockHeaderDatabase::db_name(),
+        &[(
+            *block_hash.inner(),
+            bincode::serialize(&block_header).unwrap(),
+        )],
+    );
+    put(
+        &fixture,
+        BlockBodyDatabase::db_name(),
+        &[
+            (
+                block_header.body_hash,
+                bincode::serialize(&block_body).unwrap(),
+            ),
+            (digest(9), vec![0u8; 10]),
+        ],
+    );
+    // Deploy 3 isn't included in any block.
+    let deploy_entries: Vec<(Digest, Vec<u8>)> =
+        (1..=3).map(|idx| (digest(idx), vec![idx; 100])).collect();
+    put(&fixture, DeployDatabase::db_name(), &deploy_entries);
+    put(&fixture, DeployMetadataDatabase::db_name(), &deploy_entries);
+    put(
+        &fixture,
+        FinalizedApprovalsDatabase::db_name(),
+        &[(digest(3), vec![3u8; 50])],
+    );
+    put(
+        &fixture,
+        TransferDatabase::db_name(),
+        &[
+            (*block_hash.inner(), vec![1u8; 20]),
+            (digest(8), vec![8u8; 20]),
+        ],
+    );
+
+    let report = find_orphans(fixture.tmp_dir.path(), false).unwrap();
+    assert!(!report.pruned);
+    let expected = [
+        (DeployDatabase::db_name(), 3, 1, 132),
+        (DeployMetadataDatabase::db_name(), 3, 1, 132),
+        (BlockBodyDatabase::db_name(), 2, 1, 42),
+        (BlockBodyMerkleDatabase::db_name(), 0, 0, 0),
+        (TransferDatabase::db_name(), 2, 1, 52),
+        (FinalizedApprovalsDatabase::db_name(), 1, 1, 82),
+    ];
+    for (db_name, entries, orphans, orphan_bytes) in expected {
+        assert_eq!(
+            *stats_of(&report.databases, db_name),
+            OrphanStats {
+                db_name: db_name.to_string(),
+                entries,
+                orphans,
+                orphan_bytes,
+            }
+        );
+    }
+    assert_eq!(report.reclaimable_bytes, 440);
+    assert_eq!(entry_count(&fixture, DeployDatabase::db_name()), 3);
+
+    let report = find_orphans(fixture.tmp_dir.path(), true).unwrap();
+    assert!(report.pruned);
+    assert_eq!(report.reclaimable_bytes, 440);
+    for (db_name, entries, orphans, _) in expected {
+        assert_eq!(entry_count(&fixture, db_name), entries - orphans);
+    }
+    let report = find_orphans(fixture.tmp_dir.path(), false).unwrap();
+    assert_eq!(report.reclaimable_bytes, 0);
+}
+
+#[test]
+fn merkle_bodies_should_reference_their_deploys() {
+    let fixture = storage_fixture();
+    let digest = |idx: u8| -> Digest { [idx; Digest::LENGTH].into() };
+    // The body is a linked list of its deploy hashes, then its transfer
+    // hashes, the last node pointing to a missing one.
+    let (block_hash, mut block_header) = mock_block_header(1);
+    let [first_node, second_node, deploy_list, transfer_list] =
+        [digest(10), digest(11), digest(20), digest(21)];
+    block_header.body_hash = first_node;
+    put(
+        &fixture,
+        BlockHeaderDatabase::db_name(),
+        &[(
+            *block_hash.inner(),
+            bincode::serialize(&block_header).unwrap(),
+        )],
+    );
+    put(
+        &fixture,
+        BlockBodyMerkleDatabase::db_name(),
+        &[
+            (first_node, (deploy_list, second_node).to_bytes().unwrap()),
+            (
+                second_node,
+                (transfer_list, digest(255)).to_bytes().unwrap(),
+            ),
+            (digest(12), (digest(22), digest(255)).to_bytes().unwrap()),
+        ],
+    );
+    let raw_deploy_hashes = |idx: u8| vec![RawDeployHash::new([idx; 32])].to_bytes().unwrap();
+    put(
+        &fixture,
+        DeployHashesDatabase::db_name(),
+        &[(deploy_list, raw_deploy_hashes(1))],
+    );
+    put(
+        &fixture,
+        TransferHashesDatabase::db_name(),
+        &[(transfer_list, raw_deploy_hashes(2))],
+    );
+    put(
+        &fixture,
+        DeployDatabase::db_name(),
+        &[(digest(1), vec![1u8]), (digest(2), vec![2u8])],
+    );
+
+    let report = find_orphans(fixture.tmp_dir.path(), false).unwrap();
+    assert_eq!(
+        stats_of(&report.databases, DeployDatabase::db_name()).orphans,
+        0
+    );
+    let merkle_stats = stats_of(&report.databases, BlockBodyMerkleDatabase::db_name());
+    assert_eq!(merkle_stats.entries, 3);
+    assert_eq!(merkle_stats.orphans, 1);
+}
+
+#[test]
+fn missing_storage_should_fail() {
+    let empty_dir = tempdir().unwrap();
+    assert!(matches!(
+        find_orphans(empty_dir.path(), false),
+        Err(Error::MissingStorage(_))
+    ));
+    assert!(!empty_dir.path().join(STORAGE_FILE_NAME).exists());
+}
+
+#[test]
+fn orphans_should_be_pruned_in_batches() {
+    let fixture = storage_fixture();
+    let entries: Vec<(Digest, Vec<u8>)> = (0..5)
+        .map(|idx| ([idx; Digest::LENGTH].into(), vec![idx]))
+        .collect();
+    put(&fixture, DeployDatabase::db_name(), &entries);
+    let orphan_keys: Vec<Vec<u8>> = entries
+        .iter()
+        .take(4)
+        .map(|(key, _value)| key.as_ref().to_vec())
+        .collect();
+    orphans::prune(
+        &fixture.env,
+        *fixture.db(Some(DeployDatabase::db_name())).unwrap(),
+        &orphan_keys,
+        3,
+    )
+    .unwrap();
+    assert_eq!(entry_count(&fixture, DeployDatabase::db_name()), 1);
+}
@@ -11,6 +11,8 @@ use lmdb::Error as LmdbError;
 use serde_json::Error as SerializationError;
 use thiserror::Error as ThisError;
 
+pub(crate) use read_db::get_highest_block;
+
 pub const COMMAND_NAME: &str = "latest-block-summary";
 const DB_PATH: &str = "db-path";
 const OVERWRITE: &str = "overwrite";
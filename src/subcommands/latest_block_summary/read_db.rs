@@ -23,7 +23,8 @@ use super::{
     Error,
 };
 
-fn get_highest_block(
+/// Finds the highest block by scanning all the block headers.
+pub(crate) fn get_highest_block(
     env: &Environment,
     log_progress: bool,
 ) -> Result<(BlockHash, BlockHeader), Error> {
@@ -1,52 +1,103 @@
+mod checkpoint;
 mod compact;
+mod gc;
 mod helpers;
+mod parallel;
+mod progress;
 #[cfg(test)]
 pub(crate) mod tests;
 // All code in the `utils` mod was copied from `casper-node` because it isn't available in the
 // public interface.
 mod utils;
 
-use std::{io::Error as IoError, path::PathBuf};
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Error as IoError, Write},
+    path::PathBuf,
+};
 
 use anyhow::Error as AnyError;
 use clap::{Arg, ArgMatches, Command};
 use lmdb::Error as LmdbError;
+use once_cell::sync::Lazy;
+use serde_json::Error as SerializationError;
 use thiserror::Error as ThisError;
 
 use casper_hashing::Digest;
 use casper_node::storage::Error as StorageError;
 
-use compact::DestinationOptions;
-pub use helpers::copy_state_root;
+use crate::{
+    common::db::DEFAULT_MAX_DB_SIZE,
+    subcommands::{integer_arg, unsparse::Error as UnsparseError},
+};
+
+pub use compact::RetainedBlocks;
+use compact::{CopyOptions, DestinationOptions};
+pub use helpers::{copy_state_root, BatchOptions};
 pub use utils::{create_execution_engine, load_execution_engine};
 
 pub const COMMAND_NAME: &str = "compact-trie";
 const APPEND: &str = "append";
+const BATCH_BYTES: &str = "batch-bytes";
+const BATCH_SIZE: &str = "batch-size";
 const DESTINATION_TRIE_STORE_PATH: &str = "dest-trie";
+const EXACT_PROGRESS: &str = "exact-progress";
+const FROM_HEIGHT: &str = "from-height";
+const IN_PLACE: &str = "in-place";
+const KEEP_ERA_BOUNDARIES: &str = "keep-era-boundaries";
+const KEEP_LAST: &str = "keep-last";
 const OVERWRITE: &str = "overwrite";
+const RESUME: &str = "resume";
 const MAX_DB_SIZE: &str = "max-db-size";
-pub const DEFAULT_MAX_DB_SIZE: &str = "483183820800"; // 450 gb
+const OUTPUT: &str = "output";
 const SOURCE_TRIE_STORE_PATH: &str = "src-trie";
 const STORAGE_PATH: &str = "storage-path";
+const THREADS: &str = "threads";
+const TO_HEIGHT: &str = "to-height";
+
+/// Default value of the `--max-db-size` args.
+pub(crate) static DEFAULT_MAX_DB_SIZE_ARG: Lazy<String> =
+    Lazy::new(|| DEFAULT_MAX_DB_SIZE.to_string());
 
 /// Possible errors caught while compacting the trie store.
 #[derive(Debug, ThisError)]
 pub enum Error {
+    /// Error reading or writing the checkpoint file.
+    #[error("Error with checkpoint file {0}: {1}")]
+    Checkpoint(PathBuf, String),
     /// Error copying the state root with a specific digest.
     #[error("Error copying state root {0}: {1}")]
     CopyStateRoot(Digest, AnyError),
     /// Error creating the execution engine for the destination trie.
     #[error("Error loading the execution engine: {0}")]
     CreateDestTrie(AnyError),
+    /// The digests of the nodes to mark wouldn't fit in memory.
+    #[error(
+        "Holding the digests of up to {0} nodes needs about {1} bytes of memory, but only {2} \
+        bytes are available"
+    )]
+    InsufficientMemory(usize, u64, u64),
     /// Error working with the destination trie path.
     #[error("Invalid destination: {0}")]
     InvalidDest(String),
+    /// A batch limit is zero.
+    #[error("Invalid batch limits: \"--batch-size\" and \"--batch-bytes\" must be positive")]
+    InvalidBatchSize,
+    /// The lowest height to retain is above the highest one.
+    #[error("Invalid height window: \"--from-height\" {0} is above \"--to-height\" {1}")]
+    InvalidHeightWindow(u64, u64),
     /// Path cannot be created/resolved.
     #[error("Path {0} cannot be created/resolved: {1}")]
     InvalidPath(PathBuf, IoError),
+    /// Error writing the summary of the copy.
+    #[error("Error writing output: {0}")]
+    Output(#[from] IoError),
     /// Error while operating on LMDB.
     #[error("Error while operating on LMDB: {0}")]
     LmdbOperation(LmdbError),
+    /// Error walking the trie of the state root with a specific digest.
+    #[error("Error marking the nodes of state root {0}: {1}")]
+    MarkStateRoot(Digest, AnyError),
     /// A block of specific height is missing from the storage.
     #[error("Storage database is missing block {0}")]
     MissingBlock(u64),
@@ -56,18 +107,35 @@ pub enum Error {
     /// Error opening the block/deploys LMDB store.
     #[error("Error opening the block/deploy storage: {0}")]
     OpenStorage(AnyError),
+    /// Error serializing the summary of the copy.
+    #[error("Error serializing output: {0}")]
+    Serialize(#[from] SerializationError),
     /// Error while getting a block of specific height from storage.
     #[error("Storage error while trying to retrieve block {0}: {1}")]
     Storage(u64, StorageError),
+    /// Error shrinking the trie store after deleting unreachable nodes.
+    #[error("Error shrinking the trie store: {0}")]
+    Unsparse(UnsparseError),
 }
 
 enum DisplayOrder {
     SourcePath,
     DestinationPath,
+    InPlace,
     StoragePath,
     Append,
     Overwrite,
+    Resume,
     MaxDbSize,
+    KeepLast,
+    FromHeight,
+    ToHeight,
+    KeepEraBoundaries,
+    BatchSize,
+    BatchBytes,
+    Threads,
+    ExactProgress,
+    Output,
 }
 
 pub fn command(display_order: usize) -> Command<'static> {
@@ -90,13 +158,28 @@ pub fn command(display_order: usize) -> Command<'static> {
         .arg(
             Arg::new(DESTINATION_TRIE_STORE_PATH)
                 .display_order(DisplayOrder::DestinationPath as usize)
-                .required(true)
+                .required_unless_present(IN_PLACE)
                 .short('d')
                 .long(DESTINATION_TRIE_STORE_PATH)
                 .takes_value(true)
                 .value_name("DESTINATION_TRIE_STORE_DIR_PATH")
                 .help("Path of the directory where the output `data.lmdb` file will be created."),
         )
+        .arg(
+            Arg::new(IN_PLACE)
+                .display_order(DisplayOrder::InPlace as usize)
+                .long(IN_PLACE)
+                .takes_value(false)
+                .conflicts_with_all(&[DESTINATION_TRIE_STORE_PATH, APPEND, OVERWRITE, RESUME])
+                .help(
+                    "Instead of writing a new trie store, delete the nodes of the source which \
+                    aren't reachable from any block's state root, then shrink the source \
+                    `data.lmdb` file. Needs no extra disk space, but the digests of all the \
+                    reachable nodes are held in memory, about 80 bytes per node. Fails before \
+                    deleting anything if the nodes of the source wouldn't all fit in the \
+                    available memory.",
+                ),
+        )
         .arg(
             Arg::new(STORAGE_PATH)
                 .display_order(DisplayOrder::StoragePath as usize)
@@ -136,6 +219,19 @@ pub fn command(display_order: usize) -> Command<'static> {
                     directory.",
                 ),
         )
+        .arg(
+            Arg::new(RESUME)
+                .display_order(DisplayOrder::Resume as usize)
+                .required(false)
+                .long(RESUME)
+                .takes_value(false)
+                .conflicts_with_all(&[APPEND, OVERWRITE])
+                .help(
+                    "Resume an interrupted compaction into the existing output `data.lmdb` \
+                    file from the checkpoint recorded in destination directory, starting \
+                    below the last block whose state root was copied.",
+                ),
+        )
         .arg(
             Arg::new(MAX_DB_SIZE)
                 .display_order(DisplayOrder::MaxDbSize as usize)
@@ -143,33 +239,178 @@ pub fn command(display_order: usize) -> Command<'static> {
                 .short('m')
                 .long(MAX_DB_SIZE)
                 .takes_value(true)
-                .default_value(DEFAULT_MAX_DB_SIZE)
+                .default_value(&DEFAULT_MAX_DB_SIZE_ARG)
                 .value_name("MAX_DB_SIZE")
                 .help("Maximum size the DB files are allowed to be, in bytes."),
         )
+        .arg(
+            Arg::new(KEEP_LAST)
+                .display_order(DisplayOrder::KeepLast as usize)
+                .long(KEEP_LAST)
+                .takes_value(true)
+                .value_name("BLOCK_COUNT")
+                .help("Only retain the state roots of the given number of most recent blocks."),
+        )
+        .arg(
+            Arg::new(FROM_HEIGHT)
+                .display_order(DisplayOrder::FromHeight as usize)
+                .long(FROM_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .help("Only retain the state roots of blocks at or above the given height."),
+        )
+        .arg(
+            Arg::new(TO_HEIGHT)
+                .display_order(DisplayOrder::ToHeight as usize)
+                .long(TO_HEIGHT)
+                .takes_value(true)
+                .value_name("BLOCK_HEIGHT")
+                .help("Only retain the state roots of blocks at or below the given height."),
+        )
+        .arg(
+            Arg::new(KEEP_ERA_BOUNDARIES)
+                .display_order(DisplayOrder::KeepEraBoundaries as usize)
+                .long(KEEP_ERA_BOUNDARIES)
+                .takes_value(false)
+                .help(
+                    "Also retain the state roots of all switch blocks, including those outside \
+                    the height window.",
+                ),
+        )
+        .arg(
+            Arg::new(BATCH_SIZE)
+                .display_order(DisplayOrder::BatchSize as usize)
+                .long(BATCH_SIZE)
+                .takes_value(true)
+                .value_name("NODE_COUNT")
+                .help(
+                    "Maximum number of trie nodes written, or deleted with \"--in-place\", per \
+                    write transaction. Defaults to 10000.",
+                ),
+        )
+        .arg(
+            Arg::new(BATCH_BYTES)
+                .display_order(DisplayOrder::BatchBytes as usize)
+                .long(BATCH_BYTES)
+                .takes_value(true)
+                .value_name("BYTES")
+                .help(
+                    "Maximum size of the trie nodes written per write transaction, in bytes. \
+                    Defaults to 67108864 (64 MiB).",
+                ),
+        )
+        .arg(
+            Arg::new(THREADS)
+                .display_order(DisplayOrder::Threads as usize)
+                .short('t')
+                .long(THREADS)
+                .takes_value(true)
+                .default_value("1")
+                .value_name("THREAD_COUNT")
+                .conflicts_with(IN_PLACE)
+                .help(
+                    "Number of threads reading the state roots from the source trie store \
+                    concurrently. The nodes are written to the destination by a single \
+                    thread.",
+                ),
+        )
+        .arg(
+            Arg::new(EXACT_PROGRESS)
+                .display_order(DisplayOrder::ExactProgress as usize)
+                .long(EXACT_PROGRESS)
+                .takes_value(false)
+                .conflicts_with(IN_PLACE)
+                .help(
+                    "Count the nodes to copy with a pass over the tries of all the retained \
+                    state roots before copying them, so that the progress and time left are \
+                    exact. The digests of the nodes to copy are held in memory, about 80 \
+                    bytes per node, and the nodes to copy are estimated instead if they \
+                    wouldn't fit in the available memory. By default, the nodes to copy are \
+                    estimated from the sizes of the source and destination trie stores.",
+                ),
+        )
+        .arg(
+            Arg::new(OUTPUT)
+                .display_order(DisplayOrder::Output as usize)
+                .short('o')
+                .long(OUTPUT)
+                .takes_value(true)
+                .value_name("FILE_PATH")
+                .conflicts_with(IN_PLACE)
+                .help(
+                    "Path of a file where the nodes copied for each state root, new or \
+                    already in the destination, are written as a line of JSON once the state \
+                    root is copied. The file must not exist. If unspecified, no report is \
+                    written.",
+                ),
+        )
 }
 
 pub fn run(matches: &ArgMatches) -> Result<(), Error> {
     let storage_path = matches.value_of(STORAGE_PATH).unwrap();
     let source_trie_path = matches.value_of(SOURCE_TRIE_STORE_PATH).unwrap();
-    let destination_trie_path = matches.value_of(DESTINATION_TRIE_STORE_PATH).unwrap();
     // Prettier than C style if/else.
     let dest_opt = match matches {
         _ if matches.is_present(APPEND) => DestinationOptions::Append,
         _ if matches.is_present(OVERWRITE) => DestinationOptions::Overwrite,
+        _ if matches.is_present(RESUME) => DestinationOptions::Resume,
         _ => DestinationOptions::New,
     };
-    let max_db_size = matches
-        .value_of(MAX_DB_SIZE)
-        .unwrap()
-        .parse()
-        .expect("Value of \"--max-db-size\" must be an integer.");
+    let max_db_size = integer_arg(matches, MAX_DB_SIZE).expect("should have a default");
+    let default_batch = BatchOptions::default();
+    let batch = BatchOptions {
+        batch_size: integer_arg(matches, BATCH_SIZE).unwrap_or(default_batch.batch_size),
+        batch_bytes: integer_arg(matches, BATCH_BYTES).unwrap_or(default_batch.batch_bytes),
+    };
+    if batch.batch_size == 0 || batch.batch_bytes == 0 {
+        return Err(Error::InvalidBatchSize);
+    }
+    let copy = CopyOptions {
+        batch,
+        threads: integer_arg(matches, THREADS).expect("should have a default"),
+        exact_progress: matches.is_present(EXACT_PROGRESS),
+    };
+    let retained = RetainedBlocks {
+        keep_last: integer_arg(matches, KEEP_LAST),
+        from_height: integer_arg(matches, FROM_HEIGHT),
+        to_height: integer_arg(matches, TO_HEIGHT),
+        keep_era_boundaries: matches.is_present(KEEP_ERA_BOUNDARIES),
+    };
+    if let (Some(from_height), Some(to_height)) = (retained.from_height, retained.to_height) {
+        if from_height > to_height {
+            return Err(Error::InvalidHeightWindow(from_height, to_height));
+        }
+    }
 
+    if matches.is_present(IN_PLACE) {
+        return gc::trie_gc(
+            storage_path,
+            source_trie_path,
+            max_db_size,
+            batch.batch_size,
+            retained,
+        )
+        .map(|_deleted| ());
+    }
+    let destination_trie_path = matches.value_of(DESTINATION_TRIE_STORE_PATH).unwrap();
+    let mut report = match matches.value_of(OUTPUT) {
+        Some(out_path) => Some(BufWriter::new(
+            OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .open(out_path)?,
+        )),
+        None => None,
+    };
     compact::trie_compact(
         storage_path,
         source_trie_path,
         destination_trie_path,
         dest_opt,
         max_db_size,
-    )
+        retained,
+        copy,
+        report.as_mut().map(|writer| writer as &mut dyn Write),
+    )?;
+    Ok(())
 }
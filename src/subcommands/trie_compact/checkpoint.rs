@@ -0,0 +1,142 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Error as IoError, Write},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use casper_hashing::Digest;
+
+use super::Error;
+
+/// Name of the checkpoint file, created in the destination directory.
+pub(crate) const CHECKPOINT_FILE_NAME: &str = "compact_checkpoint.jsonl";
+
+/// Line of the checkpoint file. Lines are only ever appended, so that
+/// recording the progress doesn't depend on the number of state roots
+/// already copied.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CheckpointEntry {
+    /// The copy of the state root of the block at the given height started.
+    Started { height: u64, state_root: Digest },
+    /// The state root of the block at the given height was fully copied and
+    /// flushed to the destination.
+    Completed { height: u64, state_root: Digest },
+}
+
+/// Progress of a compaction, replayed from the checkpoint file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct CompactProgress {
+    /// Height of the last block whose state root was copied.
+    pub(crate) last_height: Option<u64>,
+    /// State roots fully copied.
+    pub(crate) visited_roots: HashSet<Digest>,
+    /// State root whose copy was interrupted. Its nodes already in the
+    /// destination may be missing some of their descendants.
+    pub(crate) interrupted: Option<(u64, Digest)>,
+}
+
+impl CompactProgress {
+    /// Returns `true` if the state root of the block at `height` is left to
+    /// copy. Blocks are copied from the highest to the lowest.
+    pub(crate) fn is_pending(&self, height: u64, state_root: &Digest) -> bool {
+        self.last_height
+            .map_or(true, |last_height| height < last_height)
+            && !self.visited_roots.contains(state_root)
+    }
+}
+
+/// Appends the progress of a compaction to the checkpoint file, so that an
+/// interrupted run can resume from the last state root copied.
+pub(crate) struct Checkpoint {
+    path: PathBuf,
+    file: File,
+}
+
+impl Checkpoint {
+    /// Creates an empty checkpoint file, overwriting any previous one.
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::create(path.as_ref())
+            .map_err(|io_err| Error::Checkpoint(path.as_ref().to_path_buf(), io_err.to_string()))?;
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            file,
+        })
+    }
+
+    /// Replays the checkpoint file written by a previous run and opens it to
+    /// append the progress of this run.
+    pub(crate) fn resume<P: AsRef<Path>>(path: P) -> Result<(Self, CompactProgress), Error> {
+        let checkpoint_err =
+            |reason: String| Error::Checkpoint(path.as_ref().to_path_buf(), reason);
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path.as_ref())
+            .map_err(|io_err| checkpoint_err(io_err.to_string()))?;
+        let mut progress = CompactProgress::default();
+        for line in BufReader::new(&file).lines() {
+            let line = line.map_err(|io_err| checkpoint_err(io_err.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // Only a line cut short by an interruption can't be parsed.
+            let entry: CheckpointEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(json_err) => {
+                    warn!("Ignoring truncated checkpoint line: {}", json_err);
+                    continue;
+                }
+            };
+            match entry {
+                CheckpointEntry::Started { height, state_root } => {
+                    progress.interrupted = Some((height, state_root))
+                }
+                CheckpointEntry::Completed { height, state_root } => {
+                    progress.interrupted = None;
+                    progress.last_height = Some(height);
+                    progress.visited_roots.insert(state_root);
+                }
+            }
+        }
+        let mut checkpoint = Self {
+            path: path.as_ref().to_path_buf(),
+            file,
+        };
+        // Starts a new line in case the last one was truncated.
+        checkpoint
+            .file
+            .write_all(b"\n")
+            .map_err(|io_err| checkpoint_err(io_err.to_string()))?;
+        Ok((checkpoint, progress))
+    }
+
+    /// Appends an entry and syncs it to disk.
+    pub(crate) fn record(&mut self, entry: &CheckpointEntry) -> Result<(), Error> {
+        self.write_entry(entry)
+            .map_err(|io_err| Error::Checkpoint(self.path.clone(), io_err.to_string()))
+    }
+
+    fn write_entry(&mut self, entry: &CheckpointEntry) -> Result<(), IoError> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    /// Deletes the checkpoint file once the compaction is complete.
+    pub(crate) fn remove(self) {
+        drop(self.file);
+        if let Err(io_err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Couldn't remove checkpoint file {}: {}",
+                self.path.display(),
+                io_err
+            );
+        }
+    }
+}
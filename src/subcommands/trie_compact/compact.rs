@@ -1,16 +1,22 @@
 use std::{
     collections::HashSet,
     fs::{self, File, OpenOptions},
+    io::Write,
+    ops::RangeInclusive,
     path::Path,
 };
 
-use log::info;
+use log::{info, warn};
 
 use casper_hashing::Digest;
+use casper_node::storage::Storage;
 
 use crate::common::db::TRIE_STORE_FILE_NAME;
 
 use super::{
+    checkpoint::{Checkpoint, CheckpointEntry, CompactProgress, CHECKPOINT_FILE_NAME},
+    helpers::BatchOptions,
+    progress::{self, CopyProgress, StateRootSummary},
     utils::{create_execution_engine, create_storage, load_execution_engine},
     Error,
 };
@@ -24,6 +30,9 @@ pub enum DestinationOptions {
     Overwrite,
     /// `data.lmdb` must not exist in destination directory.
     New,
+    /// `data.lmdb` in destination directory will be appended, resuming from
+    /// the checkpoint of an interrupted compaction.
+    Resume,
 }
 
 fn validate_trie_paths<P1: AsRef<Path>, P2: AsRef<Path>>(
@@ -51,6 +60,11 @@ fn validate_trie_paths<P1: AsRef<Path>, P2: AsRef<Path>>(
                         .to_string(),
                 ));
             }
+            DestinationOptions::Resume => {
+                return Err(Error::InvalidDest(
+                    "No destination trie to resume. Consider not using \"--resume\".".to_string(),
+                ));
+            }
         }
     } else {
         let dest_data_exists = destination_trie_path
@@ -83,6 +97,18 @@ fn validate_trie_paths<P1: AsRef<Path>, P2: AsRef<Path>>(
                     )));
                 }
             }
+            DestinationOptions::Resume => {
+                if !dest_data_exists {
+                    return Err(Error::InvalidDest(format!(
+                        "Nothing to resume, output file \"data.lmdb\" doesn't exist at \
+                    destination \"{}\". Run the program without `--resume`",
+                        destination_trie_path
+                            .as_ref()
+                            .join(TRIE_STORE_FILE_NAME)
+                            .to_string_lossy()
+                    )));
+                }
+            }
             DestinationOptions::Overwrite => {
                 if dest_data_exists {
                     let _f: File = OpenOptions::new()
@@ -126,21 +152,99 @@ fn validate_trie_paths<P1: AsRef<Path>, P2: AsRef<Path>>(
     Ok(())
 }
 
+/// Selects the blocks whose state roots are retained when compacting. The
+/// height bounds and the count of most recent blocks all restrict the window
+/// of retained heights, which defaults to the whole chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RetainedBlocks {
+    /// Retain only the given number of highest blocks.
+    pub keep_last: Option<u64>,
+    /// Lowest height retained.
+    pub from_height: Option<u64>,
+    /// Highest height retained.
+    pub to_height: Option<u64>,
+    /// Also retain the switch blocks outside the window.
+    pub keep_era_boundaries: bool,
+}
+
+impl RetainedBlocks {
+    /// Returns the window of retained heights given the height of the highest
+    /// block, or `None` if it is empty.
+    pub fn window(&self, highest_height: u64) -> Option<RangeInclusive<u64>> {
+        let end = self
+            .to_height
+            .map_or(highest_height, |to_height| to_height.min(highest_height));
+        let mut start = self.from_height.unwrap_or(0);
+        if let Some(keep_last) = self.keep_last {
+            if keep_last == 0 {
+                return None;
+            }
+            start = start.max((highest_height + 1).saturating_sub(keep_last));
+        }
+        (start <= end).then_some(start..=end)
+    }
+}
+
+/// Settings of the copy of the state roots to the destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Limits of the batches of nodes written per write transaction.
+    pub batch: BatchOptions,
+    /// Number of threads reading the nodes from the source.
+    pub threads: usize,
+    /// Count the nodes to copy with a pass over the tries of all the state
+    /// roots, instead of estimating it from the sizes of the trie stores.
+    pub exact_progress: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            batch: BatchOptions::default(),
+            threads: 1,
+            exact_progress: false,
+        }
+    }
+}
+
 /// Compacts a source trie and outputs the result to the destination trie.
 ///
-/// The function first retrieves the highest block hash from storage and
+/// The function first retrieves the highest retained block from storage and
 /// compacting starts from that state root hash. Each descendant of that
 /// block's hash is copied to the destination trie. This process is repeated
-/// for all the remaining blocks, from highest to lowest.
+/// for all the remaining retained blocks, from highest to lowest.
+///
+/// The progress is recorded after each state root in a checkpoint file in
+/// the destination directory, removed once the compaction is complete. With
+/// `DestinationOptions::Resume`, the state roots of the blocks below the last
+/// one copied are copied, the interrupted state root being copied again in
+/// full first.
+///
+/// The nodes copied for each state root are written to `report`, if given,
+/// as a line of JSON once the state root is copied. Returns the number of
+/// state roots copied.
+#[allow(clippy::too_many_arguments)]
 pub fn trie_compact<P1: AsRef<Path>, P2: AsRef<Path>, P3: AsRef<Path>>(
     storage_path: P1,
     source_trie_path: P2,
     destination_trie_path: P3,
     dest_opt: DestinationOptions,
     max_db_size: usize,
-) -> Result<(), Error> {
+    retained: RetainedBlocks,
+    copy: CopyOptions,
+    mut report: Option<&mut dyn Write>,
+) -> Result<usize, Error> {
     validate_trie_paths(&source_trie_path, &destination_trie_path, dest_opt)?;
 
+    let checkpoint_path = destination_trie_path.as_ref().join(CHECKPOINT_FILE_NAME);
+    let (mut checkpoint, mut progress) = match dest_opt {
+        DestinationOptions::Resume => Checkpoint::resume(&checkpoint_path)?,
+        _ => (
+            Checkpoint::create(&checkpoint_path)?,
+            CompactProgress::default(),
+        ),
+    };
+
     let (source_state, _env) =
         load_execution_engine(source_trie_path, max_db_size, Digest::default(), true)
             .map_err(Error::OpenSourceTrie)?;
@@ -151,44 +255,161 @@ pub fn trie_compact<P1: AsRef<Path>, P2: AsRef<Path>, P3: AsRef<Path>>(
 
     // Create a separate lmdb for block/deploy storage at chain_download_path.
     let storage = create_storage(&storage_path).map_err(Error::OpenStorage)?;
+    let state_roots = state_roots(&storage, &retained)?;
 
-    let mut block = match storage
-        .read_highest_block()
-        .map_err(|err| Error::Storage(0, err))?
-    {
-        Some(block) => block,
-        None => {
-            info!("No blocks found in storage, exiting.");
-            return Ok(());
+    if let Some((_height, state_root)) = progress.interrupted {
+        progress.visited_roots.insert(state_root);
+    }
+    let pending_roots: Vec<(u64, Digest)> = state_roots
+        .into_iter()
+        .filter(|(height, state_root)| progress.is_pending(*height, state_root))
+        .collect();
+    if let Some(last_height) = progress.last_height {
+        info!(
+            "Resuming below block {}, {} state roots left to copy.",
+            last_height,
+            pending_roots.len()
+        );
+    }
+    // The interrupted state root is copied first, rewriting all its nodes.
+    let roots_to_copy: Vec<(u64, Digest, bool)> = progress
+        .interrupted
+        .into_iter()
+        .map(|(height, state_root)| (height, state_root, true))
+        .chain(
+            pending_roots
+                .iter()
+                .map(|(height, state_root)| (*height, *state_root, false)),
+        )
+        .collect();
+
+    let estimated_nodes = progress::estimate_missing_nodes(&source_state, &destination_state)?;
+    let total_nodes = if copy.exact_progress {
+        match progress::check_node_set_memory(estimated_nodes) {
+            Ok(()) => {
+                let all_roots: Vec<(u64, Digest)> = roots_to_copy
+                    .iter()
+                    .map(|(height, state_root, _)| (*height, *state_root))
+                    .collect();
+                progress::count_missing_nodes(&source_state, &destination_state, &all_roots)?
+            }
+            Err(memory_err) => {
+                warn!("{}, estimating the nodes to copy instead.", memory_err);
+                estimated_nodes
+            }
         }
+    } else {
+        estimated_nodes
     };
-    let mut visited_roots = HashSet::new();
-    let mut block_height;
+    let mut copy_progress = CopyProgress::new(total_nodes);
 
     info!("Copying state roots from source to destination.");
-    loop {
-        block_height = block.height();
-        let state_root = *block.take_header().state_root_hash();
-        if !visited_roots.contains(&state_root) {
-            super::helpers::copy_state_root(state_root, &source_state, &destination_state)
-                .map_err(|err| Error::CopyStateRoot(state_root, err))?;
-            destination_state
-                .flush_environment()
-                .map_err(Error::LmdbOperation)?;
-            visited_roots.insert(state_root);
+    let mut copied_roots = 0;
+    for (height, state_root, interrupted) in roots_to_copy {
+        checkpoint.record(&CheckpointEntry::Started { height, state_root })?;
+        let stats = if interrupted {
+            info!(
+                "Copying state root {} of block {} again, as its copy was interrupted.",
+                state_root, height
+            );
+            super::helpers::recopy_state_root(
+                state_root,
+                &source_state,
+                &destination_state,
+                copy.batch,
+                copy.threads,
+                copy_progress.as_mut(),
+            )
+        } else {
+            super::helpers::copy_state_root(
+                state_root,
+                &source_state,
+                &destination_state,
+                copy.batch,
+                copy.threads,
+                copy_progress.as_mut(),
+            )
         }
-        if block_height == 0 {
-            break;
+        .map_err(|err| Error::CopyStateRoot(state_root, err))?;
+        destination_state
+            .flush_environment()
+            .map_err(Error::LmdbOperation)?;
+        checkpoint.record(&CheckpointEntry::Completed { height, state_root })?;
+        if let Some(writer) = report.as_deref_mut() {
+            StateRootSummary {
+                height,
+                state_root,
+                new_nodes: stats.new_nodes,
+                present_nodes: stats.present_nodes,
+            }
+            .write_json_line(writer)?;
         }
-        block = storage
-            .read_block_by_height(block_height - 1)
-            .map_err(|storage_err| Error::Storage(block_height - 1, storage_err))?
-            .ok_or(Error::MissingBlock(block_height - 1))?;
+        copied_roots += 1;
     }
     info!(
         "Finished copying {} state roots to new database.",
-        visited_roots.len()
+        copied_roots
     );
+    checkpoint.remove();
 
-    Ok(())
+    Ok(copied_roots)
+}
+
+/// Returns the distinct state root hashes of the retained blocks in storage,
+/// from the highest block to the lowest, each with the height of the highest
+/// block it belongs to.
+pub(super) fn state_roots(
+    storage: &Storage,
+    retained: &RetainedBlocks,
+) -> Result<Vec<(u64, Digest)>, Error> {
+    let highest_height = match storage
+        .read_highest_block()
+        .map_err(|err| Error::Storage(0, err))?
+    {
+        Some(block) => block.height(),
+        None => {
+            info!("No blocks found in storage.");
+            return Ok(vec![]);
+        }
+    };
+    let window = retained.window(highest_height);
+    match &window {
+        Some(window) => info!(
+            "Retaining the state roots of blocks {} to {}.",
+            window.start(),
+            window.end()
+        ),
+        None => info!("No blocks in the retained height window."),
+    }
+    // Switch blocks can be anywhere in the chain, so all blocks are read to
+    // find them.
+    let heights = match (&window, retained.keep_era_boundaries) {
+        (_, true) => {
+            info!("Retaining the state roots of all switch blocks.");
+            0..=highest_height
+        }
+        (Some(window), false) => window.clone(),
+        (None, false) => return Ok(vec![]),
+    };
+
+    let mut visited_roots = HashSet::new();
+    let mut state_roots = vec![];
+    for block_height in heights.rev() {
+        let block_header = storage
+            .read_block_by_height(block_height)
+            .map_err(|storage_err| Error::Storage(block_height, storage_err))?
+            .ok_or(Error::MissingBlock(block_height))?
+            .take_header();
+        let in_window = window
+            .as_ref()
+            .map_or(false, |window| window.contains(&block_height));
+        if !(in_window || (retained.keep_era_boundaries && block_header.is_switch_block())) {
+            continue;
+        }
+        let state_root = *block_header.state_root_hash();
+        if visited_roots.insert(state_root) {
+            state_roots.push((block_height, state_root));
+        }
+    }
+    Ok(state_roots)
 }
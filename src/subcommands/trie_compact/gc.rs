@@ -0,0 +1,181 @@
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use lmdb::{Cursor, Database, Environment, Error as LmdbError, Transaction};
+use log::{info, warn};
+
+use casper_execution_engine::{
+    core::engine_state::EngineState, storage::global_state::lmdb::LmdbGlobalState,
+};
+use casper_hashing::Digest;
+use casper_types::bytesrepr::Bytes;
+
+use crate::{
+    common::{db::TRIE_STORE_FILE_NAME, lmdb_utils},
+    subcommands::unsparse::{self, Error as UnsparseError},
+};
+
+use super::{
+    compact::{self, RetainedBlocks},
+    helpers::{self, NodeSink},
+    progress,
+    utils::{create_storage, load_execution_engine},
+    Error,
+};
+
+/// Records the keys of the nodes reachable from the retained state roots.
+#[derive(Default)]
+struct Marker {
+    reachable: HashSet<Digest>,
+}
+
+impl NodeSink for Marker {
+    fn add_node(
+        &mut self,
+        trie_key: Digest,
+        value_bytes: Bytes,
+        missing_trie_keys: &mut Vec<Digest>,
+        time_in_missing_trie_keys: &mut Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.reachable.insert(trie_key);
+        let reachable = &self.reachable;
+        helpers::find_missing_descendants(
+            value_bytes,
+            missing_trie_keys,
+            time_in_missing_trie_keys,
+            |ptr| Ok(reachable.contains(ptr)),
+        )
+    }
+}
+
+/// Returns the keys of the nodes reachable from the state roots.
+pub(super) fn mark_reachable(
+    engine_state: &EngineState<LmdbGlobalState>,
+    state_roots: &[Digest],
+) -> Result<HashSet<Digest>, Error> {
+    info!(
+        "Marking the nodes reachable from {} state roots.",
+        state_roots.len()
+    );
+    let mut marker = Marker::default();
+    for state_root in state_roots {
+        if marker.reachable.contains(state_root) {
+            continue;
+        }
+        helpers::walk_state_root(*state_root, engine_state, &mut marker)
+            .map_err(|err| Error::MarkStateRoot(*state_root, err))?;
+    }
+    info!("Marked {} reachable nodes.", marker.reachable.len());
+    Ok(marker.reachable)
+}
+
+/// Deletes the entries of the database whose keys aren't in `reachable`,
+/// `batch_size` entries per write transaction, and returns how many were
+/// deleted.
+///
+/// Each batch is collected in its own read transaction, resuming past the
+/// last key inspected by the previous one, so that no reader holds on to the
+/// pages freed by the previous batches and they can be reused.
+pub(super) fn sweep_unreachable(
+    env: &Environment,
+    db: Database,
+    reachable: &HashSet<Digest>,
+    batch_size: usize,
+) -> Result<usize, LmdbError> {
+    let mut deleted = 0;
+    let mut last_key: Option<Vec<u8>> = None;
+    loop {
+        let txn = env.begin_ro_txn()?;
+        let mut cursor = txn.open_ro_cursor(db)?;
+        let entries = match last_key.as_ref() {
+            Some(key) => {
+                // Appending a zero byte gives the smallest key greater than `key`.
+                let mut start = key.clone();
+                start.push(0);
+                match lmdb_utils::iter_from_key(&mut cursor, &start)? {
+                    Some(entries) => entries,
+                    None => break,
+                }
+            }
+            None => cursor.iter(),
+        };
+        let mut unreachable = vec![];
+        for (raw_key, _raw_value) in entries {
+            last_key = Some(raw_key.to_vec());
+            let is_reachable = Digest::try_from(raw_key)
+                .map(|trie_key| reachable.contains(&trie_key))
+                .unwrap_or(false);
+            if !is_reachable {
+                unreachable.push(raw_key.to_vec());
+                if unreachable.len() == batch_size {
+                    break;
+                }
+            }
+        }
+        drop(cursor);
+        txn.commit()?;
+        if unreachable.is_empty() {
+            break;
+        }
+
+        let mut txn = env.begin_rw_txn()?;
+        for raw_key in &unreachable {
+            txn.del(db, raw_key, None)?;
+        }
+        txn.commit()?;
+        deleted += unreachable.len();
+        info!("Deleted {} unreachable nodes...", deleted);
+    }
+    env.sync(true)?;
+    Ok(deleted)
+}
+
+/// Deletes the nodes of the trie store in `trie_path` which aren't reachable
+/// from the state root of any retained block in storage, then shrinks the
+/// file.
+/// Returns the number of nodes deleted.
+///
+/// Reachable nodes are marked by walking the trie of every state root, from
+/// the highest block to the lowest, with the traversal used when copying
+/// state roots, and the digests of all of them are held in memory, so this
+/// fails before marking if the trie store holds more nodes than fit in the
+/// available memory. The trie store is then swept in batches of `batch_size`
+/// deletions.
+pub fn trie_gc<P1: AsRef<Path>, P2: AsRef<Path>>(
+    storage_path: P1,
+    trie_path: P2,
+    max_db_size: usize,
+    batch_size: usize,
+    retained: RetainedBlocks,
+) -> Result<usize, Error> {
+    let (engine_state, env) =
+        load_execution_engine(&trie_path, max_db_size, Digest::default(), true)
+            .map_err(Error::OpenSourceTrie)?;
+    let storage = create_storage(&storage_path).map_err(Error::OpenStorage)?;
+    let state_roots: Vec<Digest> = compact::state_roots(&storage, &retained)?
+        .into_iter()
+        .map(|(_height, state_root)| state_root)
+        .collect();
+    drop(storage);
+
+    // All the nodes of the trie store may be reachable.
+    let node_count = progress::node_count(&engine_state).map_err(Error::LmdbOperation)?;
+    progress::check_node_set_memory(node_count)?;
+    let reachable = mark_reachable(&engine_state, &state_roots)?;
+    let trie_db = engine_state.get_state().trie_store().get_db();
+    let deleted = sweep_unreachable(env.env(), trie_db, &reachable, batch_size)
+        .map_err(Error::LmdbOperation)?;
+    info!("Deleted {} unreachable nodes in total.", deleted);
+
+    // The file must not be open elsewhere while it's shrunk.
+    drop(engine_state);
+    drop(env);
+    if deleted == 0 {
+        warn!("No unreachable nodes found, the trie store is already compact.");
+        return Ok(0);
+    }
+    match unsparse::unsparse(&trie_path.as_ref().join(TRIE_STORE_FILE_NAME)) {
+        // The freed pages may all be in the middle of the file.
+        Ok(()) | Err(UnsparseError::Size(..)) => Ok(deleted),
+        Err(unsparse_err) => Err(Error::Unsparse(unsparse_err)),
+    }
+}
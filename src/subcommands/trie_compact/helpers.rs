@@ -1,4 +1,7 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
 use lmdb::{RwTransaction, Transaction};
 use log::{info, warn};
@@ -7,7 +10,7 @@ use casper_execution_engine::{
     core::engine_state::EngineState,
     storage::{
         global_state::lmdb::LmdbGlobalState,
-        transaction_source::{Readable, TransactionSource, Writable},
+        transaction_source::{lmdb::LmdbEnvironment, Readable, TransactionSource, Writable},
         trie::{Pointer, Trie},
         trie_store::lmdb::LmdbTrieStore,
     },
@@ -18,13 +21,38 @@ use casper_types::{
     Key, StoredValue,
 };
 
-fn memoized_find_missing_descendants(
+use super::{parallel, progress::CopyProgress};
+
+/// Handles the nodes read by [`walk_state_root`].
+pub(crate) trait NodeSink {
+    /// Handles a node read from the source trie store, then pushes to
+    /// `missing_trie_keys` the children of the node which the sink doesn't
+    /// hold yet, using [`find_missing_descendants`].
+    fn add_node(
+        &mut self,
+        trie_key: Digest,
+        value_bytes: Bytes,
+        missing_trie_keys: &mut Vec<Digest>,
+        time_in_missing_trie_keys: &mut Duration,
+    ) -> Result<(), anyhow::Error>;
+
+    /// Called once all the nodes under the state root were handled.
+    fn finish(&mut self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// Pushes to `missing_trie_keys` the children of the node for which
+/// `is_present` is `false`.
+pub(crate) fn find_missing_descendants<F>(
     value_bytes: Bytes,
-    trie_store: &LmdbTrieStore,
-    txn: &RwTransaction<'_>,
     missing_trie_keys: &mut Vec<Digest>,
     time_in_missing_trie_keys: &mut Duration,
-) -> Result<(), anyhow::Error> {
+    mut is_present: F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&Digest) -> Result<bool, anyhow::Error>,
+{
     // A first bytes of `0` indicates a leaf. We short-circuit the function here to speed things up.
     if let Some(0u8) = value_bytes.first() {
         return Ok(());
@@ -40,41 +68,209 @@ fn memoized_find_missing_descendants(
         }
         Trie::Node { pointer_block } => {
             for (_index, ptr) in pointer_block.as_indexed_pointers() {
-                find_missing_trie_keys(ptr, missing_trie_keys, trie_store, txn)?;
+                find_missing_trie_keys(ptr, missing_trie_keys, &mut is_present)?;
             }
         }
         Trie::Extension { affix: _, pointer } => {
-            find_missing_trie_keys(pointer, missing_trie_keys, trie_store, txn)?;
+            find_missing_trie_keys(pointer, missing_trie_keys, &mut is_present)?;
         }
     }
     *time_in_missing_trie_keys += start_trie_keys.elapsed();
     Ok(())
 }
 
-fn find_missing_trie_keys(
+fn find_missing_trie_keys<F>(
     ptr: Pointer,
     missing_trie_keys: &mut Vec<Digest>,
-    handle: &LmdbTrieStore,
-    txn: &RwTransaction<'_>,
-) -> Result<(), anyhow::Error> {
+    is_present: &mut F,
+) -> Result<(), anyhow::Error>
+where
+    F: FnMut(&Digest) -> Result<bool, anyhow::Error>,
+{
     let ptr = match ptr {
         Pointer::LeafPointer(pointer) | Pointer::NodePointer(pointer) => pointer,
     };
-    let existing = txn.read(
-        handle.get_db(),
-        &ptr.to_bytes()
-            .map_err(|err| anyhow::anyhow!("couldn't serialize trie pointer: {:?}", err))?,
-    )?;
-    if existing.is_none() {
+    if !is_present(&ptr)? {
         missing_trie_keys.push(ptr);
     }
     Ok(())
 }
 
-pub fn copy_state_root(
+/// Limits of the batches of nodes written per write transaction. A batch is
+/// committed as soon as it reaches either limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchOptions {
+    /// Maximum number of nodes per batch.
+    pub batch_size: usize,
+    /// Maximum size of the keys and values of the nodes in a batch, in bytes.
+    pub batch_bytes: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 10_000,
+            batch_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Nodes handled by a copy of a state root.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Nodes written to the destination.
+    pub new_nodes: u64,
+    /// Children of the nodes written which were already in the destination,
+    /// so their subtries were skipped.
+    pub present_nodes: u64,
+}
+
+/// Writes the nodes to the trie store of the destination in batches, each
+/// in its own write transaction. Nodes already in the destination, or
+/// written in the current batch, are considered held, unless `rewrite` is
+/// set.
+pub(super) struct DestinationSink<'a> {
+    pub(super) environment: &'a LmdbEnvironment,
+    pub(super) trie_store: &'a LmdbTrieStore,
+    batch: BatchOptions,
+    pub(super) rewrite: bool,
+    /// Transaction of the current batch, opened by its first node.
+    write_txn: Option<RwTransaction<'a>>,
+    /// Keys of the nodes written in the current batch.
+    batch_keys: HashSet<Digest>,
+    /// Size of the nodes written in the current batch.
+    batch_bytes: usize,
+    progress: Option<&'a mut CopyProgress>,
+    stats: CopyStats,
+}
+
+impl<'a> DestinationSink<'a> {
+    pub(super) fn new(
+        destination: &'a EngineState<LmdbGlobalState>,
+        batch: BatchOptions,
+        rewrite: bool,
+        progress: Option<&'a mut CopyProgress>,
+    ) -> Self {
+        Self {
+            environment: destination.get_state().environment(),
+            trie_store: destination.get_state().trie_store(),
+            batch,
+            rewrite,
+            write_txn: None,
+            batch_keys: HashSet::new(),
+            batch_bytes: 0,
+            progress,
+            stats: CopyStats::default(),
+        }
+    }
+
+    /// Returns the nodes handled so far.
+    pub(super) fn stats(&self) -> CopyStats {
+        self.stats
+    }
+
+    /// Writes a node in the current batch, opening its transaction if needed.
+    pub(super) fn write_node(
+        &mut self,
+        trie_key: Digest,
+        value_bytes: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        if self.write_txn.is_none() {
+            self.write_txn = Some(self.environment.create_read_write_txn()?);
+        }
+        let write_txn = self
+            .write_txn
+            .as_mut()
+            .expect("should have opened a write transaction");
+        let key_bytes = trie_key
+            .to_bytes()
+            .map_err(|err| anyhow::anyhow!("couldn't serialize trie key: {:?}", err))?;
+        write_txn.write(self.trie_store.get_db(), &key_bytes, value_bytes)?;
+        self.batch_keys.insert(trie_key);
+        self.batch_bytes += key_bytes.len() + value_bytes.len();
+        self.stats.new_nodes += 1;
+        if let Some(progress) = self.progress.as_mut() {
+            progress.advance();
+        }
+        Ok(())
+    }
+
+    /// Commits the current batch if it reached either limit.
+    pub(super) fn commit_if_full(&mut self) -> Result<(), anyhow::Error> {
+        if self.batch_keys.len() >= self.batch.batch_size
+            || self.batch_bytes >= self.batch.batch_bytes
+        {
+            self.commit_batch()?;
+        }
+        Ok(())
+    }
+
+    fn commit_batch(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(write_txn) = self.write_txn.take() {
+            write_txn.commit()?;
+        }
+        self.batch_keys.clear();
+        self.batch_bytes = 0;
+        Ok(())
+    }
+}
+
+impl NodeSink for DestinationSink<'_> {
+    fn add_node(
+        &mut self,
+        trie_key: Digest,
+        value_bytes: Bytes,
+        missing_trie_keys: &mut Vec<Digest>,
+        time_in_missing_trie_keys: &mut Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.write_node(trie_key, &value_bytes)?;
+
+        let rewrite = self.rewrite;
+        let batch_keys = &self.batch_keys;
+        let mut present_nodes = 0;
+        let db = self.trie_store.get_db();
+        let write_txn = self
+            .write_txn
+            .as_ref()
+            .expect("should have opened a write transaction");
+        find_missing_descendants(
+            value_bytes,
+            missing_trie_keys,
+            time_in_missing_trie_keys,
+            |ptr| {
+                if rewrite {
+                    return Ok(false);
+                }
+                // Spares a lookup for the nodes written in this batch.
+                let is_present = batch_keys.contains(ptr) || {
+                    let ptr_bytes = ptr.to_bytes().map_err(|err| {
+                        anyhow::anyhow!("couldn't serialize trie pointer: {:?}", err)
+                    })?;
+                    write_txn.read(db, &ptr_bytes)?.is_some()
+                };
+                if is_present {
+                    present_nodes += 1;
+                }
+                Ok(is_present)
+            },
+        )?;
+        self.stats.present_nodes += present_nodes;
+
+        self.commit_if_full()
+    }
+
+    fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.commit_batch()
+    }
+}
+
+/// Reads every node of the trie under `state_root` in the source, depth
+/// first, and hands it to `sink`. The subtries of the nodes the sink already
+/// holds aren't walked.
+pub(crate) fn walk_state_root<S: NodeSink>(
     state_root: Digest,
     source: &EngineState<LmdbGlobalState>,
-    destination: &EngineState<LmdbGlobalState>,
+    sink: &mut S,
 ) -> Result<(), anyhow::Error> {
     let mut missing_trie_keys = vec![state_root];
     let start_time = Instant::now();
@@ -85,43 +281,31 @@ pub fn copy_state_root(
 
     let mut time_searching_for_trie_keys = Duration::from_secs(0);
 
+    let source_store = source.get_state().trie_store();
+    let read_txn = source.get_state().environment().create_read_txn()?;
     while let Some(next_trie_key) = missing_trie_keys.pop() {
         // For user feedback, update on progress if this takes longer than 10 seconds.
         if heartbeat_interval.elapsed().as_secs() > 10 {
             info!(
-                "trie migration progress: bytes copied {}, tries copied {}",
+                "trie traversal progress: bytes read {}, tries read {}",
                 total_bytes, total_tries,
             );
             heartbeat_interval = Instant::now();
         }
 
-        let source_store = source.get_state().trie_store();
-        let destination_store = destination.get_state().trie_store();
         let trie_key_bytes = next_trie_key
             .to_bytes()
             .map_err(|err| anyhow::anyhow!("couldn't serialize trie key: {:?}", err))?;
 
-        let read_txn = source.get_state().environment().create_read_txn()?;
-        let mut write_txn = destination
-            .get_state()
-            .environment()
-            .create_read_write_txn()?;
-
         match read_txn.read(source_store.get_db(), &trie_key_bytes)? {
             Some(value_bytes) => {
-                let key_bytes = next_trie_key
-                    .to_bytes()
-                    .map_err(|err| anyhow::anyhow!("couldn't serialize trie key: {:?}", err))?;
-                let read_bytes = key_bytes.len() as u64 + value_bytes.len() as u64;
+                let read_bytes = trie_key_bytes.len() as u64 + value_bytes.len() as u64;
                 total_bytes += read_bytes;
                 total_tries += 1;
 
-                write_txn.write(destination_store.get_db(), &key_bytes, &value_bytes)?;
-
-                memoized_find_missing_descendants(
+                sink.add_node(
+                    next_trie_key,
                     value_bytes,
-                    destination_store,
-                    &write_txn,
                     &mut missing_trie_keys,
                     &mut time_searching_for_trie_keys,
                 )?;
@@ -134,13 +318,13 @@ pub fn copy_state_root(
                 ));
             }
         }
-        read_txn.commit()?;
-        write_txn.commit()?;
     }
+    read_txn.commit()?;
+    sink.finish()?;
 
     info!(
-        "Trie migration complete\nTotal bytes: {}\n\
-            Total tries: {}\nMigration duration (us): {}\n\
+        "Trie traversal complete\nTotal bytes: {}\n\
+            Total tries: {}\nTraversal duration (us): {}\n\
             Trie key search duration (us):{}",
         total_bytes,
         total_tries,
@@ -149,3 +333,43 @@ pub fn copy_state_root(
     );
     Ok(())
 }
+
+/// Copies the nodes of the trie under `state_root` missing from the
+/// destination, in batches limited by `batch`. With more than one thread,
+/// the trie is traversed by `threads` workers feeding the writer. Each node
+/// written advances `progress`.
+pub fn copy_state_root(
+    state_root: Digest,
+    source: &EngineState<LmdbGlobalState>,
+    destination: &EngineState<LmdbGlobalState>,
+    batch: BatchOptions,
+    threads: usize,
+    progress: Option<&mut CopyProgress>,
+) -> Result<CopyStats, anyhow::Error> {
+    let mut sink = DestinationSink::new(destination, batch, false, progress);
+    if threads > 1 {
+        return parallel::copy_state_root(state_root, source, sink, threads);
+    }
+    walk_state_root(state_root, source, &mut sink)?;
+    Ok(sink.stats())
+}
+
+/// Copies every node of the trie under `state_root`, including those already
+/// in the destination. Nodes are written before their descendants, so when a
+/// copy is interrupted, the nodes already written may be missing some of
+/// their descendants and `copy_state_root` would stop at them.
+pub(crate) fn recopy_state_root(
+    state_root: Digest,
+    source: &EngineState<LmdbGlobalState>,
+    destination: &EngineState<LmdbGlobalState>,
+    batch: BatchOptions,
+    threads: usize,
+    progress: Option<&mut CopyProgress>,
+) -> Result<CopyStats, anyhow::Error> {
+    let mut sink = DestinationSink::new(destination, batch, true, progress);
+    if threads > 1 {
+        return parallel::copy_state_root(state_root, source, sink, threads);
+    }
+    walk_state_root(state_root, source, &mut sink)?;
+    Ok(sink.stats())
+}
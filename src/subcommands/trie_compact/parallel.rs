@@ -0,0 +1,267 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, SyncSender},
+        Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use lmdb::Transaction;
+use log::info;
+
+use casper_execution_engine::{
+    core::engine_state::EngineState,
+    storage::{
+        global_state::lmdb::LmdbGlobalState,
+        transaction_source::{lmdb::LmdbEnvironment, Readable, TransactionSource},
+        trie_store::lmdb::LmdbTrieStore,
+    },
+};
+use casper_hashing::Digest;
+use casper_types::bytesrepr::{Bytes, ToBytes};
+
+use super::helpers::{self, CopyStats, DestinationSink, NodeSink};
+
+/// Number of nodes read by the workers which can wait for the writer before
+/// the workers block.
+const CHANNEL_CAPACITY: usize = 4_096;
+
+/// Trie store of a global state, which unlike the engine state can be
+/// shared between threads.
+#[derive(Clone, Copy)]
+struct TrieStore<'a> {
+    environment: &'a LmdbEnvironment,
+    store: &'a LmdbTrieStore,
+}
+
+impl<'a> TrieStore<'a> {
+    fn of(engine_state: &'a EngineState<LmdbGlobalState>) -> Self {
+        Self {
+            environment: engine_state.get_state().environment(),
+            store: engine_state.get_state().trie_store(),
+        }
+    }
+
+    fn of_sink(sink: &DestinationSink<'a>) -> Self {
+        Self {
+            environment: sink.environment,
+            store: sink.trie_store,
+        }
+    }
+}
+
+/// Trie keys left to read, shared by the workers.
+struct WorkQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    stopped: AtomicBool,
+}
+
+struct QueueState {
+    pending: Vec<Digest>,
+    /// Number of keys taken by a worker whose missing children aren't pushed
+    /// yet.
+    in_progress: usize,
+}
+
+impl WorkQueue {
+    fn new(state_root: Digest) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                pending: vec![state_root],
+                in_progress: 0,
+            }),
+            changed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Blocks until a key is available. Returns `None` once the trie is fully
+    /// traversed, or the traversal was stopped.
+    fn take(&self) -> Option<Digest> {
+        let mut state = self.state.lock().expect("work queue lock poisoned");
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(trie_key) = state.pending.pop() {
+                state.in_progress += 1;
+                return Some(trie_key);
+            }
+            if state.in_progress == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).expect("work queue lock poisoned");
+        }
+    }
+
+    /// Pushes the missing children of a key taken by a worker.
+    fn complete(&self, missing_trie_keys: Vec<Digest>) {
+        let mut state = self.state.lock().expect("work queue lock poisoned");
+        state.pending.extend(missing_trie_keys);
+        state.in_progress -= 1;
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    /// Wakes up the workers waiting for keys and makes them stop.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Holding the lock ensures no worker is between its check and its wait.
+        let _state = self.state.lock().expect("work queue lock poisoned");
+        self.changed.notify_all();
+    }
+}
+
+/// Reads the nodes taken from the queue in the source and sends them to the
+/// writer, then pushes their children missing from the destination to the
+/// queue. Nodes are sent before their children are pushed, so the writer
+/// receives them top-down, as with the single-threaded traversal.
+/// Returns the number of children found in the destination.
+fn read_nodes(
+    state_root: Digest,
+    queue: &WorkQueue,
+    source: TrieStore,
+    destination: TrieStore,
+    rewrite: bool,
+    sender: SyncSender<(Digest, Bytes)>,
+) -> Result<u64, anyhow::Error> {
+    let mut present_nodes = 0;
+    let mut time_in_missing_trie_keys = Duration::from_secs(0);
+    let source_txn = source.environment.create_read_txn()?;
+    while let Some(trie_key) = queue.take() {
+        let trie_key_bytes = trie_key
+            .to_bytes()
+            .map_err(|err| anyhow::anyhow!("couldn't serialize trie key: {:?}", err))?;
+        let value_bytes = source_txn
+            .read(source.store.get_db(), &trie_key_bytes)?
+            .ok_or_else(|| {
+                anyhow::anyhow!("error migrating state root {} {}", state_root, trie_key)
+            })?;
+
+        let mut missing_trie_keys = vec![];
+        // Nodes still in the writer's batch aren't visible yet, so some may be
+        // read and written twice.
+        let destination_txn = destination.environment.create_read_txn()?;
+        helpers::find_missing_descendants(
+            value_bytes.clone(),
+            &mut missing_trie_keys,
+            &mut time_in_missing_trie_keys,
+            |ptr| {
+                if rewrite {
+                    return Ok(false);
+                }
+                let ptr_bytes = ptr
+                    .to_bytes()
+                    .map_err(|err| anyhow::anyhow!("couldn't serialize trie pointer: {:?}", err))?;
+                let is_present = destination_txn
+                    .read(destination.store.get_db(), &ptr_bytes)?
+                    .is_some();
+                if is_present {
+                    present_nodes += 1;
+                }
+                Ok(is_present)
+            },
+        )?;
+        destination_txn.commit()?;
+
+        sender
+            .send((trie_key, value_bytes))
+            .map_err(|_| anyhow::anyhow!("the writer stopped"))?;
+        queue.complete(missing_trie_keys);
+    }
+    source_txn.commit()?;
+    Ok(present_nodes)
+}
+
+/// Writes the nodes received from the workers to the destination through
+/// `sink`, which commits them in batches.
+fn write_nodes<I: IntoIterator<Item = (Digest, Bytes)>>(
+    nodes: I,
+    sink: &mut DestinationSink<'_>,
+) -> Result<(), anyhow::Error> {
+    let start_time = Instant::now();
+    let mut heartbeat_interval = Instant::now();
+    let mut total_tries: u64 = 0;
+    let mut total_bytes: u64 = 0;
+    for (trie_key, value_bytes) in nodes {
+        // For user feedback, update on progress if this takes longer than 10 seconds.
+        if heartbeat_interval.elapsed().as_secs() > 10 {
+            info!(
+                "trie traversal progress: bytes written {}, tries written {}",
+                total_bytes, total_tries,
+            );
+            heartbeat_interval = Instant::now();
+        }
+        total_bytes += (Digest::LENGTH + value_bytes.len()) as u64;
+        total_tries += 1;
+        sink.write_node(trie_key, &value_bytes)?;
+        sink.commit_if_full()?;
+    }
+    sink.finish()?;
+    info!(
+        "Trie traversal complete\nTotal bytes: {}\n\
+            Total tries: {}\nTraversal duration (us): {}",
+        total_bytes,
+        total_tries,
+        start_time.elapsed().as_micros(),
+    );
+    Ok(())
+}
+
+/// Copies the nodes of the trie under `state_root` missing from the
+/// destination of `sink`, or all of them if it rewrites nodes, with
+/// `threads` workers reading and decoding the nodes from the source
+/// concurrently. The nodes are written by the calling thread, which owns
+/// the write transactions.
+pub(super) fn copy_state_root(
+    state_root: Digest,
+    source: &EngineState<LmdbGlobalState>,
+    mut sink: DestinationSink<'_>,
+    threads: usize,
+) -> Result<CopyStats, anyhow::Error> {
+    let queue = WorkQueue::new(state_root);
+    let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+    let source_trie = TrieStore::of(source);
+    let destination_trie = TrieStore::of_sink(&sink);
+    let rewrite = sink.rewrite;
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let queue = &queue;
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let result = read_nodes(
+                        state_root,
+                        queue,
+                        source_trie,
+                        destination_trie,
+                        rewrite,
+                        sender,
+                    );
+                    if result.is_err() {
+                        queue.stop();
+                    }
+                    result
+                })
+            })
+            .collect();
+        // The channel disconnects once every worker is done.
+        drop(sender);
+
+        let write_result = write_nodes(&receiver, &mut sink);
+        if write_result.is_err() {
+            queue.stop();
+        }
+        // Unblocks the workers waiting to send.
+        drop(receiver);
+        let mut stats = sink.stats();
+        for worker in workers {
+            stats.present_nodes += worker.join().expect("trie reader thread panicked")?;
+        }
+        write_result.map(|()| stats)
+    })
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use lmdb::{Database, Error as LmdbError, RoTransaction, Transaction};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use casper_execution_engine::{
+    core::engine_state::EngineState,
+    storage::{global_state::lmdb::LmdbGlobalState, transaction_source::Readable},
+};
+use casper_hashing::Digest;
+use casper_types::bytesrepr::{Bytes, ToBytes};
+
+use crate::common::{lmdb_utils, progress::ProgressTracker};
+
+use super::{
+    helpers::{self, NodeSink},
+    Error,
+};
+
+/// Approximate memory taken by a digest held in a `HashSet`, including the
+/// spare capacity the set keeps as it grows.
+const BYTES_PER_HELD_DIGEST: u64 = 80;
+
+/// Nodes copied for the state root of a block.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct StateRootSummary {
+    pub height: u64,
+    pub state_root: Digest,
+    /// Nodes written to the destination.
+    pub new_nodes: u64,
+    /// Nodes already in the destination, shared with the state roots copied
+    /// before, whose subtries weren't copied again.
+    pub present_nodes: u64,
+}
+
+impl StateRootSummary {
+    /// Writes the summary as a line of JSON and flushes the writer, so that
+    /// the summaries of the state roots already copied are kept if the copy
+    /// is interrupted.
+    pub(crate) fn write_json_line(&self, writer: &mut dyn Write) -> Result<(), Error> {
+        serde_json::to_writer(&mut *writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Progress of the copy of the state roots, counted in nodes written to the
+/// destination against the nodes expected to be written.
+pub struct CopyProgress {
+    tracker: ProgressTracker,
+    total_nodes: usize,
+    copied_nodes: usize,
+}
+
+impl CopyProgress {
+    /// Creates a progress tracker logging the completion rate along with an
+    /// estimate of the time left. Returns `None` if there are no nodes to
+    /// copy.
+    pub(crate) fn new(total_nodes: usize) -> Option<Self> {
+        let start_time = Instant::now();
+        let log_progress = Box::new(move |completion: u64| {
+            let completion = completion.clamp(1, 100);
+            let elapsed = start_time.elapsed().as_secs_f64();
+            let time_left = Duration::from_secs_f64(
+                elapsed * 100u64.saturating_sub(completion) as f64 / completion as f64,
+            );
+            info!(
+                "Copying state roots {}% complete, about {}s left...",
+                completion,
+                time_left.as_secs()
+            )
+        });
+        match ProgressTracker::new(total_nodes, log_progress) {
+            Ok(tracker) => Some(Self {
+                tracker,
+                total_nodes,
+                copied_nodes: 0,
+            }),
+            Err(progress_tracker_error) => {
+                warn!(
+                    "Couldn't initialize progress tracker: {}",
+                    progress_tracker_error
+                );
+                None
+            }
+        }
+    }
+
+    /// Counts a node written. Nodes past the expected total, as when it was
+    /// underestimated, aren't counted.
+    pub(crate) fn advance(&mut self) {
+        if self.copied_nodes < self.total_nodes {
+            self.copied_nodes += 1;
+            self.tracker.advance_by(1);
+        }
+    }
+}
+
+/// Estimates the nodes left to copy as the difference between the entries
+/// of the source and destination trie stores. Overestimates them when the
+/// source holds state roots which aren't retained.
+pub(crate) fn estimate_missing_nodes(
+    source: &EngineState<LmdbGlobalState>,
+    destination: &EngineState<LmdbGlobalState>,
+) -> Result<usize, Error> {
+    let source_nodes = node_count(source).map_err(Error::LmdbOperation)?;
+    let destination_nodes = node_count(destination).map_err(Error::LmdbOperation)?;
+    Ok(source_nodes.saturating_sub(destination_nodes))
+}
+
+/// Returns the number of nodes in the trie store of the engine state.
+pub(crate) fn node_count(engine_state: &EngineState<LmdbGlobalState>) -> Result<usize, LmdbError> {
+    let txn = engine_state
+        .get_state()
+        .environment()
+        .env()
+        .begin_ro_txn()?;
+    let count = lmdb_utils::entry_count(&txn, engine_state.get_state().trie_store().get_db())?;
+    txn.commit()?;
+    Ok(count)
+}
+
+/// Returns the memory available for new allocations according to
+/// `/proc/meminfo`, or `None` where it can't be read.
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    meminfo.lines().find_map(|line| {
+        let kilobytes = line
+            .strip_prefix("MemAvailable:")?
+            .trim()
+            .strip_suffix("kB")?
+            .trim()
+            .parse::<u64>()
+            .ok()?;
+        Some(kilobytes * 1024)
+    })
+}
+
+/// Checks that the digests of `node_count` nodes can be held in a `HashSet`
+/// within the available memory. Passes where the available memory is
+/// unknown.
+pub(crate) fn check_node_set_memory(node_count: usize) -> Result<(), Error> {
+    let needed = (node_count as u64).saturating_mul(BYTES_PER_HELD_DIGEST);
+    match available_memory() {
+        Some(available) if needed > available => {
+            Err(Error::InsufficientMemory(node_count, needed, available))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Records the keys of the nodes missing from the destination.
+struct MissingNodeCounter<'a> {
+    destination_txn: &'a RoTransaction<'a>,
+    destination_db: Database,
+    missing: HashSet<Digest>,
+}
+
+impl MissingNodeCounter<'_> {
+    /// Returns `true` if the node is in the destination, or was already
+    /// counted.
+    fn is_held(&self, trie_key: &Digest) -> Result<bool, anyhow::Error> {
+        if self.missing.contains(trie_key) {
+            return Ok(true);
+        }
+        let key_bytes = trie_key
+            .to_bytes()
+            .map_err(|err| anyhow::anyhow!("couldn't serialize trie key: {:?}", err))?;
+        Ok(self
+            .destination_txn
+            .read(self.destination_db, &key_bytes)?
+            .is_some())
+    }
+}
+
+impl NodeSink for MissingNodeCounter<'_> {
+    fn add_node(
+        &mut self,
+        trie_key: Digest,
+        value_bytes: Bytes,
+        missing_trie_keys: &mut Vec<Digest>,
+        time_in_missing_trie_keys: &mut Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.missing.insert(trie_key);
+        let counter = &*self;
+        helpers::find_missing_descendants(
+            value_bytes,
+            missing_trie_keys,
+            time_in_missing_trie_keys,
+            |ptr| counter.is_held(ptr),
+        )
+    }
+}
+
+/// Counts the nodes reachable from the state roots which are missing from
+/// the destination, which is how many nodes copying the state roots writes.
+/// The digests of these nodes are held in memory while they're counted.
+pub(crate) fn count_missing_nodes(
+    source: &EngineState<LmdbGlobalState>,
+    destination: &EngineState<LmdbGlobalState>,
+    state_roots: &[(u64, Digest)],
+) -> Result<usize, Error> {
+    info!(
+        "Counting the nodes to copy for {} state roots.",
+        state_roots.len()
+    );
+    let txn = destination
+        .get_state()
+        .environment()
+        .env()
+        .begin_ro_txn()
+        .map_err(Error::LmdbOperation)?;
+    let mut counter = MissingNodeCounter {
+        destination_txn: &txn,
+        destination_db: destination.get_state().trie_store().get_db(),
+        missing: HashSet::new(),
+    };
+    for (_height, state_root) in state_roots {
+        let is_held = counter
+            .is_held(state_root)
+            .map_err(|err| Error::MarkStateRoot(*state_root, err))?;
+        if !is_held {
+            helpers::walk_state_root(*state_root, source, &mut counter)
+                .map_err(|err| Error::MarkStateRoot(*state_root, err))?;
+        }
+    }
+    let missing_nodes = counter.missing.len();
+    drop(counter);
+    txn.commit().map_err(Error::LmdbOperation)?;
+    info!("Found {} nodes to copy.", missing_nodes);
+    Ok(missing_nodes)
+}
@@ -1,7 +1,10 @@
-use std::fs::{self, File};
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::Write,
+};
 
 use lmdb::DatabaseFlags;
-use once_cell::sync::Lazy;
 use tempfile::{tempdir, TempDir};
 
 use casper_execution_engine::storage::{
@@ -14,12 +17,14 @@ use casper_hashing::Digest;
 use casper_node::storage::Storage;
 use casper_types::bytesrepr::{Bytes, ToBytes};
 
-static DEFAULT_MAX_DB_SIZE: Lazy<usize> = Lazy::new(|| super::DEFAULT_MAX_DB_SIZE.parse().unwrap());
-
-use crate::common::db::TRIE_STORE_FILE_NAME;
+use crate::common::db::{DEFAULT_MAX_DB_SIZE, TRIE_STORE_FILE_NAME};
 
 use super::{
-    compact::{self, DestinationOptions},
+    checkpoint::{Checkpoint, CheckpointEntry, CHECKPOINT_FILE_NAME},
+    compact::{self, CopyOptions, DestinationOptions, RetainedBlocks},
+    gc,
+    helpers::{BatchOptions, CopyStats},
+    progress::{self, CopyProgress, StateRootSummary},
     utils::{create_execution_engine, create_storage, load_execution_engine},
     Error,
 };
@@ -95,7 +100,7 @@ pub(crate) fn create_data() -> Vec<TestData<Bytes, Bytes>> {
 
 fn create_test_trie_store() -> (TempDir, Vec<TestData<Bytes, Bytes>>) {
     let tmp_dir = tempdir().unwrap();
-    let env = LmdbEnvironment::new(tmp_dir.path(), *DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
+    let env = LmdbEnvironment::new(tmp_dir.path(), DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
     let store = LmdbTrieStore::new(&env, None, DatabaseFlags::empty()).unwrap();
     let data = create_data();
 
@@ -120,8 +125,7 @@ fn create_empty_test_storage() -> (TempDir, Storage) {
 fn copy_state_root_roundtrip() {
     let src_tmp_dir = tempdir().unwrap();
     let dst_tmp_dir = tempdir().unwrap();
-    let src_env =
-        LmdbEnvironment::new(src_tmp_dir.path(), *DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
+    let src_env = LmdbEnvironment::new(src_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
     let src_store = LmdbTrieStore::new(&src_env, None, DatabaseFlags::empty()).unwrap();
     // Construct mock data.
     let data = create_data();
@@ -136,17 +140,25 @@ fn copy_state_root_roundtrip() {
 
     let (source_state, _env) = load_execution_engine(
         src_tmp_dir.path(),
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
         Digest::default(),
         true,
     )
     .unwrap();
 
     let (destination_state, dst_env) =
-        create_execution_engine(dst_tmp_dir.path(), *DEFAULT_MAX_DB_SIZE, true).unwrap();
+        create_execution_engine(dst_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, true).unwrap();
 
     // Copy from `node1`, the root of the created trie. All data should be copied.
-    super::helpers::copy_state_root(data[3].0, &source_state, &destination_state).unwrap();
+    super::helpers::copy_state_root(
+        data[3].0,
+        &source_state,
+        &destination_state,
+        BatchOptions::default(),
+        1,
+        None,
+    )
+    .unwrap();
 
     let dst_store = LmdbTrieStore::new(&dst_env, None, DatabaseFlags::empty()).unwrap();
     {
@@ -180,8 +192,7 @@ fn copy_state_root_roundtrip() {
 fn check_no_extra_tries() {
     let src_tmp_dir = tempdir().unwrap();
     let dst_tmp_dir = tempdir().unwrap();
-    let src_env =
-        LmdbEnvironment::new(src_tmp_dir.path(), *DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
+    let src_env = LmdbEnvironment::new(src_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, 512, true).unwrap();
     let src_store = LmdbTrieStore::new(&src_env, None, DatabaseFlags::empty()).unwrap();
     // Construct mock data.
     let data = create_data();
@@ -196,17 +207,25 @@ fn check_no_extra_tries() {
 
     let (source_state, _env) = load_execution_engine(
         src_tmp_dir.path(),
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
         Digest::default(),
         true,
     )
     .unwrap();
 
     let (destination_state, dst_env) =
-        create_execution_engine(dst_tmp_dir.path(), *DEFAULT_MAX_DB_SIZE, true).unwrap();
+        create_execution_engine(dst_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, true).unwrap();
 
     // Check with `node2`, which only has `leaf1` and `leaf2` as children in the constructed trie.
-    super::helpers::copy_state_root(data[4].0, &source_state, &destination_state).unwrap();
+    super::helpers::copy_state_root(
+        data[4].0,
+        &source_state,
+        &destination_state,
+        BatchOptions::default(),
+        1,
+        None,
+    )
+    .unwrap();
 
     let dst_store = LmdbTrieStore::new(&dst_env, None, DatabaseFlags::empty()).unwrap();
     {
@@ -249,6 +268,246 @@ fn check_no_extra_tries() {
     dst_tmp_dir.close().unwrap();
 }
 
+#[test]
+fn copy_state_root_in_small_batches() {
+    let (src_dir, data) = create_test_trie_store();
+    let (source_state, _env) =
+        load_execution_engine(src_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    let batches = [
+        BatchOptions {
+            batch_size: 1,
+            batch_bytes: usize::MAX,
+        },
+        BatchOptions {
+            batch_size: 2,
+            batch_bytes: usize::MAX,
+        },
+        BatchOptions {
+            batch_size: usize::MAX,
+            batch_bytes: 1,
+        },
+    ];
+    for batch in batches {
+        let dst_tmp_dir = tempdir().unwrap();
+        let (destination_state, dst_env) =
+            create_execution_engine(dst_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, true).unwrap();
+        // Copy `node2` first, so that copying `node1` stops at `ext_node`'s child.
+        super::helpers::copy_state_root(
+            data[4].0,
+            &source_state,
+            &destination_state,
+            batch,
+            1,
+            None,
+        )
+        .unwrap();
+        super::helpers::copy_state_root(
+            data[3].0,
+            &source_state,
+            &destination_state,
+            batch,
+            1,
+            None,
+        )
+        .unwrap();
+
+        let dst_store = destination_state.get_state().trie_store();
+        let txn = dst_env.create_read_txn().unwrap();
+        let keys: Vec<_> = data.iter().map(|test_data| test_data.0).collect();
+        let entries: Vec<Option<Trie<Bytes, Bytes>>> =
+            dst_store.get_many(&txn, keys.iter()).unwrap();
+        for (test_data, entry) in data.iter().zip(entries) {
+            assert_eq!(entry, Some(test_data.1.clone()), "{:?}", batch);
+        }
+        txn.commit().unwrap();
+    }
+}
+
+#[test]
+fn parallel_copy_state_root() {
+    let (src_dir, data) = create_test_trie_store();
+    let (source_state, _env) =
+        load_execution_engine(src_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    let dst_tmp_dir = tempdir().unwrap();
+    let (destination_state, dst_env) =
+        create_execution_engine(dst_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, true).unwrap();
+    let batch = BatchOptions {
+        batch_size: 2,
+        batch_bytes: usize::MAX,
+    };
+    let dst_store = destination_state.get_state().trie_store();
+    let copied_keys = || -> HashSet<Digest> {
+        let txn = dst_env.create_read_txn().unwrap();
+        let keys: Vec<_> = data.iter().map(|test_data| test_data.0).collect();
+        let entries: Vec<Option<Trie<Bytes, Bytes>>> =
+            dst_store.get_many(&txn, keys.iter()).unwrap();
+        txn.commit().unwrap();
+        data.iter()
+            .zip(entries)
+            .filter_map(|(test_data, entry)| {
+                entry.map(|trie| {
+                    assert_eq!(trie, test_data.1);
+                    test_data.0
+                })
+            })
+            .collect()
+    };
+
+    // Copy `node2`, then `node1`, the root of the created trie.
+    let stats = super::helpers::copy_state_root(
+        data[4].0,
+        &source_state,
+        &destination_state,
+        batch,
+        4,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        stats,
+        CopyStats {
+            new_nodes: 3,
+            present_nodes: 0
+        }
+    );
+    let expected: HashSet<Digest> = [1, 2, 4].iter().map(|idx| data[*idx].0).collect();
+    assert_eq!(copied_keys(), expected);
+    // `node2` is already in the destination.
+    let stats = super::helpers::copy_state_root(
+        data[3].0,
+        &source_state,
+        &destination_state,
+        batch,
+        4,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        stats,
+        CopyStats {
+            new_nodes: 3,
+            present_nodes: 1
+        }
+    );
+    let all_keys: HashSet<Digest> = data.iter().map(|test_data| test_data.0).collect();
+    assert_eq!(copied_keys(), all_keys);
+    let stats = super::helpers::recopy_state_root(
+        data[3].0,
+        &source_state,
+        &destination_state,
+        batch,
+        4,
+        None,
+    )
+    .unwrap();
+    assert_eq!(stats.new_nodes, data.len() as u64);
+    assert_eq!(copied_keys(), all_keys);
+
+    // A node missing from the source fails the copy.
+    let missing_root: Digest = [255u8; Digest::LENGTH].into();
+    assert!(super::helpers::copy_state_root(
+        missing_root,
+        &source_state,
+        &destination_state,
+        batch,
+        4,
+        None
+    )
+    .is_err());
+}
+
+#[test]
+fn missing_nodes_should_be_counted() {
+    let (src_dir, data) = create_test_trie_store();
+    let (source_state, _env) =
+        load_execution_engine(src_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+    let dst_tmp_dir = tempdir().unwrap();
+    let (destination_state, _dst_env) =
+        create_execution_engine(dst_tmp_dir.path(), DEFAULT_MAX_DB_SIZE, true).unwrap();
+
+    // `node1` reaches all the nodes, `node2` is one of them.
+    let roots = [(2, data[3].0), (1, data[4].0)];
+    assert_eq!(
+        progress::count_missing_nodes(&source_state, &destination_state, &roots).unwrap(),
+        data.len()
+    );
+    // The destination already holds the empty trie written when it's created,
+    // which the estimate subtracts.
+    assert_eq!(
+        progress::estimate_missing_nodes(&source_state, &destination_state).unwrap(),
+        data.len() - 1
+    );
+
+    let mut copy_progress = CopyProgress::new(3);
+    super::helpers::copy_state_root(
+        data[4].0,
+        &source_state,
+        &destination_state,
+        BatchOptions::default(),
+        1,
+        copy_progress.as_mut(),
+    )
+    .unwrap();
+    assert_eq!(
+        progress::count_missing_nodes(&source_state, &destination_state, &roots).unwrap(),
+        3
+    );
+    assert_eq!(
+        progress::estimate_missing_nodes(&source_state, &destination_state).unwrap(),
+        2
+    );
+}
+
+#[test]
+fn copy_progress_with_fewer_nodes_than_steps() {
+    // Fewer nodes than logged steps, so each node passes several steps.
+    let mut copy_progress = CopyProgress::new(3).unwrap();
+    for _ in 0..5 {
+        copy_progress.advance();
+    }
+}
+
+#[test]
+fn state_root_summaries_should_be_json_lines() {
+    let summaries: Vec<StateRootSummary> = [(2u64, 3u64), (1, 0)]
+        .into_iter()
+        .map(|(height, new_nodes)| StateRootSummary {
+            height,
+            state_root: [height as u8; Digest::LENGTH].into(),
+            new_nodes,
+            present_nodes: 1,
+        })
+        .collect();
+    let mut report = vec![];
+    for summary in &summaries {
+        summary.write_json_line(&mut report).unwrap();
+    }
+    let written: Vec<StateRootSummary> = String::from_utf8(report)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(written, summaries);
+}
+
+#[test]
+fn node_set_memory_check() {
+    assert!(progress::check_node_set_memory(0).is_ok());
+    // The digests of as many nodes as fit in the address space don't fit in
+    // the available memory, wherever it's known.
+    let available_memory_known = fs::read_to_string("/proc/meminfo")
+        .map_or(false, |meminfo| meminfo.contains("MemAvailable:"));
+    if available_memory_known {
+        assert!(matches!(
+            progress::check_node_set_memory(usize::MAX),
+            Err(Error::InsufficientMemory(..))
+        ));
+    }
+}
+
 #[test]
 fn missing_source_trie() {
     match compact::trie_compact(
@@ -256,7 +515,10 @@ fn missing_source_trie() {
         "bogus_path",
         "",
         DestinationOptions::New,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidPath(..)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -273,7 +535,10 @@ fn missing_storage() {
         src_dir,
         dst_dir,
         DestinationOptions::New,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::OpenStorage(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -291,7 +556,10 @@ fn valid_empty_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::New,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Ok(_) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -303,7 +571,10 @@ fn valid_empty_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Append,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidDest(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -315,7 +586,10 @@ fn valid_empty_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Overwrite,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidDest(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -338,7 +612,10 @@ fn valid_existing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::New,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidDest(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -350,7 +627,10 @@ fn valid_existing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Append,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Ok(_) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -362,7 +642,10 @@ fn valid_existing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Overwrite,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Ok(_) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -383,7 +666,10 @@ fn missing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::New,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Ok(_) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -395,7 +681,10 @@ fn missing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Append,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidDest(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
@@ -407,10 +696,199 @@ fn missing_dst_with_destination_options() {
         &src_dir,
         &dst_dir,
         DestinationOptions::Overwrite,
-        *DEFAULT_MAX_DB_SIZE,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
     ) {
         Err(Error::InvalidDest(_)) => {}
         Err(err) => panic!("Unexpected error: {err}"),
         Ok(_) => panic!("Unexpected successful trie compact"),
     }
 }
+
+#[test]
+fn unreachable_nodes_should_be_swept() {
+    let (src_dir, data) = create_test_trie_store();
+    let (engine_state, env) =
+        load_execution_engine(src_dir.path(), DEFAULT_MAX_DB_SIZE, Digest::default(), true)
+            .unwrap();
+
+    // `ext_node` only reaches `node2` and its leaves, `leaf2` and `leaf3`.
+    let reachable = gc::mark_reachable(&engine_state, &[data[5].0]).unwrap();
+    let expected: HashSet<Digest> = [1, 2, 4, 5].iter().map(|idx| data[*idx].0).collect();
+    assert_eq!(reachable, expected);
+
+    let trie_store = engine_state.get_state().trie_store();
+    let trie_db = trie_store.get_db();
+    assert_eq!(
+        gc::sweep_unreachable(env.env(), trie_db, &reachable, 1).unwrap(),
+        2
+    );
+    assert_eq!(
+        gc::sweep_unreachable(env.env(), trie_db, &reachable, 1).unwrap(),
+        0
+    );
+
+    let txn = env.create_read_txn().unwrap();
+    let keys: Vec<_> = data.iter().map(|test_data| test_data.0).collect();
+    let entries: Vec<Option<Trie<Bytes, Bytes>>> = trie_store.get_many(&txn, keys.iter()).unwrap();
+    for (test_data, entry) in data.iter().zip(entries) {
+        assert_eq!(entry.is_some(), reachable.contains(&test_data.0));
+    }
+    txn.commit().unwrap();
+}
+
+#[test]
+fn in_place_gc_without_blocks_should_delete_all_nodes() {
+    let (src_dir, data) = create_test_trie_store();
+    let (storage_dir, _store) = create_empty_test_storage();
+    assert_eq!(
+        gc::trie_gc(
+            &storage_dir,
+            &src_dir,
+            DEFAULT_MAX_DB_SIZE,
+            4,
+            RetainedBlocks::default(),
+        )
+        .unwrap(),
+        data.len()
+    );
+    assert_eq!(
+        gc::trie_gc(
+            &storage_dir,
+            &src_dir,
+            DEFAULT_MAX_DB_SIZE,
+            4,
+            RetainedBlocks::default(),
+        )
+        .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn retained_height_window() {
+    let retained = |keep_last, from_height, to_height| RetainedBlocks {
+        keep_last,
+        from_height,
+        to_height,
+        keep_era_boundaries: false,
+    };
+    assert_eq!(RetainedBlocks::default().window(10), Some(0..=10));
+    assert_eq!(retained(Some(3), None, None).window(10), Some(8..=10));
+    assert_eq!(retained(Some(20), None, None).window(10), Some(0..=10));
+    assert_eq!(retained(Some(0), None, None).window(10), None);
+    assert_eq!(retained(None, Some(4), Some(6)).window(10), Some(4..=6));
+    assert_eq!(retained(None, Some(4), Some(60)).window(10), Some(4..=10));
+    assert_eq!(retained(None, Some(11), None).window(10), None);
+    assert_eq!(retained(None, Some(6), Some(4)).window(10), None);
+    // All the bounds restrict the window.
+    assert_eq!(retained(Some(5), Some(7), None).window(10), Some(7..=10));
+    assert_eq!(retained(Some(5), None, Some(8)).window(10), Some(6..=8));
+    assert_eq!(retained(Some(2), None, Some(5)).window(10), None);
+}
+
+#[test]
+fn checkpoint_should_replay_progress() {
+    let tmp_dir = tempdir().unwrap();
+    let checkpoint_path = tmp_dir.path().join(CHECKPOINT_FILE_NAME);
+    let [first_root, second_root, third_root, other_root]: [Digest; 4] =
+        [1u8, 2, 3, 4].map(|idx| [idx; Digest::LENGTH].into());
+    let mut checkpoint = Checkpoint::create(&checkpoint_path).unwrap();
+    for entry in [
+        CheckpointEntry::Started {
+            height: 10,
+            state_root: first_root,
+        },
+        CheckpointEntry::Completed {
+            height: 10,
+            state_root: first_root,
+        },
+        CheckpointEntry::Started {
+            height: 9,
+            state_root: second_root,
+        },
+        CheckpointEntry::Completed {
+            height: 9,
+            state_root: second_root,
+        },
+        CheckpointEntry::Started {
+            height: 7,
+            state_root: third_root,
+        },
+    ] {
+        checkpoint.record(&entry).unwrap();
+    }
+    drop(checkpoint);
+    // A line cut short by an interruption.
+    OpenOptions::new()
+        .append(true)
+        .open(&checkpoint_path)
+        .unwrap()
+        .write_all(b"{\"completed\":{\"hei")
+        .unwrap();
+
+    let (mut checkpoint, progress) = Checkpoint::resume(&checkpoint_path).unwrap();
+    assert_eq!(progress.last_height, Some(9));
+    assert_eq!(
+        progress.visited_roots,
+        HashSet::from([first_root, second_root])
+    );
+    assert_eq!(progress.interrupted, Some((7, third_root)));
+    assert!(progress.is_pending(8, &other_root));
+    assert!(!progress.is_pending(9, &other_root));
+    assert!(!progress.is_pending(5, &first_root));
+
+    checkpoint
+        .record(&CheckpointEntry::Completed {
+            height: 7,
+            state_root: third_root,
+        })
+        .unwrap();
+    drop(checkpoint);
+    let (checkpoint, progress) = Checkpoint::resume(&checkpoint_path).unwrap();
+    assert_eq!(progress.last_height, Some(7));
+    assert_eq!(progress.visited_roots.len(), 3);
+    assert!(progress.interrupted.is_none());
+
+    checkpoint.remove();
+    assert!(!checkpoint_path.exists());
+}
+
+#[test]
+fn resume_without_checkpoint_should_fail() {
+    let (src_dir, _) = create_test_trie_store();
+    let dst_dir = tempdir().unwrap();
+    let (storage_dir, _store) = create_empty_test_storage();
+    match compact::trie_compact(
+        &storage_dir,
+        &src_dir,
+        &dst_dir,
+        DestinationOptions::New,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
+    ) {
+        Ok(_) => {}
+        Err(err) => panic!("Unexpected error: {err}"),
+    }
+    // The checkpoint is removed once the compaction is complete.
+    assert!(!dst_dir.path().join(CHECKPOINT_FILE_NAME).exists());
+
+    match compact::trie_compact(
+        &storage_dir,
+        &src_dir,
+        &dst_dir,
+        DestinationOptions::Resume,
+        DEFAULT_MAX_DB_SIZE,
+        RetainedBlocks::default(),
+        CopyOptions::default(),
+        None,
+    ) {
+        Err(Error::Checkpoint(..)) => {}
+        Err(err) => panic!("Unexpected error: {err}"),
+        Ok(_) => panic!("Unexpected successful trie compact"),
+    }
+}
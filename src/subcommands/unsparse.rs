@@ -47,7 +47,7 @@ pub fn run(matches: &ArgMatches) -> Result<(), Error> {
     unsparse(path)
 }
 
-fn unsparse(path: &Path) -> Result<(), Error> {
+pub(crate) fn unsparse(path: &Path) -> Result<(), Error> {
     let size_before = fs::metadata(path)
         .map(|metadata| metadata.len())
         .map_err(|io_err| Error::Metadata(path.to_path_buf(), io_err))?;
@@ -6,9 +6,13 @@ use lmdb::{Database as LmdbDatabase, DatabaseFlags, Environment, EnvironmentFlag
 use serde::{Deserialize, Serialize};
 use tempfile::{NamedTempFile, TempDir};
 
+use casper_execution_engine::core::engine_state::executable_deploy_item::ExecutableDeployItem;
 use casper_hashing::Digest;
-use casper_node::types::{BlockHash, DeployHash, DeployMetadata, Timestamp};
-use casper_types::{EraId, ExecutionEffect, ExecutionResult, ProtocolVersion};
+use casper_node::types::{BlockHash, Deploy, DeployHash, DeployMetadata, Timestamp};
+use casper_types::{
+    bytesrepr::Bytes, EraId, ExecutionEffect, ExecutionResult, ProtocolVersion, RuntimeArgs,
+    SecretKey,
+};
 
 pub struct LmdbTestFixture {
     pub env: Environment,
@@ -122,6 +126,27 @@ pub(crate) fn mock_deploy_hash(idx: u8) -> DeployHash {
     DeployHash::new([idx; 32].into())
 }
 
+/// Returns a signed deploy whose account key is derived from `idx`, so that
+/// deploys with different indices have different hashes.
+pub(crate) fn mock_deploy(idx: u8) -> Deploy {
+    let secret_key = SecretKey::ed25519_from_bytes([idx; SecretKey::ED25519_LENGTH]).unwrap();
+    let module_bytes = || ExecutableDeployItem::ModuleBytes {
+        module_bytes: Bytes::new(),
+        args: RuntimeArgs::new(),
+    };
+    Deploy::new(
+        Timestamp::now(),
+        "1h".parse().unwrap(),
+        1,
+        vec![],
+        "casper-db-utils-test".to_string(),
+        module_bytes(),
+        module_bytes(),
+        &secret_key,
+        None,
+    )
+}
+
 pub(crate) fn mock_block_header(idx: u8) -> (BlockHash, MockBlockHeader) {
     let mut block_header = MockBlockHeader::default();
     let block_hash_digest: Digest = [idx; Digest::LENGTH].into();